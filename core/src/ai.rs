@@ -1,48 +1,483 @@
+use std::fmt::{Debug};
 use std::rc::{Rc};
-use rand::{thread_rng, Rng};
+use std::time::{Duration};
+use rand::{Rng};
 use game_state::{State};
-use map::{distance};
 use movement::{self, MovePoints, Pathfinder, path_cost, truncate_path};
 use dir::{Dir, dirs};
-use unit::{Unit, UnitTypeId};
+use unit::{Unit, UnitTypeId, is_commandable};
+use map::{Terrain, Distance, distance};
 use db::{Db};
 use misc::{get_shuffled_indices};
 use check::{check_command};
-use position::{ExactPos, MapPos, get_free_exact_pos};
+use position::{ExactPos, MapPos, SlotId, get_free_exact_pos};
 use object::{ObjectClass, Object};
-use event::{CoreEvent, Command, MoveMode};
+use event::{CoreEvent, Command, MoveMode, ReactionFireMode};
 use player::{PlayerId};
-use options::{Options};
+use options::{Options, Difficulty};
+use rng::{CoreRng};
+use serialize::{Serialize, Writer, Reader, DeserializeError};
+use search_ai::{SearchAiBuilder};
+use influence::{InfluenceMap};
+use memory::{EnemyMemory};
+use sector::{Sector};
+use attack;
+use purchase;
+
+/// Shots with a lower chance to hit are a waste: unused attack points
+/// become reactive ones at the end of the turn
+pub const MIN_HIT_CHANCE: i32 = 10;
+
+/// Percent of `Difficulty::Easy` attacks that go to a random target
+const EASY_MISTAKE_CHANCE: i32 = 30;
+
+/// `Difficulty::Hard` prefers targets that can shoot back
+const HARD_THREAT_FACTOR: f32 = 1.5;
+
+/// `Difficulty::Hard` infantry agrees to walk this much longer
+/// to end its move in cover
+const HARD_OPEN_TERRAIN_PENALTY: i32 = 4;
+
+/// Units that are farther than this from their goal go by truck
+const LONG_APPROACH_DISTANCE: Distance = Distance{n: 6};
+
+/// Extra move points that a destination costs for every enemy weapon
+/// that covers it and is not matched by a friendly unit nearby
+const KILL_ZONE_PENALTY: i32 = 3;
+
+/// Move points that a destination saves for every point of its sector's value
+const SECTOR_VALUE_BONUS: i32 = 2;
+
+/// Hidden enemies that are less likely to be where they were seen are ignored
+const MIN_MEMORY_CONFIDENCE: i32 = 50;
+
+/// Units advance only when they are this much stronger than the known enemies
+const ADVANCE_STRENGTH_RATIO: f32 = 1.2;
+
+/// What the units that are not in contact with the enemy do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Posture {
+    /// Go for the enemies and the sectors
+    Advance,
+
+    /// Keep our sectors and wait for the enemy in cover
+    Hold,
+}
+
+fn unit_strength(db: &Db, unit: &Unit) -> f32 {
+    let unit_type = db.unit_type(unit.type_id);
+    unit_type.cost.n as f32 * unit.count as f32 / unit_type.count as f32
+}
+
+/// Computer player.
+///
+/// It knows only what its player can see: it gets the same filtered
+/// events as a human player and keeps its own partial `State`.
+pub trait Ai: Debug {
+    fn apply_event(&mut self, event: &CoreEvent);
+
+    fn get_command(&mut self) -> Command;
+
+    /// Thinks about the next command for about `budget`.
+    ///
+    /// `None` means that the AI needs more time and wants to be called
+    /// again, nothing changes in between. The result must not depend
+    /// on how the thinking was split, or replays won't work.
+    fn think(&mut self, budget: Duration) -> Option<Command> {
+        let _ = budget;
+        Some(self.get_command())
+    }
+
+    /// Writes everything that `AiBuilder::load` needs
+    fn save(&self, w: &mut Writer);
+}
+
+/// Creates AIs of one kind, see `Options::ais`
+pub trait AiBuilder: Debug {
+    /// Saves and replays refer to the builder by this name
+    fn name(&self) -> &str;
+
+    fn build(&self, db: Rc<Db>, options: &Options, id: PlayerId, rng: CoreRng) -> Box<Ai>;
+
+    fn load(&self, r: &mut Reader, db: Rc<Db>) -> Result<Box<Ai>, DeserializeError>;
+
+    /// Replays record commands of the AIs that aren't deterministic
+    fn is_deterministic(&self) -> bool {
+        true
+    }
+}
+
+/// Builder of `SimpleAi`, the AI used by default
+#[derive(Clone, Copy, Debug)]
+pub struct SimpleAiBuilder;
+
+impl AiBuilder for SimpleAiBuilder {
+    fn name(&self) -> &str {
+        "simple"
+    }
+
+    fn build(&self, db: Rc<Db>, options: &Options, id: PlayerId, rng: CoreRng) -> Box<Ai> {
+        Box::new(SimpleAi::new(db, options, id, rng))
+    }
+
+    fn load(&self, r: &mut Reader, db: Rc<Db>) -> Result<Box<Ai>, DeserializeError> {
+        Ok(Box::new(SimpleAi::load(r, db)?))
+    }
+}
+
+/// Finds a builder of the AIs that come with the core
+pub fn builtin_ai_builder(name: &str) -> Option<Rc<AiBuilder>> {
+    match name {
+        "simple" => Some(Rc::new(SimpleAiBuilder)),
+        _ => match SearchAiBuilder::from_name(name) {
+            Some(builder) => Some(Rc::new(builder)),
+            None => None,
+        },
+    }
+}
+
+/// Finds an AI builder by its `AiBuilder::name`,
+/// `builtin_ai_builder` knows the AIs that come with the core
+pub type AiFinder = Fn(&str) -> Option<Rc<AiBuilder>>;
+
+/// Reads a name written by `AiBuilder::name`
+pub fn read_ai_builder(
+    r: &mut Reader,
+    find_ai: &AiFinder,
+) -> Result<Rc<AiBuilder>, DeserializeError> {
+    let name = r.read_str()?;
+    match find_ai(&name) {
+        Some(builder) => Ok(builder),
+        None => Err(DeserializeError::BadData(format!("Unknown AI: {}", name))),
+    }
+}
+
+/// Can the attacker's weapon reach the target, ignoring line of sight
+pub fn is_in_range(db: &Db, attacker: &Unit, target: &Unit) -> bool {
+    let target_type = db.unit_type(target.type_id);
+    let attacker_type = db.unit_type(attacker.type_id);
+    let weapon_type = db.weapon_type(attacker_type.weapon_type_id);
+    let distance = distance(attacker.pos.map_pos, target.pos.map_pos);
+    let max_distance = if target_type.is_air {
+        match weapon_type.max_air_distance {
+            Some(max_air_distance) => max_air_distance,
+            None => return false, // can not attack air unit
+        }
+    } else {
+        weapon_type.max_distance
+    };
+    distance <= max_distance
+}
+
+/// Distance to the nearest known enemy or not yet captured sector
+pub fn distance_to_goal(state: &State, player_id: PlayerId, pos: MapPos) -> Option<Distance> {
+    let mut min_distance = None;
+    for (_, enemy) in state.units() {
+        if state.is_ally(enemy.player_id, player_id) || !enemy.is_alive {
+            continue;
+        }
+        let d = distance(pos, enemy.pos.map_pos);
+        if min_distance.map_or(true, |min| d < min) {
+            min_distance = Some(d);
+        }
+    }
+    for sector in state.sectors().values() {
+        if let Some(owner_id) = sector.owner_id {
+            if state.is_ally(owner_id, player_id) {
+                continue;
+            }
+        }
+        for &sector_pos in &sector.positions {
+            let d = distance(pos, sector_pos);
+            if min_distance.map_or(true, |min| d < min) {
+                min_distance = Some(d);
+            }
+        }
+    }
+    min_distance
+}
+
+/// How good is it to shoot at the target: the value of the soldiers
+/// it's expected to lose plus a bonus for destroying it completely
+pub fn attack_score(db: &Db, state: &State, attacker: &Unit, target: &Unit) -> f32 {
+    let target_type = db.unit_type(target.type_id);
+    let cost = target_type.cost.n as f32;
+    let member_cost = cost / target_type.count as f32;
+    let expected_killed = attack::expected_killed_count(db, state, attacker, target);
+    let destroy_chance = attack::destroy_chance(db, state, attacker, target) as f32 / 100.0;
+    expected_killed * member_cost + destroy_chance * cost
+}
 
 #[derive(Clone, Debug)]
-pub struct Ai {
+pub struct SimpleAi {
     id: PlayerId,
     state: State,
     pathfinder: Pathfinder,
     db: Rc<Db>,
+    rng: CoreRng,
+    difficulty: Difficulty,
+    influence: InfluenceMap,
+    is_influence_dirty: bool,
+    memory: EnemyMemory,
+}
+
+fn get_shuffled_reinforcement_sectors<'a>(
+    state: &'a State,
+    rng: &mut CoreRng,
+    player_id: PlayerId,
+) -> Vec<&'a Object> {
+    let mut reinforcement_sectors = Vec::new();
+    for object in state.objects().values() {
+        let owner_id = match object.owner_id {
+            Some(id) => id,
+            None => continue,
+        };
+        if owner_id != player_id {
+            continue;
+        }
+        if object.class != ObjectClass::ReinforcementSector {
+            continue;
+        }
+        reinforcement_sectors.push(object);
+    }
+    rng.shuffle(&mut reinforcement_sectors);
+    reinforcement_sectors
 }
 
-impl Ai {
-    pub fn new(db: Rc<Db>, options: &Options, id: PlayerId) -> Ai {
+impl SimpleAi {
+    pub fn new(db: Rc<Db>, options: &Options, id: PlayerId, rng: CoreRng) -> SimpleAi {
         let state = State::new_partial(db.clone(), options, id);
         let map_size = state.map().size();
-        Ai {
+        SimpleAi {
             id: id,
             state: state,
             pathfinder: Pathfinder::new(db.clone(), map_size),
             db: db,
+            rng: rng,
+            difficulty: options.difficulty,
+            influence: InfluenceMap::new(map_size),
+            is_influence_dirty: true,
+            memory: EnemyMemory::new(id),
         }
     }
 
-    pub fn apply_event(&mut self, event: &CoreEvent) {
-        self.state.apply_event(event);
+    pub fn load(r: &mut Reader, db: Rc<Db>) -> Result<SimpleAi, DeserializeError> {
+        let id = r.read()?;
+        let rng = r.read()?;
+        let difficulty = r.read()?;
+        let memory = r.read()?;
+        let state = State::load(r, db.clone())?;
+        let map_size = state.map().size();
+        Ok(SimpleAi {
+            id: id,
+            state: state,
+            pathfinder: Pathfinder::new(db.clone(), map_size),
+            db: db,
+            rng: rng,
+            difficulty: difficulty,
+            influence: InfluenceMap::new(map_size),
+            is_influence_dirty: true,
+            memory: memory,
+        })
+    }
+
+    pub fn id(&self) -> PlayerId {
+        self.id
+    }
+
+    /// Partial state as seen by this AI's player
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn influence(&self) -> &InfluenceMap {
+        &self.influence
     }
 
+    pub fn memory(&self) -> &EnemyMemory {
+        &self.memory
+    }
+
+    /// Rebuilds the influence map if the state has changed since the last call
+    pub fn update_influence(&mut self) {
+        if !self.is_influence_dirty {
+            return;
+        }
+        let hidden_enemies: Vec<&Unit> = self.memory.units()
+            .filter(|remembered| remembered.confidence >= MIN_MEMORY_CONFIDENCE)
+            .map(|remembered| &remembered.unit)
+            .collect();
+        self.influence.update(&self.db, &self.state, self.id, &hidden_enemies);
+        self.is_influence_dirty = false;
+    }
+
+    /// Stops the unit before it drives into the range of a hidden enemy
+    /// on its way to the destination, Easy AI doesn't remember anything
+    fn get_safe_path(
+        &self,
+        unit: &Unit,
+        mut path: Vec<ExactPos>,
+        destination: ExactPos,
+    ) -> Vec<ExactPos> {
+        if self.difficulty == Difficulty::Easy || self.db.unit_type(unit.type_id).is_air {
+            return path;
+        }
+        let mut end_index = path.len();
+        if path[end_index - 1] == destination {
+            end_index -= 1;
+        }
+        let unsafe_index = (1 .. end_index)
+            .find(|&i| self.influence.tile(path[i]).hidden_threat > 0);
+        if let Some(index) = unsafe_index {
+            path.truncate(index);
+        }
+        path
+    }
+
+    /// Our and allied strength against the strength of the known enemies,
+    /// the remembered ones included
+    fn strength_ratio(&self) -> f32 {
+        let mut own = 0.0;
+        let mut enemy = 0.0;
+        for (_, unit) in self.state.units() {
+            if !unit.is_alive {
+                continue;
+            }
+            if self.state.is_ally(unit.player_id, self.id) {
+                own += unit_strength(&self.db, unit);
+            } else {
+                enemy += unit_strength(&self.db, unit);
+            }
+        }
+        for remembered in self.memory.units() {
+            if remembered.confidence >= MIN_MEMORY_CONFIDENCE {
+                enemy += unit_strength(&self.db, &remembered.unit);
+            }
+        }
+        own / f32::max(enemy, 1.0)
+    }
+
+    /// Easy AI and the AI that has nothing to hold always advance
+    fn posture(&self) -> Posture {
+        if self.difficulty == Difficulty::Easy {
+            return Posture::Advance;
+        }
+        let has_sectors = self.state.sectors().values().any(|sector| {
+            sector.owner_id.map_or(false, |id| self.state.is_ally(id, self.id))
+        });
+        if has_sectors && self.strength_ratio() < ADVANCE_STRENGTH_RATIO {
+            Posture::Hold
+        } else {
+            Posture::Advance
+        }
+    }
+
+    /// Our sector that the unit keeps: every sector is kept by its first
+    /// infantry unit or by its first unit of any type if there's no infantry
+    fn get_garrisoned_sector(&self, unit: &Unit) -> Option<&Sector> {
+        let can_keep = |unit: &Unit| {
+            unit.player_id == self.id
+                && unit.is_alive
+                && !unit.is_loaded
+                && !unit.is_attached
+                && !self.db.unit_type(unit.type_id).is_air
+        };
+        if !can_keep(unit) {
+            return None;
+        }
+        let sector = match self.state.sectors().values()
+            .find(|sector| sector.positions.contains(&unit.pos.map_pos))
+        {
+            Some(sector) => sector,
+            None => return None,
+        };
+        match sector.owner_id {
+            Some(id) if self.state.is_ally(id, self.id) => {},
+            _ => return None,
+        }
+        let garrison = self.state.units()
+            .map(|(_, unit)| unit)
+            .filter(|unit| can_keep(unit) && sector.positions.contains(&unit.pos.map_pos))
+            .min_by_key(|unit| (!self.db.unit_type(unit.type_id).is_infantry, unit.id.id))
+            .unwrap();
+        if garrison.id == unit.id {
+            Some(sector)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_garrison(&self, unit: &Unit) -> bool {
+        self.get_garrisoned_sector(unit).is_some()
+    }
+
+    /// Infantry here is hard to spot and to hit
+    fn is_cover(&self, pos: ExactPos) -> bool {
+        match *self.state.map().tile(pos) {
+            Terrain::Trees | Terrain::City => true,
+            Terrain::Plain | Terrain::Water => self.state.objects_at(pos.map_pos)
+                .any(|object| object.class == ObjectClass::Building && object.pos == pos),
+        }
+    }
+
+    /// The cheapest free cover position of the sector
+    fn get_cover_pos(&self, unit: &Unit, sector: &Sector) -> Option<ExactPos> {
+        let unit_type = self.db.unit_type(unit.type_id);
+        let mut best_pos = None;
+        let mut best_cost = movement::max_cost();
+        for &map_pos in &sector.positions {
+            let mut positions = Vec::new();
+            match *self.state.map().tile(map_pos) {
+                Terrain::Trees | Terrain::City => {
+                    if let Some(pos) = get_free_exact_pos(&self.state, unit_type, map_pos) {
+                        positions.push(pos);
+                    }
+                },
+                Terrain::Plain | Terrain::Water => {},
+            }
+            for object in self.state.objects_at(map_pos) {
+                if object.class != ObjectClass::Building {
+                    continue;
+                }
+                if let SlotId::Id(_) = object.pos.slot_id {
+                    if self.state.unit_at_opt(object.pos).is_none() {
+                        positions.push(object.pos);
+                    }
+                }
+            }
+            for pos in positions {
+                let path = match self.pathfinder.get_path(pos) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let cost = path_cost(&self.db, &self.state, unit, &path);
+                if best_cost.n > cost.n {
+                    best_cost = cost;
+                    best_pos = Some(pos);
+                }
+            }
+        }
+        best_pos
+    }
+
+    /// Garrisons take cover in their sectors. In `Posture::Hold` other units
+    /// reinforce our sectors, in `Posture::Advance` they go for the enemies
+    /// and the sectors that are not ours or are under fire.
     fn get_best_pos(&self, unit: &Unit) -> Option<ExactPos> {
+        let is_infantry = self.db.unit_type(unit.type_id).is_infantry;
+        if let Some(sector) = self.get_garrisoned_sector(unit) {
+            if is_infantry && !self.is_cover(unit.pos) {
+                return self.get_cover_pos(unit, sector);
+            }
+            return None;
+        }
+        if self.posture() == Posture::Hold {
+            return self.get_reinforcement_pos(unit);
+        }
         let mut best_pos = None;
         let mut best_cost = movement::max_cost();
         for (_, enemy) in self.state.units() {
-            if enemy.player_id == self.id || !enemy.is_alive {
+            if self.state.is_ally(enemy.player_id, self.id) || !enemy.is_alive {
                 continue;
             }
             for dir in dirs() {
@@ -51,6 +486,7 @@ impl Ai {
                     continue;
                 }
                 if let Some((cost, pos)) = self.estimate_path(unit, pos) {
+                    let cost = self.position_cost(unit, cost, pos);
                     if best_cost.n > cost.n {
                         best_cost = cost;
                         best_pos = Some(pos);
@@ -59,7 +495,10 @@ impl Ai {
             }
         }
         for sector in self.state.sectors().values() {
-            if sector.owner_id == Some(self.id) {
+            // Our sectors are worth something only while the enemy can shoot at them
+            let is_worth = sector.positions.iter()
+                .any(|&pos| self.influence.tile(pos).sector_value > 0);
+            if !is_worth {
                 continue;
             }
             for &pos in &sector.positions {
@@ -67,6 +506,32 @@ impl Ai {
                     return None;
                 }
                 if let Some((cost, pos)) = self.estimate_path(unit, pos) {
+                    let cost = self.position_cost(unit, cost, pos);
+                    if best_cost.n > cost.n {
+                        best_cost = cost;
+                        best_pos = Some(pos);
+                    }
+                }
+            }
+        }
+        best_pos
+    }
+
+    /// The cheapest position in one of our sectors, the contested ones are preferred
+    fn get_reinforcement_pos(&self, unit: &Unit) -> Option<ExactPos> {
+        let mut best_pos = None;
+        let mut best_cost = movement::max_cost();
+        for sector in self.state.sectors().values() {
+            let is_ours = sector.owner_id.map_or(false, |id| self.state.is_ally(id, self.id));
+            if !is_ours {
+                continue;
+            }
+            for &pos in &sector.positions {
+                if unit.pos.map_pos == pos {
+                    return None;
+                }
+                if let Some((cost, pos)) = self.estimate_path(unit, pos) {
+                    let cost = self.position_cost(unit, cost, pos);
                     if best_cost.n > cost.n {
                         best_cost = cost;
                         best_pos = Some(pos);
@@ -98,40 +563,199 @@ impl Ai {
         Some((cost, exact_destination))
     }
 
+    /// Avoids kill zones and prefers valuable sectors, Easy AI doesn't care.
+    /// Hard AI's infantry also avoids ending its moves in the open.
+    fn position_cost(&self, unit: &Unit, cost: MovePoints, pos: ExactPos) -> MovePoints {
+        let unit_type = self.db.unit_type(unit.type_id);
+        let mut n = cost.n;
+        if self.difficulty != Difficulty::Easy {
+            let tile = self.influence.tile(pos);
+            let threat = if unit_type.is_air { tile.air_threat } else { tile.threat };
+            if self.influence.is_kill_zone(pos, unit_type.is_air) {
+                n += KILL_ZONE_PENALTY * (threat - tile.support);
+            }
+            n -= SECTOR_VALUE_BONUS * tile.sector_value;
+        }
+        if self.difficulty == Difficulty::Hard
+            && unit_type.is_infantry
+            && *self.state.map().tile(pos) == Terrain::Plain
+        {
+            n += HARD_OPEN_TERRAIN_PENALTY;
+        }
+        MovePoints{n: n}
+    }
+
+    /// Can shoot at a known enemy or has already walked into a kill zone
     fn is_close_to_enemies(&self, unit: &Unit) -> bool {
+        let is_air = self.db.unit_type(unit.type_id).is_air;
+        if self.influence.is_kill_zone(unit.pos, is_air) {
+            return true;
+        }
         for (_, target) in self.state.units() {
-            if target.player_id == self.id {
+            if self.state.is_ally(target.player_id, self.id) {
                 continue;
             }
-            let target_type = &self.db.unit_type(target.type_id);
-            let attacker_type = &self.db.unit_type(unit.type_id);
-            let weapon_type = &self.db.weapon_type(attacker_type.weapon_type_id);
-            let distance = distance(unit.pos.map_pos, target.pos.map_pos);
-            let max_distance = if target_type.is_air {
-                match weapon_type.max_air_distance {
-                    Some(max_air_distance) => max_air_distance,
-                    None => continue, // can not attack air unit, skipping.
-                }
-            } else {
-                weapon_type.max_distance
-            };
-            if distance <= max_distance {
+            if is_in_range(&self.db, unit, target) {
                 return true;
             }
         }
         false
     }
 
-    pub fn try_get_attack_command(&self) -> Option<Command> {
+    fn is_far_from_goal(&self, unit: &Unit) -> bool {
+        match distance_to_goal(&self.state, self.id, unit.pos.map_pos) {
+            Some(d) => d > LONG_APPROACH_DISTANCE,
+            None => false,
+        }
+    }
+
+    fn is_at_goal(&self, unit: &Unit) -> bool {
+        match distance_to_goal(&self.state, self.id, unit.pos.map_pos) {
+            Some(d) => d <= Distance{n: 1},
+            None => true,
+        }
+    }
+
+    /// Infantry goes in the truck, slow guns are towed behind it
+    fn can_ride(&self, transporter: &Unit, unit: &Unit) -> bool {
+        let transporter_type = self.db.unit_type(transporter.type_id);
+        let unit_type = self.db.unit_type(unit.type_id);
+        if unit_type.can_be_towed {
+            !unit_type.is_transporter
+                && unit_type.size <= transporter_type.size
+                && unit_type.move_points < transporter_type.move_points
+                && transporter.attached_unit_id.is_none()
+        } else {
+            unit_type.is_infantry && transporter.passenger_id.is_none()
+        }
+    }
+
+    fn needs_ride(&self, transporter: &Unit, unit: &Unit) -> bool {
+        is_commandable(self.id, unit)
+            && unit.move_points.unwrap().n > 0
+            && !self.is_garrison(unit)
+            && self.can_ride(transporter, unit)
+            && self.is_far_from_goal(unit)
+            && !self.is_close_to_enemies(unit)
+    }
+
+    fn is_empty_transporter(&self, unit: &Unit) -> bool {
+        self.db.unit_type(unit.type_id).is_transporter
+            && unit.passenger_id.is_none()
+            && unit.attached_unit_id.is_none()
+    }
+
+    /// A free position next to the nearest unit that waits for this transporter
+    fn get_pickup_pos(&self, transporter: &Unit) -> Option<ExactPos> {
+        let mut best_pos = None;
+        let mut best_cost = movement::max_cost();
         for (_, unit) in self.state.units() {
-            if unit.player_id != self.id {
+            if !self.needs_ride(transporter, unit) {
+                continue;
+            }
+            for dir in dirs() {
+                let pos = Dir::get_neighbour_pos(unit.pos.map_pos, dir);
+                if !self.state.map().is_inboard(pos) {
+                    continue;
+                }
+                if let Some((cost, pos)) = self.estimate_path(transporter, pos) {
+                    if best_cost.n > cost.n {
+                        best_cost = cost;
+                        best_pos = Some(pos);
+                    }
+                }
+            }
+        }
+        best_pos
+    }
+
+    /// First free neighbour position where the command made by `f` is valid
+    fn try_get_command_near<F: Fn(ExactPos) -> Command>(
+        &self,
+        unit: &Unit,
+        f: F,
+    ) -> Option<Command> {
+        for dir in dirs() {
+            let pos = Dir::get_neighbour_pos(unit.pos.map_pos, dir);
+            if !self.state.map().is_inboard(pos) {
+                continue;
+            }
+            let exact_pos = match get_free_exact_pos(
+                &self.state,
+                self.db.unit_type(unit.type_id),
+                pos,
+            ) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let command = f(exact_pos);
+            if check_command(&self.db, self.id, &self.state, &command).is_ok() {
+                return Some(command);
+            }
+        }
+        None
+    }
+
+    /// Hard AI prefers the targets that can shoot back
+    fn attack_score(&self, attacker: &Unit, target: &Unit) -> f32 {
+        let score = attack_score(&self.db, &self.state, attacker, target);
+        if self.difficulty == Difficulty::Hard && is_in_range(&self.db, target, attacker) {
+            score * HARD_THREAT_FACTOR
+        } else {
+            score
+        }
+    }
+
+    pub fn try_get_attack_command(&mut self) -> Option<Command> {
+        if self.difficulty == Difficulty::Easy
+            && self.rng.gen_range(0, 100) < EASY_MISTAKE_CHANCE
+        {
+            return self.try_get_random_attack_command();
+        }
+        let mut best_command = None;
+        let mut best_score = 0.0;
+        for (_, unit) in self.state.units() {
+            if !is_commandable(self.id, unit) {
                 continue;
             }
             if unit.attack_points.unwrap().n <= 0 {
                 continue;
             }
             for (_, target) in self.state.units() {
-                if target.player_id == self.id {
+                if self.state.is_ally(target.player_id, self.id) {
+                    continue;
+                }
+                let hit_chance = attack::hit_chance(
+                    &self.db, &self.state, unit, target);
+                if hit_chance.n < MIN_HIT_CHANCE {
+                    continue;
+                }
+                let command = Command::AttackUnit {
+                    attacker_id: unit.id,
+                    defender_id: target.id,
+                };
+                if check_command(&self.db, self.id, &self.state, &command).is_err() {
+                    continue;
+                }
+                let score = self.attack_score(unit, target);
+                if score > best_score {
+                    best_score = score;
+                    best_command = Some(command);
+                }
+            }
+        }
+        best_command
+    }
+
+    /// Easy AI's mistake: shoot at anything, even with no chance to hit
+    fn try_get_random_attack_command(&mut self) -> Option<Command> {
+        let mut commands = Vec::new();
+        for (_, unit) in self.state.units() {
+            if !is_commandable(self.id, unit) {
+                continue;
+            }
+            for (_, target) in self.state.units() {
+                if self.state.is_ally(target.player_id, self.id) {
                     continue;
                 }
                 let command = Command::AttackUnit {
@@ -139,23 +763,34 @@ impl Ai {
                     defender_id: target.id,
                 };
                 if check_command(&self.db, self.id, &self.state, &command).is_ok() {
-                    return Some(command);
+                    commands.push(command);
                 }
             }
         }
-        None
+        if commands.is_empty() {
+            None
+        } else {
+            let index = self.rng.gen_range(0, commands.len());
+            Some(commands.swap_remove(index))
+        }
     }
 
     pub fn try_get_move_command(&mut self) -> Option<Command> {
         for (_, unit) in self.state.units() {
-            if unit.player_id != self.id {
+            if !is_commandable(self.id, unit) {
                 continue;
             }
-            if self.is_close_to_enemies(unit) {
+            // Easy AI doesn't stop when enemies are in range
+            if self.difficulty != Difficulty::Easy && self.is_close_to_enemies(unit) {
                 continue;
             }
             self.pathfinder.fill_map(&self.state, unit);
-            let destination = match self.get_best_pos(unit) {
+            let pickup_pos = if self.is_empty_transporter(unit) {
+                self.get_pickup_pos(unit)
+            } else {
+                None
+            };
+            let destination = match pickup_pos.or_else(|| self.get_best_pos(unit)) {
                 Some(destination) => destination,
                 None => continue,
             };
@@ -167,6 +802,10 @@ impl Ai {
                 Some(path) => path,
                 None => continue,
             };
+            let path = self.get_safe_path(unit, path, destination);
+            if path.len() < 2 {
+                continue;
+            }
             let cost = path_cost(&self.db, &self.state, unit, &path);
             let move_points = unit.move_points.unwrap();
             if move_points.n < cost.n {
@@ -185,30 +824,267 @@ impl Ai {
         None
     }
 
-    fn get_shuffled_reinforcement_sectors(&self, player_id: PlayerId) -> Vec<&Object> {
-        let mut reinforcement_sectors = Vec::new();
-        for object in self.state.objects().values() {
-            let owner_id = match object.owner_id {
+    /// Moves the garrisons that are out of cover into cover
+    pub fn try_get_garrison_command(&mut self) -> Option<Command> {
+        for (_, unit) in self.state.units() {
+            if !is_commandable(self.id, unit) || unit.move_points.unwrap().n <= 0 {
+                continue;
+            }
+            if !self.db.unit_type(unit.type_id).is_infantry || self.is_cover(unit.pos) {
+                continue;
+            }
+            let sector = match self.get_garrisoned_sector(unit) {
+                Some(sector) => sector.clone(),
+                None => continue,
+            };
+            self.pathfinder.fill_map(&self.state, unit);
+            let destination = match self.get_cover_pos(unit, &sector) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let path = match self.pathfinder.get_path(destination) {
+                Some(path) => path,
+                None => continue,
+            };
+            let command = Command::Move {
+                unit_id: unit.id,
+                path: path,
+                mode: MoveMode::Fast,
+            };
+            if check_command(&self.db, self.id, &self.state, &command).is_ok() {
+                return Some(command);
+            }
+        }
+        None
+    }
+
+    /// Mortars blind the enemies that they can't hurt
+    /// but that can shoot at our units
+    pub fn try_get_smoke_command(&self) -> Option<Command> {
+        for (_, unit) in self.state.units() {
+            if !is_commandable(self.id, unit) {
+                continue;
+            }
+            let unit_type = self.db.unit_type(unit.type_id);
+            if self.db.weapon_type(unit_type.weapon_type_id).smoke.is_none() {
+                continue;
+            }
+            for (_, target) in self.state.units() {
+                if self.state.is_ally(target.player_id, self.id) || !target.is_alive {
+                    continue;
+                }
+                let hit_chance = attack::hit_chance(
+                    &self.db, &self.state, unit, target);
+                if hit_chance.n >= MIN_HIT_CHANCE {
+                    continue;
+                }
+                let pos = target.pos.map_pos;
+                if self.state.objects_at(pos).any(|o| o.class == ObjectClass::Smoke) {
+                    continue;
+                }
+                let is_threat = self.state.units().any(|(_, own_unit)| {
+                    is_commandable(self.id, own_unit)
+                        && is_in_range(&self.db, target, own_unit)
+                });
+                if !is_threat {
+                    continue;
+                }
+                let command = Command::Smoke{unit_id: unit.id, pos: pos};
+                if check_command(&self.db, self.id, &self.state, &command).is_ok() {
+                    return Some(command);
+                }
+            }
+        }
+        None
+    }
+
+    /// Infantry gets into an adjacent truck before a long approach
+    pub fn try_get_load_command(&self) -> Option<Command> {
+        for (_, transporter) in self.state.units() {
+            if !is_commandable(self.id, transporter) {
+                continue;
+            }
+            if !self.db.unit_type(transporter.type_id).is_transporter {
+                continue;
+            }
+            for (_, passenger) in self.state.units() {
+                if self.db.unit_type(passenger.type_id).can_be_towed {
+                    continue;
+                }
+                if !self.needs_ride(transporter, passenger) {
+                    continue;
+                }
+                let command = Command::LoadUnit {
+                    transporter_id: transporter.id,
+                    passenger_id: passenger.id,
+                };
+                if check_command(&self.db, self.id, &self.state, &command).is_ok() {
+                    return Some(command);
+                }
+            }
+        }
+        None
+    }
+
+    /// Passengers get out near the enemies or at the goal
+    pub fn try_get_unload_command(&self) -> Option<Command> {
+        for (_, transporter) in self.state.units() {
+            if !is_commandable(self.id, transporter) {
+                continue;
+            }
+            let passenger_id = match transporter.passenger_id {
                 Some(id) => id,
                 None => continue,
             };
-            if owner_id != player_id {
+            let passenger = self.state.unit(passenger_id);
+            if !self.is_close_to_enemies(passenger) && !self.is_at_goal(transporter) {
+                continue;
+            }
+            let command = self.try_get_command_near(passenger, |pos| {
+                Command::UnloadUnit {
+                    transporter_id: transporter.id,
+                    passenger_id: passenger_id,
+                    pos: pos,
+                }
+            });
+            if command.is_some() {
+                return command;
+            }
+        }
+        None
+    }
+
+    /// Trucks hook up slow guns that have a long way to go
+    pub fn try_get_attach_command(&self) -> Option<Command> {
+        for (_, transporter) in self.state.units() {
+            if !is_commandable(self.id, transporter) {
+                continue;
+            }
+            if !self.db.unit_type(transporter.type_id).is_transporter {
+                continue;
+            }
+            for (_, unit) in self.state.units() {
+                if !self.db.unit_type(unit.type_id).can_be_towed {
+                    continue;
+                }
+                if !self.needs_ride(transporter, unit) {
+                    continue;
+                }
+                let command = Command::Attach {
+                    transporter_id: transporter.id,
+                    attached_unit_id: unit.id,
+                };
+                if check_command(&self.db, self.id, &self.state, &command).is_ok() {
+                    return Some(command);
+                }
+            }
+        }
+        None
+    }
+
+    /// Towed guns are left in position before the enemies get in range
+    pub fn try_get_detach_command(&self) -> Option<Command> {
+        for (_, transporter) in self.state.units() {
+            if !is_commandable(self.id, transporter) {
                 continue;
             }
-            if object.class != ObjectClass::ReinforcementSector {
+            let attached_unit_id = match transporter.attached_unit_id {
+                Some(id) => id,
+                None => continue,
+            };
+            let attached_unit = self.state.unit(attached_unit_id);
+            if !self.is_close_to_enemies(attached_unit) && !self.is_at_goal(transporter) {
                 continue;
             }
-            reinforcement_sectors.push(object);
+            let command = self.try_get_command_near(transporter, |pos| {
+                Command::Detach {
+                    transporter_id: transporter.id,
+                    pos: pos,
+                }
+            });
+            if command.is_some() {
+                return command;
+            }
         }
-        thread_rng().shuffle(&mut reinforcement_sectors);
-        reinforcement_sectors
+        None
     }
 
-    pub fn try_get_create_unit_command(&self) -> Option<Command> {
-        let reinforcement_sectors = self.get_shuffled_reinforcement_sectors(self.id);
+    /// Infantry in cover holds fire to stay hidden
+    /// until the enemies come close enough for an ambush
+    pub fn try_get_reaction_fire_mode_command(&self) -> Option<Command> {
+        for (_, unit) in self.state.units() {
+            if !is_commandable(self.id, unit) {
+                continue;
+            }
+            let unit_type = self.db.unit_type(unit.type_id);
+            if !self.db.weapon_type(unit_type.weapon_type_id).reaction_fire {
+                continue;
+            }
+            // Hidden infantry lets the enemy come closer
+            let mode = if unit_type.is_infantry && self.is_cover(unit.pos)
+                && !self.is_close_to_enemies(unit)
+            {
+                ReactionFireMode::HoldFire
+            } else {
+                ReactionFireMode::Normal
+            };
+            if unit.reaction_fire_mode == mode {
+                continue;
+            }
+            return Some(Command::SetReactionFireMode {
+                unit_id: unit.id,
+                mode: mode,
+            });
+        }
+        None
+    }
+
+    /// Known enemy types with the strength of their units
+    fn known_enemy_types(&self) -> Vec<(UnitTypeId, f32)> {
+        let mut enemies = Vec::new();
+        for (_, unit) in self.state.units() {
+            if unit.is_alive && !self.state.is_ally(unit.player_id, self.id) {
+                enemies.push((unit.type_id, unit_strength(&self.db, unit)));
+            }
+        }
+        for remembered in self.memory.units() {
+            let unit = &remembered.unit;
+            enemies.push((unit.type_id, unit_strength(&self.db, unit)));
+        }
+        enemies
+    }
+
+    /// Easy AI buys random units, others buy what suits the known
+    /// enemies and may save points for a better unit
+    fn get_unit_types_to_buy(&mut self) -> Vec<UnitTypeId> {
+        if self.difficulty == Difficulty::Easy {
+            return get_shuffled_indices(&mut self.rng, self.db.unit_types())
+                .into_iter()
+                .map(|index| UnitTypeId{id: index as i32})
+                .collect();
+        }
+        let enemies = self.known_enemy_types();
+        let type_ids = purchase::rank_unit_types(
+            &self.db, &self.state, self.id, &enemies);
+        let best_cost = match type_ids.first() {
+            Some(&type_id) => self.db.unit_type(type_id).cost,
+            None => return Vec::new(),
+        };
+        let turns = purchase::turns_to_afford(&self.state, self.id, best_cost);
+        if turns > 0 && turns <= purchase::MAX_SAVING_TURNS
+            && !purchase::is_endgame(&self.state)
+        {
+            return Vec::new();
+        }
+        type_ids
+    }
+
+    pub fn try_get_create_unit_command(&mut self) -> Option<Command> {
+        let type_ids = self.get_unit_types_to_buy();
+        let reinforcement_sectors = get_shuffled_reinforcement_sectors(
+            &self.state, &mut self.rng, self.id);
         let reinforcement_points = self.state.reinforcement_points()[&self.id];
-        for type_index in get_shuffled_indices(self.db.unit_types()) {
-            let unit_type_id = UnitTypeId{id: type_index as i32};
+        for unit_type_id in type_ids {
             let unit_type = self.db.unit_type(unit_type_id);
             if unit_type.cost > reinforcement_points {
                 continue;
@@ -235,15 +1111,151 @@ impl Ai {
         None
     }
 
-    pub fn get_command(&mut self) -> Command {
-        if let Some(cmd) = self.try_get_attack_command() {
+}
+
+impl Ai for SimpleAi {
+    fn apply_event(&mut self, event: &CoreEvent) {
+        self.memory.apply_event(&self.state, event);
+        self.state.apply_event(event);
+        self.memory.forget_visible(&self.state);
+        self.is_influence_dirty = true;
+    }
+
+    fn get_command(&mut self) -> Command {
+        self.update_influence();
+        if let Some(cmd) = self.try_get_smoke_command() {
+            cmd
+        } else if let Some(cmd) = self.try_get_attack_command() {
+            cmd
+        } else if let Some(cmd) = self.try_get_unload_command() {
+            cmd
+        } else if let Some(cmd) = self.try_get_detach_command() {
+            cmd
+        } else if let Some(cmd) = self.try_get_load_command() {
+            cmd
+        } else if let Some(cmd) = self.try_get_attach_command() {
             cmd
         } else if let Some(cmd) = self.try_get_move_command() {
             cmd
+        } else if let Some(cmd) = self.try_get_reaction_fire_mode_command() {
+            cmd
         } else if let Some(cmd) = self.try_get_create_unit_command() {
             cmd
         } else {
             Command::EndTurn
         }
     }
+
+    fn save(&self, w: &mut Writer) {
+        self.id.serialize(w);
+        self.rng.serialize(w);
+        self.difficulty.serialize(w);
+        w.new_line();
+        self.memory.serialize(w);
+        w.new_line();
+        self.state.save(w);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use Core;
+    use options::{Options};
+    use scenario::{Scenario};
+    use player::{PlayerId, PlayerClass};
+    use position::{MapPos, ExactPos, SlotId};
+    use event::{Command};
+    use unit::{UnitId};
+    use rng::{CoreRng};
+    use ai::{Ai, SimpleAi, Posture};
+
+    const GARRISON_ID: UnitId = UnitId{id: 2};
+    const SOLDIER_ID: UnitId = UnitId{id: 3};
+
+    fn line_pos(x: i32) -> ExactPos {
+        ExactPos {
+            map_pos: MapPos{v: Vector2{x: x, y: 0}},
+            slot_id: SlotId::Id(0),
+        }
+    }
+
+    /// Player 0 has one unit of `enemy_type`. The AI's player 1 has
+    /// a garrison in its sector and a soldier between the sector
+    /// and the enemy that can see the enemy but can't be shot.
+    fn new_ai(enemy_type: &str) -> SimpleAi {
+        let text = "
+            size 13 1
+            target_score 5
+            players_count 2
+            terrain
+                ............T
+            reinforcement_sector 1 0 some 0
+            reinforcement_sector 7 0 some 1
+            reinforcement_sector 11 0 some 1
+            sector 11 0 12 0 end
+        ";
+        let scenario = text.parse::<Scenario>().unwrap();
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let options = Options::new(players, scenario);
+        let mut core = Core::new(&options);
+        let enemy_type_id = core.db().unit_type_id(enemy_type);
+        let soldier_type_id = core.db().unit_type_id("soldier");
+        core.do_command(Command::CreateUnit{pos: line_pos(1), type_id: enemy_type_id});
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::CreateUnit{pos: line_pos(11), type_id: soldier_type_id});
+        core.do_command(Command::CreateUnit{pos: line_pos(7), type_id: soldier_type_id});
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::EndTurn);
+        let id = PlayerId{id: 1};
+        let mut ai = SimpleAi::new(core.db().clone(), &options, id, CoreRng::new(0));
+        while let Some(event) = core.get_event_for(id) {
+            ai.apply_event(&event);
+        }
+        assert!(ai.state.units().any(|(_, unit)| unit.player_id == PlayerId{id: 0}));
+        ai.update_influence();
+        ai
+    }
+
+    fn best_x(ai: &mut SimpleAi, unit_id: UnitId) -> Option<i32> {
+        let unit = ai.state.unit(unit_id).clone();
+        ai.pathfinder.fill_map(&ai.state, &unit);
+        ai.get_best_pos(&unit).map(|pos| pos.map_pos.v.x)
+    }
+
+    #[test]
+    fn test_hold_against_stronger_enemy() {
+        let mut ai = new_ai("heavy_tank");
+        assert_eq!(ai.posture(), Posture::Hold);
+        // The soldier falls back to the sector instead of going for the tank
+        let x = best_x(&mut ai, SOLDIER_ID).unwrap();
+        assert!(x >= 11, "x = {}", x);
+    }
+
+    #[test]
+    fn test_advance_against_weaker_enemy() {
+        let mut ai = new_ai("soldier");
+        assert_eq!(ai.posture(), Posture::Advance);
+        let x = best_x(&mut ai, SOLDIER_ID).unwrap();
+        assert!(x <= 2, "x = {}", x);
+    }
+
+    #[test]
+    fn test_garrison_keeps_objective() {
+        for &enemy_type in &["heavy_tank", "soldier"] {
+            let mut ai = new_ai(enemy_type);
+            assert!(ai.is_garrison(&ai.state.unit(GARRISON_ID).clone()));
+            assert!(!ai.is_garrison(&ai.state.unit(SOLDIER_ID).clone()));
+            // The garrison takes cover in the trees of its sector
+            // and doesn't leave it even when the AI advances
+            assert_eq!(best_x(&mut ai, GARRISON_ID), Some(12));
+            match ai.get_command() {
+                Command::Move{unit_id, ref path, ..} => {
+                    assert_eq!(unit_id, GARRISON_ID);
+                    assert_eq!(path.last().unwrap().map_pos.v.x, 12);
+                },
+                ref command => panic!("Unexpected command: {:?}", command),
+            }
+        }
+    }
 }
@@ -1,9 +1,10 @@
-use rand::{thread_rng, Rng};
+use rand::{Rng};
 use db::{Db};
 use game_state::{State};
-use unit::{Unit};
+use unit::{Unit, UnitTypeId};
 use misc::{clamp};
 use map::{Terrain};
+use rng::{CoreRng};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct AttackPoints{pub n: i32}
@@ -11,16 +12,29 @@ pub struct AttackPoints{pub n: i32}
 #[derive(PartialOrd, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct HitChance{pub n: i32}
 
+/// One hit kills from one to this number of soldiers
+const MAX_KILLED_SOLDIERS: i32 = 4;
+
 pub fn hit_chance(
     db: &Db,
     state: &State,
     attacker: &Unit,
     defender: &Unit,
 ) -> HitChance {
-    let attacker_type = db.unit_type(attacker.type_id);
-    let defender_type = db.unit_type(defender.type_id);
-    let weapon_type = db.weapon_type(attacker_type.weapon_type_id);
     let cover_bonus = cover_bonus(db, state, defender);
+    type_hit_chance(db, attacker.type_id, defender.type_id, cover_bonus)
+}
+
+/// Hit chance of any unit of one type against any unit of another type
+pub fn type_hit_chance(
+    db: &Db,
+    attacker_type_id: UnitTypeId,
+    defender_type_id: UnitTypeId,
+    cover_bonus: i32,
+) -> HitChance {
+    let attacker_type = db.unit_type(attacker_type_id);
+    let defender_type = db.unit_type(defender_type_id);
+    let weapon_type = db.weapon_type(attacker_type.weapon_type_id);
     let hit_test_v = -7 - cover_bonus + defender_type.size
         + weapon_type.accuracy + attacker_type.weapon_skill;
     let pierce_test_v = 10 + -defender_type.armor + weapon_type.ap;
@@ -45,21 +59,165 @@ fn cover_bonus(db: &Db, state: &State, defender: &Unit) -> i32 {
     }
 }
 
-pub fn get_killed_count(db: &Db, state: &State, attacker: &Unit, defender: &Unit) -> i32 {
-    let hit = attack_test(db, state, attacker, defender);
+pub fn get_killed_count(
+    db: &Db,
+    state: &State,
+    attacker: &Unit,
+    defender: &Unit,
+    rng: &mut CoreRng,
+) -> i32 {
+    let hit = attack_test(db, state, attacker, defender, rng);
     if !hit {
         return 0;
     }
     let defender_type = db.unit_type(defender.type_id);
     if defender_type.is_infantry {
-        clamp(thread_rng().gen_range(1, 5), 1, defender.count)
+        clamp(rng.gen_range(1, MAX_KILLED_SOLDIERS + 1), 1, defender.count)
     } else {
         1
     }
 }
 
-fn attack_test(db: &Db, state: &State, attacker: &Unit, defender: &Unit) -> bool {
+/// Average result of `get_killed_count`
+pub fn expected_killed_count(
+    db: &Db,
+    state: &State,
+    attacker: &Unit,
+    defender: &Unit,
+) -> f32 {
+    let hit_chance = hit_chance(db, state, attacker, defender).n as f32 / 100.0;
+    let defender_type = db.unit_type(defender.type_id);
+    if defender_type.is_infantry {
+        let mut sum = 0;
+        for killed in 1 .. MAX_KILLED_SOLDIERS + 1 {
+            sum += clamp(killed, 1, defender.count);
+        }
+        hit_chance * sum as f32 / MAX_KILLED_SOLDIERS as f32
+    } else {
+        hit_chance
+    }
+}
+
+/// Chance that one attack destroys the whole defender, in percents
+pub fn destroy_chance(
+    db: &Db,
+    state: &State,
+    attacker: &Unit,
+    defender: &Unit,
+) -> i32 {
+    let hit_chance = hit_chance(db, state, attacker, defender).n;
+    let defender_type = db.unit_type(defender.type_id);
+    if defender_type.is_infantry {
+        let enough_count = (1 .. MAX_KILLED_SOLDIERS + 1)
+            .filter(|&killed| killed >= defender.count)
+            .count() as i32;
+        hit_chance * enough_count / MAX_KILLED_SOLDIERS
+    } else if defender.count <= 1 {
+        hit_chance
+    } else {
+        0
+    }
+}
+
+fn attack_test(
+    db: &Db,
+    state: &State,
+    attacker: &Unit,
+    defender: &Unit,
+    rng: &mut CoreRng,
+) -> bool {
     let k = hit_chance(db, state, attacker, defender).n;
-    let r = thread_rng().gen_range(0, 100);
+    let r = rng.gen_range(0, 100);
     r < k
 }
+
+#[cfg(test)]
+mod tests {
+    use std::rc::{Rc};
+    use cgmath::{Vector2};
+    use db::{Db};
+    use game_state::{State};
+    use options::{Options};
+    use player::{PlayerId, PlayerClass};
+    use position::{MapPos, ExactPos, SlotId};
+    use scenario::{test_scenario};
+    use unit::{Unit, UnitId};
+    use event::{ReactionFireMode};
+    use attack::{hit_chance, expected_killed_count, destroy_chance};
+
+    /// The default db where the field gun never misses soldiers
+    fn sniper_db() -> Db {
+        let text = include_str!("../data/db.txt");
+        let field_gun = "unit_type \"field_gun\"\n    size 6\n    count 1\n    \
+            armor 3\n    toughness 7\n    weapon_skill ";
+        let sniper_text = text.replacen(
+            &format!("{}7", field_gun), &format!("{}8", field_gun), 1);
+        assert!(sniper_text != text);
+        sniper_text.parse().unwrap()
+    }
+
+    /// On the plain tile, so there's no cover
+    fn unit(db: &Db, type_name: &str, count: i32) -> Unit {
+        Unit {
+            id: UnitId{id: 0},
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: 0, y: 0}},
+                slot_id: SlotId::WholeTile,
+            },
+            player_id: PlayerId{id: 0},
+            type_id: db.unit_type_id(type_name),
+            move_points: None,
+            attack_points: None,
+            reactive_attack_points: None,
+            reaction_fire_mode: ReactionFireMode::Normal,
+            count: count,
+            morale: 100,
+            passenger_id: None,
+            attached_unit_id: None,
+            is_alive: true,
+            is_loaded: false,
+            is_attached: false,
+        }
+    }
+
+    #[test]
+    fn test_expected_damage() {
+        // Attacker, defender, defender's count, hit chance,
+        // expected killed count and destroy chance
+        let certain_hits = [
+            ("field_gun", "soldier", 4, 100, 2.5, 25),
+            ("field_gun", "soldier", 2, 100, 1.75, 75),
+            ("field_gun", "soldier", 1, 100, 1.0, 100),
+        ];
+        let zero_hits = [
+            ("soldier", "heavy_tank", 1, 0, 0.0, 0),
+            ("soldier", "medium_tank", 1, 0, 0.0, 0),
+        ];
+        let armored = [
+            ("heavy_tank", "truck", 1, 90, 0.9, 90),
+            ("medium_tank", "heavy_tank", 1, 18, 0.18, 18),
+            // Some vehicles are never destroyed by one shot
+            ("heavy_tank", "truck", 2, 90, 0.9, 0),
+        ];
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let options = Options::new(players, test_scenario());
+        let default_db = Rc::new(Db::new());
+        let sniper_db = Rc::new(sniper_db());
+        let tables = [
+            (sniper_db, &certain_hits[..]),
+            (default_db.clone(), &zero_hits[..]),
+            (default_db, &armored[..]),
+        ];
+        for &(ref db, table) in &tables {
+            let state = State::new_full(db.clone(), &options);
+            for &(attacker, defender, count, hit, killed, destroy) in table {
+                let attacker = unit(db, attacker, 1);
+                let defender = unit(db, defender, count);
+                assert_eq!(hit_chance(db, &state, &attacker, &defender).n, hit);
+                let expected = expected_killed_count(db, &state, &attacker, &defender);
+                assert!((expected - killed).abs() < 0.001, "{} != {}", expected, killed);
+                assert_eq!(destroy_chance(db, &state, &attacker, &defender), destroy);
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Plays a game between AI players without any graphics.
+//!
+//! Usage: zoc_headless <scenario file> [turns limit] [seed] [easy|normal|hard] [ai names]
+//!
+//! AI names are separated by commas, one for each player in order:
+//! "search,simple" pits the search AI against the simple one.
+
+extern crate core;
+
+use std::{env, process};
+use std::collections::{BTreeMap};
+use std::fs::{File};
+use std::io::{Read};
+use std::rc::{Rc};
+use core::{Core};
+use core::options::{Options, Difficulty};
+use core::player::{PlayerClass, PlayerId};
+use core::scenario::{Scenario};
+use core::ai::{AiBuilder, builtin_ai_builder};
+
+const DEFAULT_TURNS_LIMIT: i32 = 100;
+
+fn read_scenario(path: &str) -> Scenario {
+    let mut text = String::new();
+    let result = File::open(path).and_then(|mut file| file.read_to_string(&mut text));
+    if let Err(err) = result {
+        println!("Can`t read '{}': {}", path, err);
+        process::exit(1);
+    }
+    match text.parse::<Scenario>() {
+        Ok(scenario) => scenario,
+        Err(err) => {
+            println!("Bad scenario '{}': {}", path, err);
+            process::exit(1);
+        },
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<String>, name: &str) -> Option<T> {
+    arg.map(|arg| match arg.parse() {
+        Ok(value) => value,
+        Err(_) => {
+            println!("Bad {}: '{}'", name, arg);
+            process::exit(1);
+        },
+    })
+}
+
+fn parse_difficulty(arg: Option<String>) -> Difficulty {
+    match arg.as_ref().map(|arg| arg.as_str()) {
+        Some("easy") => Difficulty::Easy,
+        Some("normal") | None => Difficulty::Normal,
+        Some("hard") => Difficulty::Hard,
+        Some(arg) => {
+            println!("Bad difficulty: '{}'", arg);
+            process::exit(1);
+        },
+    }
+}
+
+fn parse_ais(arg: Option<String>) -> BTreeMap<PlayerId, Rc<AiBuilder>> {
+    let mut ais = BTreeMap::new();
+    let arg = match arg {
+        Some(arg) => arg,
+        None => return ais,
+    };
+    for (id, name) in arg.split(',').enumerate() {
+        match builtin_ai_builder(name) {
+            Some(builder) => {
+                ais.insert(PlayerId{id: id as i32}, builder);
+            },
+            None => {
+                println!("Unknown AI: '{}'", name);
+                process::exit(1);
+            },
+        }
+    }
+    ais
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let path = match args.next() {
+        Some(path) => path,
+        None => {
+            println!("Usage: zoc_headless <scenario file> [turns limit] [seed] [easy|normal|hard] [ai names]");
+            process::exit(1);
+        },
+    };
+    let turns_limit = parse_arg(args.next(), "turns limit")
+        .unwrap_or(DEFAULT_TURNS_LIMIT);
+    let seed = parse_arg(args.next(), "seed");
+    let difficulty = parse_difficulty(args.next());
+    let ais = parse_ais(args.next());
+    let scenario = read_scenario(&path);
+    let players_count = scenario.players_count as usize;
+    let options = Options {
+        ais: ais,
+        difficulty: difficulty,
+        seed: seed,
+        .. Options::new(vec![PlayerClass::Ai; players_count], scenario)
+    };
+    let mut core = Core::new(&options);
+    println!("Scenario: {}, seed: {}", path, core.seed());
+    let mut turns = 0;
+    while turns < turns_limit && core.state().winner_team_id().is_none() {
+        for _ in 0 .. players_count {
+            core.do_ai_turn();
+        }
+        turns += 1;
+    }
+    let state = core.state();
+    match state.winner_team_id() {
+        Some(team_id) => println!("Winner: team {}", team_id.id),
+        None => println!("Winner: none, turns limit reached"),
+    }
+    println!("Turns: {}", turns);
+    for id in 0 .. players_count as i32 {
+        let player_id = PlayerId{id: id};
+        println!(
+            "Player {}: score {}, casualties {}",
+            id,
+            state.score()[&player_id].n,
+            state.casualties()[&player_id],
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Hosts a network game: owns the core, plays the AI turns
+//! and waits for a client for every human player.
+//! A player who has lost the connection can join again
+//! with the secret that its client got on joining.
+//!
+//! Usage: zoc_server <scenario file> [port] [players] [seed] [easy|normal|hard]
+//!     [alternate|simultaneous] [turn time limit] [game time limit]
+//!
+//! Players are separated by commas, one for each player in order:
+//! "human,ai" lets one client play against the AI. All are humans by default.
+//! Simultaneous turns are only for humans.
+//! Time limits are in seconds, there are none by default.
+
+extern crate core;
+
+use std::{env, process};
+use std::fs::{File};
+use std::io::{Read};
+use std::net::{TcpListener};
+use std::time::{Duration};
+use core::{Core};
+use core::options::{Options, Difficulty, TurnMode};
+use core::player::{PlayerClass};
+use core::scenario::{Scenario};
+use core::net::{Server, DEFAULT_PORT};
+
+fn read_scenario(path: &str) -> Scenario {
+    let mut text = String::new();
+    let result = File::open(path).and_then(|mut file| file.read_to_string(&mut text));
+    if let Err(err) = result {
+        println!("Can`t read '{}': {}", path, err);
+        process::exit(1);
+    }
+    match text.parse::<Scenario>() {
+        Ok(scenario) => scenario,
+        Err(err) => {
+            println!("Bad scenario '{}': {}", path, err);
+            process::exit(1);
+        },
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<String>, name: &str) -> Option<T> {
+    arg.map(|arg| match arg.parse() {
+        Ok(value) => value,
+        Err(_) => {
+            println!("Bad {}: '{}'", name, arg);
+            process::exit(1);
+        },
+    })
+}
+
+fn parse_players(arg: Option<String>, players_count: usize) -> Vec<PlayerClass> {
+    let arg = match arg {
+        Some(arg) => arg,
+        None => return vec![PlayerClass::Human; players_count],
+    };
+    let players: Vec<PlayerClass> = arg.split(',').map(|name| match name {
+        "human" => PlayerClass::Human,
+        "ai" => PlayerClass::Ai,
+        _ => {
+            println!("Bad player: '{}'", name);
+            process::exit(1);
+        },
+    }).collect();
+    if players.len() != players_count {
+        println!("The scenario needs {} players", players_count);
+        process::exit(1);
+    }
+    players
+}
+
+fn parse_difficulty(arg: Option<String>) -> Difficulty {
+    match arg.as_ref().map(|arg| arg.as_str()) {
+        Some("easy") => Difficulty::Easy,
+        Some("normal") | None => Difficulty::Normal,
+        Some("hard") => Difficulty::Hard,
+        Some(arg) => {
+            println!("Bad difficulty: '{}'", arg);
+            process::exit(1);
+        },
+    }
+}
+
+fn parse_turn_mode(arg: Option<String>) -> TurnMode {
+    match arg.as_ref().map(|arg| arg.as_str()) {
+        Some("alternate") | None => TurnMode::Alternate,
+        Some("simultaneous") => TurnMode::Simultaneous,
+        Some(arg) => {
+            println!("Bad turn mode: '{}'", arg);
+            process::exit(1);
+        },
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let path = match args.next() {
+        Some(path) => path,
+        None => {
+            println!("Usage: zoc_server <scenario file> [port] [players] [seed] \
+                [easy|normal|hard] [alternate|simultaneous] \
+                [turn time limit] [game time limit]");
+            process::exit(1);
+        },
+    };
+    let port = parse_arg(args.next(), "port").unwrap_or(DEFAULT_PORT);
+    let scenario = read_scenario(&path);
+    let players_count = scenario.players_count as usize;
+    let players = parse_players(args.next(), players_count);
+    let seed = parse_arg(args.next(), "seed");
+    let difficulty = parse_difficulty(args.next());
+    let turn_mode = parse_turn_mode(args.next());
+    let turn_time_limit = parse_arg(args.next(), "turn time limit").map(Duration::from_secs);
+    let game_time_limit = parse_arg(args.next(), "game time limit").map(Duration::from_secs);
+    let options = Options {
+        turn_mode: turn_mode,
+        turn_time_limit: turn_time_limit,
+        game_time_limit: game_time_limit,
+        difficulty: difficulty,
+        seed: seed,
+        .. Options::new(players, scenario)
+    };
+    let core = match Core::try_new(&options) {
+        Ok(core) => core,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        },
+    };
+    let listener = match TcpListener::bind(("0.0.0.0", port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("Can`t listen on port {}: {}", port, err);
+            process::exit(1);
+        },
+    };
+    println!("Scenario: {}, seed: {}, port: {}", path, core.seed(), port);
+    let mut server = match Server::accept(core, &listener) {
+        Ok(server) => server,
+        Err(err) => {
+            println!("{}", err);
+            process::exit(1);
+        },
+    };
+    println!("All players are here");
+    loop {
+        match server.run() {
+            Ok(()) => {
+                let team_id = server.core().state().winner_team_id().unwrap();
+                println!("Winner: team {}", team_id.id);
+                return;
+            },
+            Err(err) => println!("Lost a player: {}", err),
+        }
+        println!("Waiting for the player to join again");
+        if let Err(err) = server.wait_for_players(&listener) {
+            println!("{}", err);
+            process::exit(1);
+        }
+        println!("All players are here");
+    }
+}
@@ -5,10 +5,12 @@ use movement::{path_cost, tile_cost, move_cost_modifier};
 use unit::{Unit};
 use db::{Db};
 use fov::{fov, simple_fov};
-use position::{can_place_unit};
-use event::{Command, FireMode};
+use position::{ExactPos, MapPos, can_place_unit};
+use event::{Command, FireMode, MoveMode};
 use object::{ObjectClass};
 use player::{PlayerId};
+use options::{TurnMode};
+use orders::{check_orders};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CommandError {
@@ -45,6 +47,13 @@ pub enum CommandError {
     BadAttachedUnitType,
     NoAttachedUnit,
     TooManyAttachedUnits,
+    BadPos,
+    CanNotAttackAllies,
+    OrdersOnly,
+    NoOrdersInAlternateTurns,
+    NoTransportInSimultaneousTurns,
+    NoTurnTimeLimit,
+    TimeIsNotOut,
 }
 
 impl CommandError {
@@ -83,6 +92,13 @@ impl CommandError {
             CommandError::BadAttachedUnitType => "Bad attached unit type",
             CommandError::NoAttachedUnit => "No attached unit",
             CommandError::TooManyAttachedUnits => "too many attached units",
+            CommandError::BadPos => "Bad position",
+            CommandError::CanNotAttackAllies => "Can not attack allies",
+            CommandError::OrdersOnly => "Units only take orders in simultaneous turns",
+            CommandError::NoOrdersInAlternateTurns => "Units don`t take orders in alternate turns",
+            CommandError::NoTransportInSimultaneousTurns => "No transport in simultaneous turns",
+            CommandError::NoTurnTimeLimit => "Turn has no time limit",
+            CommandError::TimeIsNotOut => "Time is not out",
         }
     }
 }
@@ -106,9 +122,32 @@ pub fn check_command(
     command: &Command,
 ) -> Result<(), CommandError> {
     assert!(state.is_partial());
+    if state.turn_mode() == TurnMode::Simultaneous {
+        if is_transport(command) {
+            return Err(CommandError::NoTransportInSimultaneousTurns);
+        }
+        if is_order(command) {
+            return Err(CommandError::OrdersOnly);
+        }
+    }
     match *command {
-        Command::EndTurn => Ok(()),
+        // The clocks are checked by the core
+        Command::EndTurn |
+        Command::PassTime{..} |
+        Command::TimeOut => Ok(()),
+        Command::SetOrders{unit_id, ref orders} => {
+            if state.turn_mode() != TurnMode::Simultaneous {
+                return Err(CommandError::NoOrdersInAlternateTurns);
+            }
+            check_orders(db, player_id, state, unit_id, orders)
+        },
         Command::CreateUnit{pos, type_id} => {
+            if !state.map().is_inboard(pos) {
+                return Err(CommandError::BadPos);
+            }
+            if type_id.id < 0 || type_id.id as usize >= db.unit_types().len() {
+                return Err(CommandError::BadUnitType);
+            }
             let mut is_sector = false;
             for object in state.objects_at(pos.map_pos) {
                 if object.class == ObjectClass::ReinforcementSector {
@@ -140,22 +179,7 @@ pub fn check_command(
             if unit.player_id != player_id {
                 return Err(CommandError::CanNotCommandEnemyUnits);
             }
-            if path.len() < 2 {
-                return Err(CommandError::BadPath);
-            }
-            for window in path.windows(2) {
-                let pos = window[1];
-                if !can_place_unit(state, db.unit_type(unit.type_id), pos) {
-                    return Err(CommandError::BadPath);
-                }
-            }
-            let cost = path_cost(db, state, unit, path).n
-                * move_cost_modifier(mode);
-            let move_points = unit.move_points.unwrap();
-            if cost > move_points.n {
-                return Err(CommandError::NotEnoughMovePoints);
-            }
-            Ok(())
+            check_move(db, state, unit, path, mode)
         },
         Command::AttackUnit{attacker_id, defender_id} => {
             let attacker = match state.unit_opt(attacker_id) {
@@ -175,6 +199,9 @@ pub fn check_command(
             if !defender.is_alive {
                 return Err(CommandError::UnitIsDead);
             }
+            if state.is_ally(defender.player_id, player_id) {
+                return Err(CommandError::CanNotAttackAllies);
+            }
             check_attack(db, state, attacker, defender, FireMode::Active)
         },
         Command::LoadUnit{transporter_id, passenger_id} => {
@@ -240,12 +267,18 @@ pub fn check_command(
             if !transporter_type.is_transporter {
                 return Err(CommandError::BadTransporterType);
             }
+            if !state.map().is_inboard(pos) {
+                return Err(CommandError::BadPos);
+            }
             if distance(transporter.pos.map_pos, pos.map_pos).n > 1 {
                 return Err(CommandError::UnloadDistanceIsTooBig);
             }
             if transporter.passenger_id.is_none() {
                 return Err(CommandError::TransporterIsEmpty);
             }
+            if transporter.passenger_id != Some(passenger_id) {
+                return Err(CommandError::BadPassengerId);
+            }
             if !can_place_unit(state, db.unit_type(passenger.type_id), pos) {
                 return Err(CommandError::DestinationTileIsNotEmpty);
             }
@@ -327,6 +360,9 @@ pub fn check_command(
             if transporter.player_id != player_id {
                 return Err(CommandError::CanNotCommandEnemyUnits);
             }
+            if !state.map().is_inboard(pos) {
+                return Err(CommandError::BadPos);
+            }
             if distance(transporter.pos.map_pos, pos.map_pos).n > 1 {
                 return Err(CommandError::UnloadDistanceIsTooBig);
             }
@@ -364,23 +400,113 @@ pub fn check_command(
             if unit.player_id != player_id {
                 return Err(CommandError::CanNotCommandEnemyUnits);
             }
-            let unit_type = db.unit_type(unit.type_id);
-            let weapon_type = db.weapon_type(unit_type.weapon_type_id);
-            if !weapon_type.smoke.is_some() {
-                return Err(CommandError::BadUnitType);
-            }
-            if distance(unit.pos.map_pos, pos) > weapon_type.max_distance {
-                return Err(CommandError::OutOfRange);
-            }
-            let attack_points = unit.attack_points.unwrap();
-            if attack_points.n != unit_type.attack_points.n {
-                return Err(CommandError::NotEnoughAttackPoints);
-            }
-            Ok(())
+            check_smoke(db, state, unit, pos)
         },
     }
 }
 
+/// Commands that are plotted as `Order`s in simultaneous turns
+fn is_order(command: &Command) -> bool {
+    match *command {
+        Command::Move{..} |
+        Command::AttackUnit{..} |
+        Command::Smoke{..} => true,
+        Command::LoadUnit{..} |
+        Command::UnloadUnit{..} |
+        Command::Attach{..} |
+        Command::Detach{..} |
+        Command::EndTurn |
+        Command::CreateUnit{..} |
+        Command::SetReactionFireMode{..} |
+        Command::SetOrders{..} |
+        Command::PassTime{..} |
+        Command::TimeOut => false,
+    }
+}
+
+/// The last command of every turn
+pub fn ends_turn(command: &Command) -> bool {
+    match *command {
+        Command::EndTurn | Command::TimeOut => true,
+        _ => false,
+    }
+}
+
+/// These have no orders yet, so simultaneous turns go without them
+pub fn is_transport(command: &Command) -> bool {
+    match *command {
+        Command::LoadUnit{..} |
+        Command::UnloadUnit{..} |
+        Command::Attach{..} |
+        Command::Detach{..} => true,
+        Command::Move{..} |
+        Command::AttackUnit{..} |
+        Command::Smoke{..} |
+        Command::EndTurn |
+        Command::CreateUnit{..} |
+        Command::SetReactionFireMode{..} |
+        Command::SetOrders{..} |
+        Command::PassTime{..} |
+        Command::TimeOut => false,
+    }
+}
+
+/// Can the unit go along the path from where it is now?
+pub fn check_move(
+    db: &Db,
+    state: &State,
+    unit: &Unit,
+    path: &[ExactPos],
+    mode: MoveMode,
+) -> Result<(), CommandError> {
+    if path.len() < 2 || path[0].map_pos != unit.pos.map_pos {
+        return Err(CommandError::BadPath);
+    }
+    for window in path.windows(2) {
+        let pos = window[1];
+        if !state.map().is_inboard(pos) {
+            return Err(CommandError::BadPath);
+        }
+        if distance(window[0].map_pos, pos.map_pos).n > 1 {
+            return Err(CommandError::BadPath);
+        }
+        if !can_place_unit(state, db.unit_type(unit.type_id), pos) {
+            return Err(CommandError::BadPath);
+        }
+    }
+    let cost = path_cost(db, state, unit, path).n
+        * move_cost_modifier(mode);
+    let move_points = unit.move_points.unwrap();
+    if cost > move_points.n {
+        return Err(CommandError::NotEnoughMovePoints);
+    }
+    Ok(())
+}
+
+pub fn check_smoke(
+    db: &Db,
+    state: &State,
+    unit: &Unit,
+    pos: MapPos,
+) -> Result<(), CommandError> {
+    let unit_type = db.unit_type(unit.type_id);
+    let weapon_type = db.weapon_type(unit_type.weapon_type_id);
+    if !weapon_type.smoke.is_some() {
+        return Err(CommandError::BadUnitType);
+    }
+    if !state.map().is_inboard(pos) {
+        return Err(CommandError::BadPos);
+    }
+    if distance(unit.pos.map_pos, pos) > weapon_type.max_distance {
+        return Err(CommandError::OutOfRange);
+    }
+    let attack_points = unit.attack_points.unwrap();
+    if attack_points.n != unit_type.attack_points.n {
+        return Err(CommandError::NotEnoughAttackPoints);
+    }
+    Ok(())
+}
+
 pub fn check_attack(
     db: &Db,
     state: &State,
@@ -1,387 +1,240 @@
+use std::{fmt, error};
+use std::str::{FromStr};
 use unit::{UnitType, WeaponType, UnitTypeId, WeaponTypeId};
-use map::{Distance};
-use movement::{MovePoints};
-use attack::{AttackPoints};
-use game_state::{ReinforcementPoints};
+use serialize::{Serialize, Deserialize, Writer, Reader, DeserializeError};
 
-fn weapon_type_id(weapon_types: &[WeaponType], name: &str)
-    -> WeaponTypeId
+/// Tables that are used when no other file is given
+const DEFAULT_DB: &'static str = include_str!("../data/db.txt");
+
+/// Most additional smoke clouds that a weapon can make,
+/// there are only so many tiles around the target
+pub const MAX_SMOKE: i32 = 3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DbError {
+    Syntax(DeserializeError),
+    NoWeaponType(String),
+    DuplicateName(String),
+    NegativeValue{name: String, field: &'static str},
+    TooBigValue{name: String, field: &'static str, max: i32},
+
+    /// The weapon's `min_distance` is greater than its `max_distance`
+    BadDistances(String),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DbError::Syntax(ref err) => {
+                write!(f, "Syntax error: {}", err)
+            },
+            DbError::NoWeaponType(ref name) => {
+                write!(f, "No weapon type with name \"{}\"", name)
+            },
+            DbError::DuplicateName(ref name) => {
+                write!(f, "Duplicate name \"{}\"", name)
+            },
+            DbError::NegativeValue{ref name, field} => {
+                write!(f, "Negative value of \"{}\" in \"{}\"", field, name)
+            },
+            DbError::TooBigValue{ref name, field, max} => {
+                write!(f, "Value of \"{}\" in \"{}\" is greater than {}", field, name, max)
+            },
+            DbError::BadDistances(ref name) => {
+                write!(f, "Min distance of \"{}\" is greater than its max distance", name)
+            },
+        }
+    }
+}
+
+impl error::Error for DbError {
+    fn description(&self) -> &str {
+        "bad db"
+    }
+}
+
+impl From<DeserializeError> for DbError {
+    fn from(err: DeserializeError) -> DbError {
+        DbError::Syntax(err)
+    }
+}
+
+impl From<DbError> for DeserializeError {
+    fn from(err: DbError) -> DeserializeError {
+        match err {
+            DbError::Syntax(err) => err,
+            err => DeserializeError::BadData(err.to_string()),
+        }
+    }
+}
+
+fn weapon_type_id_opt(weapon_types: &[WeaponType], name: &str)
+    -> Option<WeaponTypeId>
 {
     for (id, weapon_type) in weapon_types.iter().enumerate() {
         if weapon_type.name == name {
-            return WeaponTypeId{id: id as i32};
+            return Some(WeaponTypeId{id: id as i32});
         }
     }
-    panic!("No weapon type with name \"{}\"", name);
+    None
+}
+
+fn weapon_type_id(weapon_types: &[WeaponType], name: &str)
+    -> WeaponTypeId
+{
+    match weapon_type_id_opt(weapon_types, name) {
+        Some(id) => id,
+        None => panic!("No weapon type with name \"{}\"", name),
+    }
 }
 
-// TODO: read from json/toml config
-fn get_weapon_types() -> Vec<WeaponType> {
-    vec![
-        WeaponType {
-            name: "mortar".to_owned(),
-            damage: 6,
-            ap: 2,
-            accuracy: 5,
-            max_distance: Distance{n: 5},
-            max_air_distance: None,
-            min_distance: Distance{n: 1},
-            is_inderect: true,
-            reaction_fire: false,
-            smoke: Some(3),
-        },
-        WeaponType {
-            name: "super_heavy_tank_gun".to_owned(),
-            damage: 11,
-            ap: 11,
-            accuracy: 5,
-            max_distance: Distance{n: 6},
-            max_air_distance: None,
-            min_distance: Distance{n: 0},
-            is_inderect: false,
-            reaction_fire: true,
-            smoke: None,
-        },
-        WeaponType {
-            name: "heavy_tank_gun".to_owned(),
-            damage: 9,
-            ap: 9,
-            accuracy: 5,
-            max_distance: Distance{n: 5},
-            max_air_distance: None,
-            min_distance: Distance{n: 0},
-            is_inderect: false,
-            reaction_fire: true,
-            smoke: None,
-        },
-        WeaponType {
-            name: "medium_tank_gun".to_owned(),
-            damage: 7,
-            ap: 7,
-            accuracy: 5,
-            max_distance: Distance{n: 4},
-            max_air_distance: None,
-            min_distance: Distance{n: 0},
-            is_inderect: false,
-            reaction_fire: true,
-            smoke: None,
-        },
-        WeaponType {
-            name: "light_tank_gun".to_owned(),
-            damage: 6,
-            ap: 5,
-            accuracy: 5,
-            max_distance: Distance{n: 4},
-            max_air_distance: None,
-            min_distance: Distance{n: 0},
-            is_inderect: false,
-            reaction_fire: true,
-            smoke: None,
-        },
-        WeaponType {
-            name: "rifle".to_owned(),
-            damage: 2,
-            ap: 1,
-            accuracy: 5,
-            max_distance: Distance{n: 3},
-            max_air_distance: Some(Distance{n: 2}),
-            min_distance: Distance{n: 0},
-            is_inderect: false,
-            reaction_fire: true,
-            smoke: None,
-        },
-        WeaponType {
-            name: "submachine_gun".to_owned(),
-            damage: 3,
-            ap: 1,
-            accuracy: 4,
-            max_distance: Distance{n: 2},
-            max_air_distance: Some(Distance{n: 1}),
-            min_distance: Distance{n: 0},
-            is_inderect: false,
-            reaction_fire: true,
-            smoke: None,
-        },
-        WeaponType {
-            name: "machine_gun".to_owned(),
-            damage: 5,
-            ap: 2,
-            accuracy: 5,
-            max_distance: Distance{n: 3},
-            max_air_distance: Some(Distance{n: 2}),
-            min_distance: Distance{n: 0},
-            is_inderect: false,
-            reaction_fire: true,
-            smoke: None,
-        },
-    ]
+fn read_field<T: Deserialize>(r: &mut Reader, field: &str)
+    -> Result<T, DeserializeError>
+{
+    r.expect_tag(field)?;
+    r.read()
 }
 
-// TODO: read from json/toml config
-fn get_unit_types(weapon_types: &[WeaponType]) -> Vec<UnitType> {
-    vec![
-        UnitType {
-            name: "mammoth_tank".to_owned(),
-            size: 12,
-            count: 1,
-            armor: 13,
-            toughness: 9,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "super_heavy_tank_gun"),
-            move_points: MovePoints{n: 5},
-            attack_points: AttackPoints{n: 1},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 7},
-            cover_los_range: Distance{n: 0},
-            is_transporter: false,
-            is_big: true,
-            is_air: false,
-            is_infantry: false,
-            can_be_towed: false,
-            cost: ReinforcementPoints{n: 16},
-        },
-        UnitType {
-            name: "heavy_tank".to_owned(),
-            size: 8,
-            count: 1,
-            armor: 11,
-            toughness: 9,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "heavy_tank_gun"),
-            move_points: MovePoints{n: 7},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 7},
-            cover_los_range: Distance{n: 0},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: false,
-            can_be_towed: true,
-            cost: ReinforcementPoints{n: 10},
-        },
-        UnitType {
-            name: "medium_tank".to_owned(),
-            size: 7,
-            count: 1,
-            armor: 9,
-            toughness: 9,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "medium_tank_gun"),
-            move_points: MovePoints{n: 8},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 7},
-            cover_los_range: Distance{n: 0},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: false,
-            can_be_towed: true,
-            cost: ReinforcementPoints{n: 8},
-        },
-        UnitType {
-            name: "light_tank".to_owned(),
-            size: 6,
-            count: 1,
-            armor: 7,
-            toughness: 9,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "light_tank_gun"),
-            move_points: MovePoints{n: 10},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 7},
-            cover_los_range: Distance{n: 0},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: false,
-            can_be_towed: true,
-            cost: ReinforcementPoints{n: 6},
-        },
-        UnitType {
-            name: "light_spg".to_owned(),
-            size: 6,
-            count: 1,
-            armor: 5,
-            toughness: 9,
-            weapon_skill: 7,
-            weapon_type_id: weapon_type_id(weapon_types, "medium_tank_gun"),
-            move_points: MovePoints{n: 10},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 7},
-            cover_los_range: Distance{n: 0},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: false,
-            can_be_towed: true,
-            cost: ReinforcementPoints{n: 6},
-        },
-        UnitType {
-            name: "field_gun".to_owned(),
-            size: 6,
-            count: 1,
-            armor: 3,
-            toughness: 7,
-            weapon_skill: 7,
-            // TODO: "tank_gun" on field gun??
-            weapon_type_id: weapon_type_id(weapon_types, "medium_tank_gun"),
-            move_points: MovePoints{n: 7},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 7},
-            cover_los_range: Distance{n: 0},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: true,
-            can_be_towed: true,
-            cost: ReinforcementPoints{n: 5},
-        },
-        UnitType {
-            name: "jeep".to_owned(),
-            size: 5,
-            count: 1,
-            armor: 2,
-            toughness: 3,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "machine_gun"),
-            move_points: MovePoints{n: 12},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 8},
-            cover_los_range: Distance{n: 0},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: false,
-            can_be_towed: true,
-            cost: ReinforcementPoints{n: 4},
-        },
-        UnitType {
-            name: "truck".to_owned(),
-            size: 6,
-            count: 1,
-            armor: 2,
-            toughness: 3,
-            weapon_skill: 0,
-            weapon_type_id: weapon_type_id(weapon_types, "machine_gun"), // TODO: remove hack
-            move_points: MovePoints{n: 10},
-            attack_points: AttackPoints{n: 0},
-            reactive_attack_points: AttackPoints{n: 0},
-            los_range: Distance{n: 6},
-            cover_los_range: Distance{n: 0},
-            is_transporter: true,
-            is_big: false,
-            is_air: false,
-            is_infantry: false,
-            can_be_towed: true,
-            cost: ReinforcementPoints{n: 4},
-        },
-        UnitType {
-            name: "helicopter".to_owned(),
-            size: 9,
-            count: 1,
-            armor: 3,
-            toughness: 3,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "machine_gun"),
-            move_points: MovePoints{n: 10},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 8},
-            cover_los_range: Distance{n: 0},
-            is_transporter: false,
-            is_big: true,
-            is_air: true,
-            is_infantry: false,
-            can_be_towed: false,
-            cost: ReinforcementPoints{n: 10},
-        },
-        UnitType {
-            name: "soldier".to_owned(),
-            size: 4,
-            count: 4,
-            armor: 1,
-            toughness: 2,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "rifle"),
-            move_points: MovePoints{n: 9},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 6},
-            cover_los_range: Distance{n: 1},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: true,
-            can_be_towed: false,
-            cost: ReinforcementPoints{n: 2},
-        },
-        UnitType {
-            name: "smg".to_owned(),
-            size: 4,
-            count: 3,
-            armor: 1,
-            toughness: 2,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "submachine_gun"),
-            move_points: MovePoints{n: 9},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 6},
-            cover_los_range: Distance{n: 1},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: true,
-            can_be_towed: false,
-            cost: ReinforcementPoints{n: 2},
-        },
-        UnitType {
-            name: "scout".to_owned(),
-            size: 4,
-            count: 2,
-            armor: 1,
-            toughness: 2,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "rifle"),
-            move_points: MovePoints{n: 11},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 1},
-            los_range: Distance{n: 8},
-            cover_los_range: Distance{n: 2},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: true,
-            can_be_towed: false,
-            cost: ReinforcementPoints{n: 3},
-        },
-        UnitType {
-            name: "mortar".to_owned(),
-            size: 4,
-            count: 1,
-            armor: 1,
-            toughness: 2,
-            weapon_skill: 5,
-            weapon_type_id: weapon_type_id(weapon_types, "mortar"),
-            move_points: MovePoints{n: 7},
-            attack_points: AttackPoints{n: 2},
-            reactive_attack_points: AttackPoints{n: 0},
-            los_range: Distance{n: 6},
-            cover_los_range: Distance{n: 1},
-            is_transporter: false,
-            is_big: false,
-            is_air: false,
-            is_infantry: true,
-            can_be_towed: false,
-            cost: ReinforcementPoints{n: 4},
-        },
-    ]
+fn write_field<T: Serialize>(w: &mut Writer, field: &str, value: &T) {
+    w.new_line();
+    w.write_tag(field);
+    value.serialize(w);
+}
+
+fn check_non_negative(name: &str, field: &'static str, n: i32)
+    -> Result<(), DbError>
+{
+    if n < 0 {
+        Err(DbError::NegativeValue{name: name.to_owned(), field: field})
+    } else {
+        Ok(())
+    }
+}
+
+fn read_weapon_type(r: &mut Reader) -> Result<WeaponType, DbError> {
+    let weapon_type = WeaponType {
+        name: r.read()?,
+        damage: read_field(r, "damage")?,
+        ap: read_field(r, "ap")?,
+        accuracy: read_field(r, "accuracy")?,
+        max_distance: read_field(r, "max_distance")?,
+        max_air_distance: read_field(r, "max_air_distance")?,
+        min_distance: read_field(r, "min_distance")?,
+        is_inderect: read_field(r, "is_inderect")?,
+        reaction_fire: read_field(r, "reaction_fire")?,
+        smoke: read_field(r, "smoke")?,
+    };
+    let name = &weapon_type.name;
+    check_non_negative(name, "damage", weapon_type.damage)?;
+    check_non_negative(name, "ap", weapon_type.ap)?;
+    check_non_negative(name, "accuracy", weapon_type.accuracy)?;
+    check_non_negative(name, "max_distance", weapon_type.max_distance.n)?;
+    if let Some(max_air_distance) = weapon_type.max_air_distance {
+        check_non_negative(name, "max_air_distance", max_air_distance.n)?;
+    }
+    check_non_negative(name, "min_distance", weapon_type.min_distance.n)?;
+    if weapon_type.min_distance.n > weapon_type.max_distance.n {
+        return Err(DbError::BadDistances(name.clone()));
+    }
+    if let Some(smoke) = weapon_type.smoke {
+        check_non_negative(name, "smoke", smoke)?;
+        if smoke > MAX_SMOKE {
+            return Err(DbError::TooBigValue {
+                name: name.clone(),
+                field: "smoke",
+                max: MAX_SMOKE,
+            });
+        }
+    }
+    Ok(weapon_type)
+}
+
+fn write_weapon_type(w: &mut Writer, weapon_type: &WeaponType) {
+    w.write_tag("weapon_type");
+    weapon_type.name.serialize(w);
+    write_field(w, "damage", &weapon_type.damage);
+    write_field(w, "ap", &weapon_type.ap);
+    write_field(w, "accuracy", &weapon_type.accuracy);
+    write_field(w, "max_distance", &weapon_type.max_distance);
+    write_field(w, "max_air_distance", &weapon_type.max_air_distance);
+    write_field(w, "min_distance", &weapon_type.min_distance);
+    write_field(w, "is_inderect", &weapon_type.is_inderect);
+    write_field(w, "reaction_fire", &weapon_type.reaction_fire);
+    write_field(w, "smoke", &weapon_type.smoke);
+    w.new_line();
+}
+
+fn read_unit_type(r: &mut Reader, weapon_types: &[WeaponType])
+    -> Result<UnitType, DbError>
+{
+    let name: String = r.read()?;
+    let size = read_field(r, "size")?;
+    let count = read_field(r, "count")?;
+    let armor = read_field(r, "armor")?;
+    let toughness = read_field(r, "toughness")?;
+    let weapon_skill = read_field(r, "weapon_skill")?;
+    let weapon_name: String = read_field(r, "weapon_type")?;
+    let weapon_type_id = match weapon_type_id_opt(weapon_types, &weapon_name) {
+        Some(id) => id,
+        None => return Err(DbError::NoWeaponType(weapon_name)),
+    };
+    let unit_type = UnitType {
+        name: name,
+        size: size,
+        count: count,
+        armor: armor,
+        toughness: toughness,
+        weapon_skill: weapon_skill,
+        weapon_type_id: weapon_type_id,
+        move_points: read_field(r, "move_points")?,
+        attack_points: read_field(r, "attack_points")?,
+        reactive_attack_points: read_field(r, "reactive_attack_points")?,
+        los_range: read_field(r, "los_range")?,
+        cover_los_range: read_field(r, "cover_los_range")?,
+        is_transporter: read_field(r, "is_transporter")?,
+        is_big: read_field(r, "is_big")?,
+        is_air: read_field(r, "is_air")?,
+        is_infantry: read_field(r, "is_infantry")?,
+        can_be_towed: read_field(r, "can_be_towed")?,
+        cost: read_field(r, "cost")?,
+    };
+    let name = &unit_type.name;
+    check_non_negative(name, "size", unit_type.size)?;
+    check_non_negative(name, "count", unit_type.count)?;
+    check_non_negative(name, "armor", unit_type.armor)?;
+    check_non_negative(name, "toughness", unit_type.toughness)?;
+    check_non_negative(name, "weapon_skill", unit_type.weapon_skill)?;
+    check_non_negative(name, "move_points", unit_type.move_points.n)?;
+    check_non_negative(name, "attack_points", unit_type.attack_points.n)?;
+    check_non_negative(name, "reactive_attack_points", unit_type.reactive_attack_points.n)?;
+    check_non_negative(name, "los_range", unit_type.los_range.n)?;
+    check_non_negative(name, "cover_los_range", unit_type.cover_los_range.n)?;
+    check_non_negative(name, "cost", unit_type.cost.n)?;
+    Ok(unit_type)
+}
+
+fn write_unit_type(w: &mut Writer, unit_type: &UnitType, weapon_types: &[WeaponType]) {
+    let weapon_name = &weapon_types[unit_type.weapon_type_id.id as usize].name;
+    w.write_tag("unit_type");
+    unit_type.name.serialize(w);
+    write_field(w, "size", &unit_type.size);
+    write_field(w, "count", &unit_type.count);
+    write_field(w, "armor", &unit_type.armor);
+    write_field(w, "toughness", &unit_type.toughness);
+    write_field(w, "weapon_skill", &unit_type.weapon_skill);
+    write_field(w, "weapon_type", weapon_name);
+    write_field(w, "move_points", &unit_type.move_points);
+    write_field(w, "attack_points", &unit_type.attack_points);
+    write_field(w, "reactive_attack_points", &unit_type.reactive_attack_points);
+    write_field(w, "los_range", &unit_type.los_range);
+    write_field(w, "cover_los_range", &unit_type.cover_los_range);
+    write_field(w, "is_transporter", &unit_type.is_transporter);
+    write_field(w, "is_big", &unit_type.is_big);
+    write_field(w, "is_air", &unit_type.is_air);
+    write_field(w, "is_infantry", &unit_type.is_infantry);
+    write_field(w, "can_be_towed", &unit_type.can_be_towed);
+    write_field(w, "cost", &unit_type.cost);
+    w.new_line();
 }
 
 #[derive(Clone, Debug)]
@@ -396,14 +249,68 @@ impl Default for Db {
     }
 }
 
+impl FromStr for Db {
+    type Err = DbError;
+
+    /// Parses and validates a db in the format of `data/db.txt`
+    fn from_str(text: &str) -> Result<Db, DbError> {
+        let mut r = Reader::new(text);
+        let db = Db::load(&mut r)?;
+        if !r.is_finished() {
+            let token: String = r.read_tag()?;
+            return Err(DbError::Syntax(DeserializeError::BadToken(token)));
+        }
+        Ok(db)
+    }
+}
+
 impl Db {
     pub fn new() -> Db {
-        let weapon_types = get_weapon_types();
-        let unit_types = get_unit_types(&weapon_types);
-        Db {
+        match DEFAULT_DB.parse() {
+            Ok(db) => db,
+            Err(err) => panic!("Bad default db: {}", err),
+        }
+    }
+
+    /// Reads records until the `end` tag, so a db can be embedded into saves
+    pub fn load(r: &mut Reader) -> Result<Db, DbError> {
+        let mut weapon_types: Vec<WeaponType> = Vec::new();
+        let mut unit_types: Vec<UnitType> = Vec::new();
+        loop {
+            let tag = r.read_tag()?;
+            match tag.as_str() {
+                "weapon_type" => {
+                    let weapon_type = read_weapon_type(r)?;
+                    if weapon_type_id_opt(&weapon_types, &weapon_type.name).is_some() {
+                        return Err(DbError::DuplicateName(weapon_type.name));
+                    }
+                    weapon_types.push(weapon_type);
+                },
+                "unit_type" => {
+                    let unit_type = read_unit_type(r, &weapon_types)?;
+                    if unit_types.iter().any(|t| t.name == unit_type.name) {
+                        return Err(DbError::DuplicateName(unit_type.name));
+                    }
+                    unit_types.push(unit_type);
+                },
+                "end" => break,
+                _ => return Err(DbError::Syntax(DeserializeError::BadTag(tag))),
+            }
+        }
+        Ok(Db {
             weapon_types: weapon_types,
             unit_types: unit_types,
+        })
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        for weapon_type in &self.weapon_types {
+            write_weapon_type(w, weapon_type);
+        }
+        for unit_type in &self.unit_types {
+            write_unit_type(w, unit_type, &self.weapon_types);
         }
+        w.write_tag("end");
     }
 
     fn unit_type_id_opt(&self, name: &str) -> Option<UnitTypeId> {
@@ -438,3 +345,70 @@ impl Db {
         weapon_type_id(&self.weapon_types, name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serialize::{Writer};
+    use db::{Db, DbError, DEFAULT_DB, MAX_SMOKE};
+
+    fn db_to_string(db: &Db) -> String {
+        let mut writer = Writer::new();
+        db.save(&mut writer);
+        writer.into_string()
+    }
+
+    #[test]
+    fn test_save_load() {
+        let text = db_to_string(&Db::new());
+        let db = text.parse::<Db>().unwrap();
+        assert_eq!(db_to_string(&db), text);
+    }
+
+    #[test]
+    fn test_missing_weapon() {
+        let text = DEFAULT_DB.replacen(
+            "weapon_type \"mortar\"", "weapon_type \"old_mortar\"", 1);
+        let err = text.parse::<Db>().unwrap_err();
+        assert_eq!(err, DbError::NoWeaponType("mortar".to_owned()));
+    }
+
+    #[test]
+    fn test_negative_cost() {
+        let mut db = Db::new();
+        db.unit_types[0].cost.n = -1;
+        let err = db_to_string(&db).parse::<Db>().unwrap_err();
+        assert_eq!(err, DbError::NegativeValue {
+            name: "mammoth_tank".to_owned(),
+            field: "cost",
+        });
+    }
+
+    #[test]
+    fn test_too_much_smoke() {
+        let mut db = Db::new();
+        db.weapon_types[0].smoke = Some(MAX_SMOKE + 1);
+        let err = db_to_string(&db).parse::<Db>().unwrap_err();
+        assert_eq!(err, DbError::TooBigValue {
+            name: db.weapon_types[0].name.clone(),
+            field: "smoke",
+            max: MAX_SMOKE,
+        });
+    }
+
+    #[test]
+    fn test_bad_distances() {
+        let mut db = Db::new();
+        db.weapon_types[0].min_distance.n = db.weapon_types[0].max_distance.n + 1;
+        let err = db_to_string(&db).parse::<Db>().unwrap_err();
+        assert_eq!(err, DbError::BadDistances(db.weapon_types[0].name.clone()));
+    }
+
+    #[test]
+    fn test_duplicate_name() {
+        let mut db = Db::new();
+        let unit_type = db.unit_types[1].clone();
+        db.unit_types.push(unit_type);
+        let err = db_to_string(&db).parse::<Db>().unwrap_err();
+        assert_eq!(err, DbError::DuplicateName("heavy_tank".to_owned()));
+    }
+}
@@ -1,9 +1,11 @@
+use std::time::{Duration};
 use unit::{Unit, UnitId, UnitTypeId};
 use position::{ExactPos, MapPos};
 use player::{PlayerId};
 use sector::{SectorId};
 use object::{ObjectId};
 use movement::{MovePoints};
+use orders::{Order};
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum FireMode {
@@ -35,6 +37,15 @@ pub enum Command {
     Detach{transporter_id: UnitId, pos: ExactPos},
     SetReactionFireMode{unit_id: UnitId, mode: ReactionFireMode},
     Smoke{unit_id: UnitId, pos: MapPos},
+
+    /// Replaces the unit's orders in simultaneous turns
+    SetOrders{unit_id: UnitId, orders: Vec<Order>},
+
+    /// Runs the clock of the current human turn, see `Core::pass_time`
+    PassTime{time: Duration},
+
+    /// Ends the turn of the human whose time is out, see `Core::is_time_out`
+    TimeOut,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -125,4 +136,14 @@ pub enum CoreEvent {
     RemoveSmoke {
         id: ObjectId,
     },
+    /// The clock of the human who plays now, see `Core::pass_time`
+    TimeLeft {
+        player_id: PlayerId,
+
+        /// Until the turn ends with `Command::TimeOut`
+        turn: Duration,
+
+        /// For the rest of the game, if it's limited
+        game: Option<Duration>,
+    },
 }
@@ -1,4 +1,4 @@
-use std::collections::{HashSet};
+use std::collections::{BTreeSet, BTreeMap};
 use game_state::{State};
 use fow::{Fow};
 use unit::{Unit, UnitId};
@@ -6,7 +6,7 @@ use event::{CoreEvent, MoveMode, AttackInfo};
 use player::{PlayerId};
 use movement::{MovePoints};
 
-fn filtered_unit(unit: &Unit) -> Unit {
+pub fn filtered_unit(unit: &Unit) -> Unit {
     Unit {
         move_points: None,
         attack_points: None,
@@ -16,14 +16,34 @@ fn filtered_unit(unit: &Unit) -> Unit {
     }
 }
 
+/// Own units and the visible enemies, enemies are filtered
+/// the same way as in `ShowUnit` events
+pub fn get_known_units(
+    state: &State,
+    player_id: PlayerId,
+    visible_enemies: &BTreeSet<UnitId>,
+) -> BTreeMap<UnitId, Unit> {
+    let mut units = BTreeMap::new();
+    for (&id, unit) in state.units() {
+        if unit.player_id == player_id {
+            units.insert(id, unit.clone());
+        } else if visible_enemies.contains(&id) {
+            units.insert(id, filtered_unit(unit));
+        }
+    }
+    units
+}
+
 pub fn get_visible_enemies(
     state: &State,
     fow: &Fow,
     player_id: PlayerId,
-) -> HashSet<UnitId> {
-    let mut visible_enemies = HashSet::new();
+) -> BTreeSet<UnitId> {
+    let mut visible_enemies = BTreeSet::new();
     for (&id, unit) in state.units() {
+        // Passengers are hidden inside of their transporters
         if unit.player_id != player_id
+            && !unit.is_loaded
             && fow.is_visible(unit)
         {
             visible_enemies.insert(id);
@@ -34,9 +54,9 @@ pub fn get_visible_enemies(
 
 pub fn show_or_hide_passive_enemies(
     state: &State,
-    active_unit_ids: &HashSet<UnitId>,
-    old: &HashSet<UnitId>,
-    new: &HashSet<UnitId>,
+    active_unit_ids: &BTreeSet<UnitId>,
+    old: &BTreeSet<UnitId>,
+    new: &BTreeSet<UnitId>,
 ) -> Vec<CoreEvent> {
     let mut events = Vec::new();
     let located_units = new.difference(old);
@@ -64,9 +84,9 @@ pub fn filter_events(
     player_id: PlayerId,
     fow: &Fow,
     event: &CoreEvent,
-) -> (Vec<CoreEvent>, HashSet<UnitId>) {
+) -> (Vec<CoreEvent>, BTreeSet<UnitId>) {
     assert!(!state.is_partial());
-    let mut active_unit_ids = HashSet::new();
+    let mut active_unit_ids = BTreeSet::new();
     let mut events = vec![];
     match *event {
         CoreEvent::Move{unit_id, from, to, ..} => {
@@ -146,37 +166,19 @@ pub fn filter_events(
                 events.push(CoreEvent::ShowUnit {
                     unit_info: filtered_unit(unit_info),
                 });
+                active_unit_ids.insert(unit_info.id);
             }
         },
         CoreEvent::ShowUnit{..} |
         CoreEvent::HideUnit{..} => panic!(),
-        CoreEvent::LoadUnit{passenger_id, from, to, transporter_id} => {
+        CoreEvent::LoadUnit{passenger_id, from, ..} => {
             let passenger = state.unit(passenger_id);
-            let transporter = state.unit(transporter_id.unwrap());
-            let is_transporter_vis = fow.is_visible(transporter);
-            let is_passenger_vis = fow.is_visible_at(passenger, from);
-            if passenger.player_id == player_id {
+            if fow.is_vision_shared_with(passenger.player_id) {
                 events.push(event.clone());
-            } else if is_passenger_vis || is_transporter_vis {
-                if !fow.is_visible_at(passenger, from) {
-                    events.push(CoreEvent::ShowUnit {
-                        unit_info: Unit {
-                            pos: from,
-                            .. filtered_unit(passenger)
-                        },
-                    });
-                }
-                let filtered_transporter_id = if is_transporter_vis {
-                    transporter_id
-                } else {
-                    None
-                };
-                events.push(CoreEvent::LoadUnit {
-                    transporter_id: filtered_transporter_id,
-                    passenger_id: passenger_id,
-                    from: from,
-                    to: to,
-                });
+                active_unit_ids.insert(passenger_id);
+            } else if fow.is_visible_at(passenger, from) {
+                // Enemies don't know who is inside of the transporter
+                events.push(CoreEvent::HideUnit{unit_id: passenger_id});
                 active_unit_ids.insert(passenger_id);
             }
         },
@@ -184,12 +186,10 @@ pub fn filter_events(
             active_unit_ids.insert(unit_info.id);
             let passenger = state.unit(unit_info.id);
             let transporter = state.unit(transporter_id.unwrap());
-            let is_transporter_vis = fow.is_visible_at(transporter, from);
-            let is_passenger_vis = fow.is_visible_at(passenger, to);
-            if passenger.player_id == player_id {
+            if fow.is_vision_shared_with(passenger.player_id) {
                 events.push(event.clone());
-            } else if is_passenger_vis || is_transporter_vis {
-                let filtered_transporter_id = if is_transporter_vis {
+            } else if fow.is_visible_at(passenger, to) {
+                let filtered_transporter_id = if fow.is_visible_at(transporter, from) {
                     transporter_id
                 } else {
                     None
@@ -197,20 +197,12 @@ pub fn filter_events(
                 events.push(CoreEvent::UnloadUnit {
                     transporter_id: filtered_transporter_id,
                     unit_info: Unit {
-                        move_points: None,
-                        attack_points: None,
-                        reactive_attack_points: None,
-                        passenger_id: None,
-                        .. unit_info.clone()
+                        is_loaded: false,
+                        .. filtered_unit(unit_info)
                     },
                     from: from,
                     to: to,
                 });
-                if !is_passenger_vis {
-                    events.push(CoreEvent::HideUnit {
-                        unit_id: passenger.id,
-                    });
-                }
             }
         },
         CoreEvent::Attach{transporter_id, attached_unit_id, from, to} => {
@@ -302,6 +294,7 @@ pub fn filter_events(
         CoreEvent::EndTurn{..} |
         CoreEvent::RemoveSmoke{..} |
         CoreEvent::VictoryPoint{..} |
+        CoreEvent::TimeLeft{..} |
         CoreEvent::SectorOwnerChanged{..} => {
             events.push(event.clone());
         },
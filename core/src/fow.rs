@@ -1,4 +1,5 @@
 use std::default::{Default};
+use std::collections::{BTreeSet};
 use std::rc::{Rc};
 use game_state::{State};
 use map::{Map, Terrain, distance};
@@ -9,6 +10,7 @@ use position::{MapPos, ExactPos, SlotId};
 use event::{CoreEvent};
 use player::{PlayerId};
 use object::{ObjectClass};
+use serialize::{Serialize, Deserialize, Writer, Reader, DeserializeError};
 
 #[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
 pub enum TileVisibility {
@@ -22,6 +24,28 @@ impl Default for TileVisibility {
     fn default() -> Self { TileVisibility::No }
 }
 
+impl Serialize for TileVisibility {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_tag(match *self {
+            TileVisibility::No => "0",
+            TileVisibility::Normal => "1",
+            TileVisibility::Excellent => "2",
+        });
+    }
+}
+
+impl Deserialize for TileVisibility {
+    fn deserialize(r: &mut Reader) -> Result<TileVisibility, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "0" => Ok(TileVisibility::No),
+            "1" => Ok(TileVisibility::Normal),
+            "2" => Ok(TileVisibility::Excellent),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
 fn calc_visibility(
     state: &State,
     unit_type: &UnitType,
@@ -58,9 +82,24 @@ pub struct Fow {
     map: Map<TileVisibility>,
     air_map: Map<TileVisibility>,
     player_id: PlayerId,
+
+    /// Players whose units give vision to this Fow, includes its own player
+    vision_ids: BTreeSet<PlayerId>,
+
     db: Rc<Db>,
 }
 
+fn get_vision_ids(state: &State, player_id: PlayerId) -> BTreeSet<PlayerId> {
+    let mut ids = BTreeSet::new();
+    for id in 0 .. state.players_count() {
+        let id = PlayerId{id: id};
+        if id == player_id || (state.is_vision_shared() && state.is_ally(id, player_id)) {
+            ids.insert(id);
+        }
+    }
+    ids
+}
+
 impl Fow {
     pub fn new(state: &State, player_id: PlayerId) -> Fow {
         let db = state.db().clone();
@@ -69,12 +108,30 @@ impl Fow {
             map: Map::new(map_size),
             air_map: Map::new(map_size),
             player_id: player_id,
+            vision_ids: get_vision_ids(state, player_id),
             db: db,
         };
         fow.reset(state);
         fow
     }
 
+    pub fn save(&self, w: &mut Writer) {
+        self.player_id.serialize(w);
+        self.vision_ids.serialize(w);
+        self.map.serialize(w);
+        self.air_map.serialize(w);
+    }
+
+    pub fn load(r: &mut Reader, db: Rc<Db>) -> Result<Fow, DeserializeError> {
+        Ok(Fow {
+            player_id: r.read()?,
+            vision_ids: r.read()?,
+            map: r.read()?,
+            air_map: r.read()?,
+            db: db,
+        })
+    }
+
     pub fn is_ground_tile_visible(&self, pos: MapPos) -> bool {
         match *self.map.tile(pos) {
             TileVisibility::Excellent |
@@ -83,12 +140,19 @@ impl Fow {
         }
     }
 
+    /// Units of this player are always visible and nothing about them is hidden
+    pub fn is_vision_shared_with(&self, player_id: PlayerId) -> bool {
+        self.vision_ids.contains(&player_id)
+    }
+
     pub fn is_visible(&self, unit: &Unit) -> bool {
         self.is_visible_at(unit, unit.pos)
     }
 
     pub fn is_visible_at(&self, unit: &Unit, pos: ExactPos) -> bool {
-        if pos.slot_id == SlotId::Air {
+        if self.vision_ids.contains(&unit.player_id) {
+            true
+        } else if pos.slot_id == SlotId::Air {
             *self.air_map.tile(pos.map_pos) != TileVisibility::No
         } else {
             let unit_type = self.db.unit_type(unit.type_id);
@@ -133,14 +197,16 @@ impl Fow {
     fn reset(&mut self, state: &State) {
         self.clear();
         for (_, unit) in state.units() {
-            if unit.player_id == self.player_id && unit.is_alive {
+            if self.vision_ids.contains(&unit.player_id) && unit.is_alive {
                 self.fov_unit(state, unit);
             }
         }
         for object in state.objects().values() {
-            if object.class != ObjectClass::ReinforcementSector
-                || object.owner_id != Some(self.player_id)
-            {
+            let is_seen = match object.owner_id {
+                Some(id) => self.vision_ids.contains(&id),
+                None => false,
+            };
+            if object.class != ObjectClass::ReinforcementSector || !is_seen {
                 continue;
             }
             *self.map.tile_mut(object.pos) = TileVisibility::Excellent;
@@ -156,7 +222,7 @@ impl Fow {
         match *event {
             CoreEvent::Move{unit_id, ..} => {
                 let unit = state.unit(unit_id);
-                if unit.player_id == self.player_id {
+                if self.vision_ids.contains(&unit.player_id) {
                     self.fov_unit(state, unit);
                 }
             },
@@ -167,7 +233,7 @@ impl Fow {
             },
             CoreEvent::CreateUnit{ref unit_info} => {
                 let unit = state.unit(unit_info.id);
-                if self.player_id == unit_info.player_id {
+                if self.vision_ids.contains(&unit_info.player_id) {
                     self.fov_unit(state, unit);
                 }
             },
@@ -176,23 +242,36 @@ impl Fow {
                     if !attack_info.is_ambush {
                         let pos = state.unit(attacker_id).pos;
                         // TODO: do not give away all units in this tile!
+                        if pos.slot_id == SlotId::Air {
+                            *self.air_map.tile_mut(pos) = TileVisibility::Excellent;
+                        } else {
+                            *self.map.tile_mut(pos) = TileVisibility::Excellent;
+                        }
+                    }
+                }
+            },
+            CoreEvent::Reveal{ref unit_info} => {
+                if !self.vision_ids.contains(&unit_info.player_id) {
+                    let pos = unit_info.pos;
+                    if pos.slot_id == SlotId::Air {
+                        *self.air_map.tile_mut(pos) = TileVisibility::Excellent;
+                    } else {
                         *self.map.tile_mut(pos) = TileVisibility::Excellent;
                     }
                 }
             },
             CoreEvent::UnloadUnit{ref unit_info, ..} => {
-                if self.player_id == unit_info.player_id {
+                if self.vision_ids.contains(&unit_info.player_id) {
                     let unit = state.unit(unit_info.id);
                     self.fov_unit(state, unit);
                 }
             },
             CoreEvent::Detach{transporter_id, ..} => {
                 let transporter = state.unit(transporter_id);
-                if self.player_id == transporter.player_id {
+                if self.vision_ids.contains(&transporter.player_id) {
                     self.fov_unit(state, transporter);
                 }
             },
-            CoreEvent::Reveal{..} |
             CoreEvent::ShowUnit{..} |
             CoreEvent::HideUnit{..} |
             CoreEvent::LoadUnit{..} |
@@ -201,6 +280,7 @@ impl Fow {
             CoreEvent::SectorOwnerChanged{..} |
             CoreEvent::Smoke{..} |
             CoreEvent::RemoveSmoke{..} |
+            CoreEvent::TimeLeft{..} |
             CoreEvent::VictoryPoint{..} => {},
         }
     }
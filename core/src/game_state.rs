@@ -1,21 +1,19 @@
-use std::collections::hash_map::{self, HashMap};
-use std::collections::{HashSet};
+use std::collections::btree_map::{self, BTreeMap};
+use std::collections::{HashMap, HashSet};
 use std::rc::{Rc};
-use cgmath::{Vector2};
-use types::{Size2};
 use unit::{Unit, UnitId};
 use db::{Db};
 use map::{Map, Terrain};
-use dir::{Dir};
 use fow::{Fow};
 use sector::{Sector, SectorId};
-use position::{self, MapPos, ExactPos, SlotId};
+use position::{MapPos, ExactPos, SlotId};
 use event::{CoreEvent, FireMode};
-use player::{PlayerId};
+use player::{PlayerId, PlayerClass, TeamId};
 use object::{ObjectId, Object, ObjectClass};
 use movement::{MovePoints};
 use attack::{AttackPoints};
-use options::{Options};
+use options::{Options, Difficulty, TurnMode};
+use serialize::{Serialize, Writer, Reader, DeserializeError};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ReinforcementPoints{pub n: i32}
@@ -23,14 +21,29 @@ pub struct ReinforcementPoints{pub n: i32}
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Score{pub n: i32}
 
+/// Reinforcement points that a player gets at the start and after every turn
+const REINFORCEMENT_INCOME: i32 = 10;
+
+/// Extra income of `Difficulty::Hard` AI players
+const HARD_AI_INCOME_BONUS: i32 = 5;
+
+fn player_income(options: &Options, player_id: PlayerId) -> ReinforcementPoints {
+    let class = options.players[player_id.id as usize];
+    if class == PlayerClass::Ai && options.difficulty == Difficulty::Hard {
+        ReinforcementPoints{n: REINFORCEMENT_INCOME + HARD_AI_INCOME_BONUS}
+    } else {
+        ReinforcementPoints{n: REINFORCEMENT_INCOME}
+    }
+}
+
 #[derive(Clone)]
 pub struct ObjectsAtIter<'a> {
-    it: hash_map::Iter<'a, ObjectId, Object>,
+    it: btree_map::Iter<'a, ObjectId, Object>,
     pos: MapPos,
 }
 
 impl<'a> ObjectsAtIter<'a> {
-    pub fn new(objects: &HashMap<ObjectId, Object>, pos: MapPos) -> ObjectsAtIter {
+    pub fn new(objects: &BTreeMap<ObjectId, Object>, pos: MapPos) -> ObjectsAtIter {
         ObjectsAtIter{it: objects.iter(), pos: pos}
     }
 }
@@ -71,7 +84,7 @@ impl<'a> Iterator for UnitsAtIter<'a> {
 
 #[derive(Clone)]
 pub struct UnitIter<'a> {
-    iter: hash_map::Iter<'a, UnitId, Unit>,
+    iter: btree_map::Iter<'a, UnitId, Unit>,
     state: &'a State,
 }
 
@@ -91,14 +104,28 @@ impl<'a> Iterator for UnitIter<'a> {
 
 #[derive(Clone, Debug)]
 pub struct State {
-    units: HashMap<UnitId, Unit>,
-    objects: HashMap<ObjectId, Object>,
+    units: BTreeMap<UnitId, Unit>,
+    objects: BTreeMap<ObjectId, Object>,
     map: Map<Terrain>,
-    sectors: HashMap<SectorId, Sector>,
+    sectors: BTreeMap<SectorId, Sector>,
     score: HashMap<PlayerId, Score>,
     target_score: Score,
+
+    /// Soldiers and vehicles lost by each player
+    casualties: HashMap<PlayerId, i32>,
+
     reinforcement_points: HashMap<PlayerId, ReinforcementPoints>,
+
+    /// Reinforcement points that each player gets after their turn
+    reinforcement_income: HashMap<PlayerId, ReinforcementPoints>,
+
     players_count: i32,
+
+    /// Team of each player, indexed by player id
+    teams: Vec<TeamId>,
+
+    shared_vision: bool,
+    turn_mode: TurnMode,
     db: Rc<Db>,
 
     // If this field is None then the State is considered "Full State"
@@ -115,22 +142,34 @@ pub struct State {
 }
 
 fn basic_state(db: Rc<Db>, options: &Options) -> State {
+    let players_count = options.players.len() as i32;
     let mut score = HashMap::new();
-    score.insert(PlayerId{id: 0}, Score{n: 0});
-    score.insert(PlayerId{id: 1}, Score{n: 0});
     let mut reinforcement_points = HashMap::new();
-    reinforcement_points.insert(PlayerId{id: 0}, ReinforcementPoints{n: 10});
-    reinforcement_points.insert(PlayerId{id: 1}, ReinforcementPoints{n: 10});
-    let (map, objects, sectors, target_score) = load_map(&options.map_name);
+    let mut reinforcement_income = HashMap::new();
+    let mut casualties = HashMap::new();
+    for id in 0 .. players_count {
+        let player_id = PlayerId{id: id};
+        let income = player_income(options, player_id);
+        score.insert(player_id, Score{n: 0});
+        casualties.insert(player_id, 0);
+        reinforcement_points.insert(player_id, income);
+        reinforcement_income.insert(player_id, income);
+    }
+    let scenario = options.scenario.clone();
     State {
-        units: HashMap::new(),
-        objects: objects,
-        map: map,
-        sectors: sectors,
+        units: BTreeMap::new(),
+        objects: scenario.objects,
+        map: scenario.map,
+        sectors: scenario.sectors,
         score: score,
-        target_score: target_score,
+        target_score: scenario.target_score,
+        casualties: casualties,
         reinforcement_points: reinforcement_points,
-        players_count: options.players_count,
+        reinforcement_income: reinforcement_income,
+        players_count: players_count,
+        teams: options.teams.clone(),
+        shared_vision: options.shared_vision,
+        turn_mode: options.turn_mode,
         db: db,
         fow: None,
         shown_unit_ids: HashSet::new(),
@@ -159,10 +198,107 @@ impl State {
         self.fow.take().unwrap()
     }
 
+    /// Partial copy of the full state for the player that has only
+    /// the given units. Other players' reinforcement points are secret.
+    pub fn to_snapshot(
+        &self,
+        player_id: PlayerId,
+        fow: Fow,
+        units: BTreeMap<UnitId, Unit>,
+    ) -> State {
+        assert!(!self.is_partial());
+        let reinforcement_points = self.reinforcement_points.iter()
+            .map(|(&id, &points)| {
+                let points = if id == player_id { points } else { ReinforcementPoints{n: 0} };
+                (id, points)
+            })
+            .collect();
+        State {
+            units: units,
+            reinforcement_points: reinforcement_points,
+            fow: Some(fow),
+            shown_unit_ids: HashSet::new(),
+            .. self.clone()
+        }
+    }
+
     pub fn is_partial(&self) -> bool {
         self.fow.is_some()
     }
 
+    pub fn save(&self, w: &mut Writer) {
+        w.write_tag("state");
+        self.players_count.serialize(w);
+        self.teams.serialize(w);
+        self.shared_vision.serialize(w);
+        self.turn_mode.serialize(w);
+        self.target_score.serialize(w);
+        self.score.serialize(w);
+        self.casualties.serialize(w);
+        self.reinforcement_points.serialize(w);
+        self.reinforcement_income.serialize(w);
+        w.new_line();
+        self.map.serialize(w);
+        w.new_line();
+        self.sectors.serialize(w);
+        w.new_line();
+        self.objects.serialize(w);
+        w.new_line();
+        self.units.serialize(w);
+        w.new_line();
+        self.shown_unit_ids.serialize(w);
+        w.new_line();
+        match self.fow {
+            Some(ref fow) => {
+                w.write_tag("partial");
+                fow.save(w);
+            },
+            None => w.write_tag("full"),
+        }
+    }
+
+    pub fn load(r: &mut Reader, db: Rc<Db>) -> Result<State, DeserializeError> {
+        r.expect_tag("state")?;
+        let players_count = r.read()?;
+        let teams = r.read()?;
+        let shared_vision = r.read()?;
+        let turn_mode = r.read()?;
+        let target_score = r.read()?;
+        let score = r.read()?;
+        let casualties = r.read()?;
+        let reinforcement_points = r.read()?;
+        let reinforcement_income = r.read()?;
+        let map = r.read()?;
+        let sectors = r.read()?;
+        let objects = r.read()?;
+        let units = r.read()?;
+        let shown_unit_ids = r.read()?;
+        let tag = r.read_tag()?;
+        let fow = match tag.as_str() {
+            "partial" => Some(Fow::load(r, db.clone())?),
+            "full" => None,
+            _ => return Err(DeserializeError::BadTag(tag)),
+        };
+        Ok(State {
+            units: units,
+            objects: objects,
+            map: map,
+            sectors: sectors,
+            score: score,
+            target_score: target_score,
+            casualties: casualties,
+            reinforcement_points: reinforcement_points,
+            reinforcement_income: reinforcement_income,
+            players_count: players_count,
+            teams: teams,
+            shared_vision: shared_vision,
+            turn_mode: turn_mode,
+            db: db,
+            fow: fow,
+            shown_unit_ids: shown_unit_ids,
+        })
+    }
+
     pub fn db(&self) -> &Rc<Db> {
         &self.db
     }
@@ -171,6 +307,53 @@ impl State {
         self.target_score
     }
 
+    pub fn players_count(&self) -> i32 {
+        self.players_count
+    }
+
+    pub fn team_id(&self, player_id: PlayerId) -> TeamId {
+        self.teams[player_id.id as usize]
+    }
+
+    /// A player is always its own ally
+    pub fn is_ally(&self, a: PlayerId, b: PlayerId) -> bool {
+        self.team_id(a) == self.team_id(b)
+    }
+
+    pub fn is_vision_shared(&self) -> bool {
+        self.shared_vision
+    }
+
+    pub fn turn_mode(&self) -> TurnMode {
+        self.turn_mode
+    }
+
+    /// Team that has got `target_score` victory points, if any
+    pub fn winner_team_id(&self) -> Option<TeamId> {
+        for &player_id in self.score.keys() {
+            let team_id = self.team_id(player_id);
+            if self.team_score(team_id).n >= self.target_score.n {
+                return Some(team_id);
+            }
+        }
+        None
+    }
+
+    pub fn casualties(&self) -> &HashMap<PlayerId, i32> {
+        &self.casualties
+    }
+
+    /// Victory points of all the team's players
+    pub fn team_score(&self, team_id: TeamId) -> Score {
+        let mut score = Score{n: 0};
+        for (&player_id, player_score) in &self.score {
+            if self.team_id(player_id) == team_id {
+                score.n += player_score.n;
+            }
+        }
+        score
+    }
+
     /// Converts active ap (attack points) to reactive
     fn convert_ap(&mut self, player_id: PlayerId) {
         for unit in self.units.values_mut() {
@@ -242,6 +425,20 @@ impl State {
         })
     }
 
+    /// Also finds the units that are already out of sight
+    /// but whose `HideUnit` events are not applied yet
+    pub fn unit_info_opt(&self, id: UnitId) -> Option<&Unit> {
+        self.units.get(&id)
+    }
+
+    /// Could the player see the unit at this position, always true for full states
+    pub fn is_unit_visible_at(&self, unit: &Unit, pos: ExactPos) -> bool {
+        match self.fow {
+            Some(ref fow) => fow.is_visible_at(unit, pos),
+            None => true,
+        }
+    }
+
     pub fn unit(&self, id: UnitId) -> &Unit {
         self.unit_opt(id).unwrap()
     }
@@ -267,7 +464,7 @@ impl State {
         self.unit_at_opt(pos).unwrap()
     }
 
-    pub fn objects(&self) -> &HashMap<ObjectId, Object> {
+    pub fn objects(&self) -> &BTreeMap<ObjectId, Object> {
         &self.objects
     }
 
@@ -275,7 +472,7 @@ impl State {
         &self.map
     }
 
-    pub fn sectors(&self) -> &HashMap<SectorId, Sector> {
+    pub fn sectors(&self) -> &BTreeMap<SectorId, Sector> {
         &self.sectors
     }
 
@@ -287,6 +484,10 @@ impl State {
         &self.reinforcement_points
     }
 
+    pub fn reinforcement_income(&self) -> &HashMap<PlayerId, ReinforcementPoints> {
+        &self.reinforcement_income
+    }
+
     pub fn is_ground_tile_visible(&self, pos: MapPos) -> bool {
         if let Some(ref fow) = self.fow {
             fow.is_ground_tile_visible(pos)
@@ -319,12 +520,17 @@ impl State {
             CoreEvent::EndTurn{new_id, old_id} => {
                 self.shown_unit_ids.clear();
                 {
+                    let income = self.reinforcement_income[&old_id];
                     let reinforcement_points = self.reinforcement_points
                         .get_mut(&old_id).unwrap();
-                    reinforcement_points.n += 10;
+                    reinforcement_points.n += income.n;
                 }
                 self.refresh_units(new_id);
-                self.convert_ap(old_id);
+                // Planned attacks need their attack points
+                // until the orders are carried out
+                if self.turn_mode == TurnMode::Alternate {
+                    self.convert_ap(old_id);
+                }
                 for object in self.objects.values_mut() {
                     if let Some(ref mut timer) = object.timer {
                         *timer -= 1;
@@ -349,6 +555,7 @@ impl State {
                         .expect("Can`t find defender");
                     unit.count -= attack_info.killed;
                     unit.morale -= attack_info.suppression;
+                    *self.casualties.get_mut(&unit.player_id).unwrap() += attack_info.killed;
                     if attack_info.remove_move_points {
                         if let Some(ref mut move_points) = unit.move_points {
                             move_points.n = 0;
@@ -511,6 +718,7 @@ impl State {
             CoreEvent::RemoveSmoke{id} => {
                 self.objects.remove(&id);
             },
+            CoreEvent::TimeLeft{..} => {},
         }
         if self.fow.is_some() {
             let mut fow = self.to_full();
@@ -519,355 +727,3 @@ impl State {
         }
     }
 }
-
-// TODO: create trees, buildings and roads like units - using event system
-fn add_object(objects: &mut HashMap<ObjectId, Object>, object: Object) {
-    let id = ObjectId{id: objects.len() as i32 + 1};
-    objects.insert(id, object);
-}
-
-fn add_road(objects: &mut HashMap<ObjectId, Object>, path: &[MapPos]) {
-    for window in path.windows(2) {
-        let from = window[0];
-        let to = window[1];
-        let dir = Dir::get_dir_from_to(from, to);
-        let object = Object {
-            class: ObjectClass::Road,
-            pos: ExactPos {
-                map_pos: from,
-                slot_id: SlotId::TwoTiles(dir),
-            },
-            timer: None,
-            owner_id: None,
-        };
-        add_object(objects, object);
-    }
-}
-
-fn add_reinforcement_sector(
-    objects: &mut HashMap<ObjectId, Object>,
-    pos: MapPos,
-    owner_id: Option<PlayerId>,
-) {
-    let object = Object {
-        class: ObjectClass::ReinforcementSector,
-        pos: ExactPos {
-            map_pos: pos,
-            slot_id: SlotId::WholeTile,
-        },
-        timer: None,
-        owner_id: owner_id,
-    };
-    add_object(objects, object);
-}
-
-fn add_buildings(
-    map: &mut Map<Terrain>,
-    objects: &mut HashMap<ObjectId, Object>,
-    pos: MapPos,
-    count: i32,
-) {
-    *map.tile_mut(pos) = Terrain::City;
-    for _ in 0 .. count {
-        let slot_id = position::get_free_slot_for_building(map, objects, pos).unwrap();
-        let obj_pos = ExactPos{map_pos: pos, slot_id: slot_id};
-        let object = Object {
-            class: ObjectClass::Building,
-            pos: obj_pos,
-            timer: None,
-            owner_id: None,
-        };
-        add_object(objects, object);
-    }
-}
-
-fn add_big_building(
-    map: &mut Map<Terrain>,
-    objects: &mut HashMap<ObjectId, Object>,
-    pos: MapPos,
-) {
-    *map.tile_mut(pos) = Terrain::City;
-    let object = Object {
-        class: ObjectClass::Building,
-        pos: ExactPos {
-            map_pos: pos,
-            slot_id: SlotId::WholeTile,
-        },
-        timer: None,
-        owner_id: None,
-    };
-    add_object(objects, object);
-}
-
-type MapInfo = (
-    Map<Terrain>,
-    HashMap<ObjectId, Object>,
-    HashMap<SectorId, Sector>,
-    Score,
-);
-
-// TODO: read from scenario.json?
-fn load_map(map_name: &str) -> MapInfo {
-    match map_name {
-        "map01" => load_map_01(),
-        "map02" => load_map_02(),
-        "map03" => load_map_03(),
-        "map04" => load_map_04(),
-        "map05" => load_map_05(),
-        "map_fov_bug_test" => load_map_fov_bug_test(),
-        _ => unimplemented!(),
-    }
-}
-
-fn load_map_01() -> MapInfo {
-    let target_score = Score{n: 7};
-    let map_size = Size2{w: 10, h: 12};
-    let mut objects = HashMap::new();
-    let mut map = Map::new(map_size);
-    let mut sectors = HashMap::new();
-    for &((x, y), terrain) in &[
-        ((6, 7), Terrain::Water),
-        ((5, 8), Terrain::Water),
-        ((5, 9), Terrain::Water),
-        ((4, 10), Terrain::Water),
-        ((5, 11), Terrain::Water),
-        ((1, 2), Terrain::Trees),
-        ((1, 6), Terrain::Trees),
-        ((2, 6), Terrain::Trees),
-        ((4, 3), Terrain::Trees),
-        ((4, 4), Terrain::Trees),
-        ((4, 5), Terrain::Trees),
-        ((5, 1), Terrain::Trees),
-        ((5, 10), Terrain::Trees),
-        ((6, 0), Terrain::Trees),
-        ((6, 1), Terrain::Trees),
-        ((6, 2), Terrain::Trees),
-    ] {
-        *map.tile_mut(MapPos{v: Vector2{x: x, y: y}}) = terrain;
-    }
-    for &((x, y), count) in &[
-        ((5, 4), 2),
-        ((5, 5), 2),
-        ((5, 6), 1),
-        ((6, 5), 3),
-        ((6, 6), 1),
-        ((8, 11), 2),
-        ((8, 10), 2),
-        ((9, 11), 1),
-    ] {
-        let pos = MapPos{v: Vector2{x: x, y: y}};
-        add_buildings(&mut map, &mut objects, pos, count);
-    }
-    for &(x, y) in &[
-        (6, 4),
-    ] {
-        let pos = MapPos{v: Vector2{x: x, y: y}};
-        add_big_building(&mut map, &mut objects, pos);
-    }
-    add_road(&mut objects, &[
-        MapPos{v: Vector2{x: 0, y: 1}},
-        MapPos{v: Vector2{x: 1, y: 1}},
-        MapPos{v: Vector2{x: 2, y: 1}},
-        MapPos{v: Vector2{x: 2, y: 2}},
-        MapPos{v: Vector2{x: 3, y: 2}},
-        MapPos{v: Vector2{x: 4, y: 2}},
-        MapPos{v: Vector2{x: 5, y: 2}},
-        MapPos{v: Vector2{x: 6, y: 3}},
-        MapPos{v: Vector2{x: 7, y: 3}},
-        MapPos{v: Vector2{x: 8, y: 3}},
-        MapPos{v: Vector2{x: 9, y: 3}},
-    ]);
-    add_road(&mut objects, &[
-        MapPos{v: Vector2{x: 2, y: 2}},
-        MapPos{v: Vector2{x: 3, y: 3}},
-        MapPos{v: Vector2{x: 3, y: 4}},
-        MapPos{v: Vector2{x: 3, y: 5}},
-        MapPos{v: Vector2{x: 3, y: 6}},
-        MapPos{v: Vector2{x: 4, y: 6}},
-        MapPos{v: Vector2{x: 5, y: 7}},
-        MapPos{v: Vector2{x: 5, y: 8}},
-        MapPos{v: Vector2{x: 6, y: 9}},
-        MapPos{v: Vector2{x: 6, y: 10}},
-        MapPos{v: Vector2{x: 7, y: 11}},
-    ]);
-    for &((x, y), player_index) in &[
-        ((0, 1), 0),
-        ((0, 7), 0),
-        ((9, 3), 1),
-        ((9, 8), 1),
-    ] {
-        add_reinforcement_sector(
-            &mut objects,
-            MapPos{v: Vector2{x: x, y: y}},
-            Some(PlayerId{id: player_index}),
-        );
-    }
-    sectors.insert(
-        SectorId{id: 0},
-        Sector {
-            positions: vec![
-                MapPos{v: Vector2{x: 5, y: 0}},
-                MapPos{v: Vector2{x: 6, y: 0}},
-                MapPos{v: Vector2{x: 5, y: 1}},
-                MapPos{v: Vector2{x: 6, y: 1}},
-                MapPos{v: Vector2{x: 7, y: 1}},
-                MapPos{v: Vector2{x: 5, y: 2}},
-                MapPos{v: Vector2{x: 6, y: 2}},
-            ],
-            owner_id: None,
-        },
-    );
-    sectors.insert(
-        SectorId{id: 1},
-        Sector {
-            positions: vec![
-                MapPos{v: Vector2{x: 5, y: 4}},
-                MapPos{v: Vector2{x: 6, y: 4}},
-                MapPos{v: Vector2{x: 5, y: 5}},
-                MapPos{v: Vector2{x: 6, y: 5}},
-                MapPos{v: Vector2{x: 7, y: 5}},
-                MapPos{v: Vector2{x: 5, y: 6}},
-                MapPos{v: Vector2{x: 6, y: 6}},
-            ],
-            owner_id: None,
-        },
-    );
-    (map, objects, sectors, target_score)
-}
-
-fn load_map_02() -> MapInfo {
-    let target_score = Score{n: 5};
-    let map_size = Size2{w: 9, h: 12};
-    let mut objects = HashMap::new();
-    let mut map = Map::new(map_size);
-    let mut sectors = HashMap::new();
-    for &((x, y), terrain) in &[
-        ((3, 6), Terrain::Trees),
-        ((3, 7), Terrain::Trees),
-    ] {
-        *map.tile_mut(MapPos{v: Vector2{x: x, y: y}}) = terrain;
-    }
-    for &((x, y), player_index) in &[
-        ((0, 4), 0),
-        ((0, 10), 0),
-        ((8, 4), 1),
-        ((8, 10), 1),
-    ] {
-        add_reinforcement_sector(
-            &mut objects,
-            MapPos{v: Vector2{x: x, y: y}},
-            Some(PlayerId{id: player_index}),
-        );
-    }
-    sectors.insert(
-        SectorId{id: 0},
-        Sector {
-            positions: vec![
-                MapPos{v: Vector2{x: 4, y: 3}},
-            ],
-            owner_id: None,
-        },
-    );
-    sectors.insert(
-        SectorId{id: 1},
-        Sector {
-            positions: vec![
-                MapPos{v: Vector2{x: 5, y: 8}},
-            ],
-            owner_id: None,
-        },
-    );
-    (map, objects, sectors, target_score)
-}
-
-fn load_map_03() -> MapInfo {
-    let target_score = Score{n: 5};
-    let map_size = Size2{w: 3, h: 1};
-    let mut objects = HashMap::new();
-    let mut map = Map::new(map_size);
-    let sectors = HashMap::new();
-    for &((x, y), terrain) in &[
-        ((1, 0), Terrain::Trees),
-    ] {
-        *map.tile_mut(MapPos{v: Vector2{x: x, y: y}}) = terrain;
-    }
-    for &((x, y), player_index) in &[
-        ((0, 0), 0),
-        ((2, 0), 1),
-    ] {
-        add_reinforcement_sector(
-            &mut objects,
-            MapPos{v: Vector2{x: x, y: y}},
-            Some(PlayerId{id: player_index}),
-        );
-    }
-    (map, objects, sectors, target_score)
-}
-
-fn load_map_04() -> MapInfo {
-    let target_score = Score{n: 5};
-    let map_size = Size2{w: 2, h: 1};
-    let mut objects = HashMap::new();
-    let mut map = Map::new(map_size);
-    let sectors = HashMap::new();
-    for &((x, y), terrain) in &[
-        ((1, 0), Terrain::Trees),
-    ] {
-        *map.tile_mut(MapPos{v: Vector2{x: x, y: y}}) = terrain;
-    }
-    for &((x, y), player_index) in &[
-        ((0, 0), 0),
-        ((1, 0), 1),
-    ] {
-        add_reinforcement_sector(
-            &mut objects,
-            MapPos{v: Vector2{x: x, y: y}},
-            Some(PlayerId{id: player_index}),
-        );
-    }
-    (map, objects, sectors, target_score)
-}
-
-fn load_map_05() -> MapInfo {
-    let target_score = Score{n: 5};
-    let map_size = Size2{w: 3, h: 1};
-    let mut objects = HashMap::new();
-    let map = Map::new(map_size);
-    let sectors = HashMap::new();
-    for &((x, y), player_index) in &[
-        ((0, 0), 0),
-        ((2, 0), 1),
-    ] {
-        add_reinforcement_sector(
-            &mut objects,
-            MapPos{v: Vector2{x: x, y: y}},
-            Some(PlayerId{id: player_index}),
-        );
-    }
-    (map, objects, sectors, target_score)
-}
-
-/// Map for repoducing of https://github.com/ozkriff/zoc/issues/149
-fn load_map_fov_bug_test() -> MapInfo {
-    let target_score = Score{n: 5};
-    let map_size = Size2{w: 20, h: 20};
-    let mut objects = HashMap::new();
-    let mut map = Map::new(map_size);
-    let sectors = HashMap::new();
-    for &((x, y), terrain) in &[
-        ((9, 10), Terrain::Trees),
-        ((10, 9), Terrain::Trees),
-    ] {
-        *map.tile_mut(MapPos{v: Vector2{x: x, y: y}}) = terrain;
-    }
-    for &((x, y), player_index) in &[
-        ((10, 10), 0),
-    ] {
-        add_reinforcement_sector(
-            &mut objects,
-            MapPos{v: Vector2{x: x, y: y}},
-            Some(PlayerId{id: player_index}),
-        );
-    }
-    (map, objects, sectors, target_score)
-}
@@ -0,0 +1,257 @@
+//! What the AI knows about every tile: which known enemy weapons
+//! can shoot there, how many friends are near and what sectors are worth
+
+use std::cmp;
+use game_state::{State};
+use map::{Map, Distance, distance};
+use fov::{fov, simple_fov};
+use db::{Db};
+use unit::{Unit};
+use player::{PlayerId};
+use position::{MapPos};
+use types::{Size2};
+
+/// Units that are this close can help each other
+const SUPPORT_DISTANCE: Distance = Distance{n: 2};
+
+/// Value of a sector that is not ours yet
+const FREE_SECTOR_VALUE: i32 = 1;
+
+/// Value of our sector that the enemy can shoot at
+const CONTESTED_SECTOR_VALUE: i32 = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Influence {
+    /// Known enemy weapons that can shoot at ground units here
+    pub threat: i32,
+
+    /// Known enemy weapons that can shoot at air units here
+    pub air_threat: i32,
+
+    /// Part of `threat` that comes from the enemies that are
+    /// out of sight now but may still be where they were seen
+    pub hidden_threat: i32,
+
+    /// Own and allied units in `SUPPORT_DISTANCE`
+    pub support: i32,
+
+    /// Zero outside of sectors and in our safe sectors
+    pub sector_value: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct InfluenceMap {
+    map: Map<Influence>,
+}
+
+impl InfluenceMap {
+    pub fn new(map_size: Size2) -> InfluenceMap {
+        InfluenceMap {
+            map: Map::new(map_size),
+        }
+    }
+
+    pub fn tile<P: Into<MapPos>>(&self, pos: P) -> &Influence {
+        self.map.tile(pos)
+    }
+
+    /// Recalculates all tiles from the player's view of the state
+    /// and the remembered positions of the hidden enemies
+    pub fn update(
+        &mut self,
+        db: &Db,
+        state: &State,
+        player_id: PlayerId,
+        hidden_enemies: &[&Unit],
+    ) {
+        for pos in self.map.get_iter() {
+            *self.map.tile_mut(pos) = Influence::default();
+        }
+        for (_, unit) in state.units() {
+            if !unit.is_alive || unit.is_loaded || unit.is_attached {
+                continue;
+            }
+            if state.is_ally(unit.player_id, player_id) {
+                self.add_support(state, unit);
+            } else {
+                self.add_threat(db, state, unit, 0);
+            }
+        }
+        for enemy in hidden_enemies {
+            self.add_threat(db, state, enemy, 1);
+        }
+        self.add_sectors(state, player_id);
+    }
+
+    /// More known enemy weapons cover the tile than there are friends around
+    pub fn is_kill_zone<P: Into<MapPos>>(&self, pos: P, is_air: bool) -> bool {
+        let tile = self.tile(pos);
+        let threat = if is_air { tile.air_threat } else { tile.threat };
+        threat > tile.support
+    }
+
+    fn add_support(&mut self, state: &State, unit: &Unit) {
+        let map = &mut self.map;
+        simple_fov(state, unit.pos.map_pos, SUPPORT_DISTANCE, &mut |pos| {
+            map.tile_mut(pos).support += 1;
+        });
+    }
+
+    /// Marks tiles that pass the range and line of sight checks of `check_attack`
+    /// `hidden` is 1 for the remembered enemies and 0 for the visible ones
+    fn add_threat(&mut self, db: &Db, state: &State, enemy: &Unit, hidden: i32) {
+        let enemy_type = db.unit_type(enemy.type_id);
+        let weapon_type = db.weapon_type(enemy_type.weapon_type_id);
+        let origin = enemy.pos.map_pos;
+        {
+            let map = &mut self.map;
+            let ground_cb = &mut |pos| {
+                let d = distance(origin, pos);
+                if d >= weapon_type.min_distance && d <= weapon_type.max_distance {
+                    let tile = map.tile_mut(pos);
+                    tile.threat += 1;
+                    tile.hidden_threat += hidden;
+                }
+            };
+            let los_range = cmp::min(enemy_type.los_range, weapon_type.max_distance);
+            if weapon_type.is_inderect {
+                simple_fov(state, origin, weapon_type.max_distance, ground_cb);
+            } else if enemy_type.is_air {
+                simple_fov(state, origin, los_range, ground_cb);
+            } else {
+                fov(state, origin, los_range, ground_cb);
+            }
+        }
+        if let Some(max_air_distance) = weapon_type.max_air_distance {
+            let map = &mut self.map;
+            let range = cmp::min(enemy_type.los_range, max_air_distance);
+            simple_fov(state, origin, range, &mut |pos| {
+                map.tile_mut(pos).air_threat += 1;
+            });
+        }
+    }
+
+    fn add_sectors(&mut self, state: &State, player_id: PlayerId) {
+        for sector in state.sectors().values() {
+            let is_ours = sector.owner_id.map_or(false, |owner_id| {
+                state.is_ally(owner_id, player_id)
+            });
+            let value = if !is_ours {
+                FREE_SECTOR_VALUE
+            } else if sector.positions.iter().any(|&pos| self.tile(pos).threat > 0) {
+                CONTESTED_SECTOR_VALUE
+            } else {
+                0
+            };
+            for &pos in &sector.positions {
+                self.map.tile_mut(pos).sector_value = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use Core;
+    use options::{Options};
+    use scenario::{Scenario};
+    use player::{PlayerId, PlayerClass};
+    use position::{MapPos, ExactPos, SlotId};
+    use event::{Command};
+    use influence::{InfluenceMap};
+
+    fn line_pos(x: i32) -> MapPos {
+        MapPos{v: Vector2{x: x, y: 0}}
+    }
+
+    /// Soldiers at the opposite ends of the line, they can't see each other
+    fn new_core() -> Core {
+        let text = "
+            size 8 1
+            target_score 5
+            players_count 2
+            terrain
+                ........
+            reinforcement_sector 0 0 some 0
+            reinforcement_sector 7 0 some 1
+        ";
+        let scenario = text.parse::<Scenario>().unwrap();
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core = Core::new(&Options::new(players, scenario));
+        let soldier_id = core.db().unit_type_id("soldier");
+        for &x in &[0, 7] {
+            core.do_command(Command::CreateUnit {
+                pos: ExactPos{map_pos: line_pos(x), slot_id: SlotId::Id(0)},
+                type_id: soldier_id,
+            });
+            core.do_command(Command::EndTurn);
+        }
+        core
+    }
+
+    #[test]
+    fn test_two_opposing_units() {
+        let core = new_core();
+        let size = core.state().map().size();
+        let mut influence = InfluenceMap::new(size);
+        influence.update(core.db(), core.state(), PlayerId{id: 0}, &[]);
+        // Rifles shoot at ground units three tiles away and at air units two tiles away,
+        // friends support each other two tiles away
+        let expected = [
+            // threat, air threat, support
+            (0, 0, 1),
+            (0, 0, 1),
+            (0, 0, 1),
+            (0, 0, 0),
+            (1, 0, 0),
+            (1, 1, 0),
+            (1, 1, 0),
+            (1, 1, 0),
+        ];
+        for (x, &(threat, air_threat, support)) in expected.iter().enumerate() {
+            let tile = influence.tile(line_pos(x as i32));
+            assert_eq!((tile.threat, tile.air_threat, tile.support),
+                (threat, air_threat, support), "x = {}", x);
+            assert_eq!(tile.hidden_threat, 0);
+            assert_eq!(influence.is_kill_zone(line_pos(x as i32), false), threat > 0);
+        }
+        // The enemy sees the same from its side
+        influence.update(core.db(), core.state(), PlayerId{id: 1}, &[]);
+        for (x, &(threat, air_threat, support)) in expected.iter().rev().enumerate() {
+            let tile = influence.tile(line_pos(x as i32));
+            assert_eq!((tile.threat, tile.air_threat, tile.support),
+                (threat, air_threat, support), "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn test_hidden_enemy() {
+        let core = new_core();
+        let size = core.state().map().size();
+        let mut influence = InfluenceMap::new(size);
+        let mut hidden_enemy = core.state().units()
+            .map(|(_, unit)| unit)
+            .find(|unit| unit.player_id == PlayerId{id: 1})
+            .unwrap()
+            .clone();
+        hidden_enemy.pos.map_pos = line_pos(3);
+        influence.update(core.db(), core.state(), PlayerId{id: 0}, &[&hidden_enemy]);
+        // Threats of the visible and the remembered enemy add up
+        let expected = [
+            // threat, hidden threat
+            (1, 1),
+            (1, 1),
+            (1, 1),
+            (1, 1),
+            (2, 1),
+            (2, 1),
+            (2, 1),
+            (1, 0),
+        ];
+        for (x, &(threat, hidden_threat)) in expected.iter().enumerate() {
+            let tile = influence.tile(line_pos(x as i32));
+            assert_eq!((tile.threat, tile.hidden_threat), (threat, hidden_threat), "x = {}", x);
+        }
+    }
+}
@@ -19,29 +19,53 @@ pub mod player;
 pub mod object;
 pub mod options;
 pub mod attack;
+pub mod rng;
+pub mod serialize;
+pub mod replay;
+pub mod scenario;
+pub mod ai;
+pub mod search_ai;
+pub mod influence;
+pub mod memory;
+pub mod purchase;
+pub mod net;
+pub mod pbem;
+pub mod orders;
 
-mod ai;
 mod fov;
 mod fow;
 mod filter;
 
-use std::{cmp};
-use std::collections::{HashMap};
+use std::{cmp, mem};
+use std::collections::{HashMap, VecDeque};
 use std::rc::{Rc};
+use std::time::{Duration, Instant};
 use rand::{thread_rng, Rng};
+use rng::{CoreRng};
 use game_state::{State};
-use options::{Options};
+use options::{Options, OptionsError, TurnMode};
 use movement::{MovePoints, tile_cost, move_cost_modifier};
 use unit::{Unit, UnitId};
-use db::{Db};
-use ai::{Ai};
+use db::{Db, MAX_SMOKE};
+use ai::{Ai, AiBuilder, AiFinder, SimpleAiBuilder, read_ai_builder};
 use dir::{Dir};
+use position::{MapPos, ExactPos, can_place_unit};
 use attack::{AttackPoints, hit_chance, get_killed_count};
 use sector::{check_sectors};
-use check::{check_attack};
+use check::{CommandError, check_attack, check_smoke, ends_turn};
 use player::{Player, PlayerId, PlayerClass, PlayerInfo};
 use object::{ObjectId};
-use event::{CoreEvent, Command};
+use event::{CoreEvent, Command, MoveMode};
+use orders::{Order, Plan};
+use serialize::{Serialize, Writer, Reader, DeserializeError, write_header, read_header};
+use replay::{Replay};
+
+/// Bump this when the layout written by `Core::save` changes
+const SAVE_VERSION: i32 = 12;
+
+/// Players get `CoreEvent::TimeLeft` when their turn starts
+/// and then every this many seconds
+const TIME_LEFT_EVENT_INTERVAL_S: u64 = 10;
 
 #[derive(PartialEq, Clone, Copy, Debug)]
 enum ReactionFireResult {
@@ -51,64 +75,226 @@ enum ReactionFireResult {
 }
 
 fn get_players_list(options: &Options) -> Vec<Player> {
-    assert_eq!(options.players_count, 2);
-    vec!(
-        Player {
-            id: PlayerId{id: 0},
-            class: PlayerClass::Human,
-        },
+    options.players.iter().enumerate().map(|(id, &class)| {
         Player {
-            id: PlayerId{id: 1},
-            class: match options.game_type {
-                options::GameType::SingleVsAi => PlayerClass::Ai,
-                options::GameType::Hotseat => PlayerClass::Human,
-            },
-        },
-    )
+            id: PlayerId{id: id as i32},
+            class: class,
+            team_id: options.teams[id],
+        }
+    }).collect()
 }
 
-fn get_player_info_list(state: &State) -> HashMap<PlayerId, PlayerInfo> {
+fn get_player_info_list(state: &State, players: &[Player]) -> HashMap<PlayerId, PlayerInfo> {
     let mut map = HashMap::new();
-    map.insert(PlayerId{id: 0}, PlayerInfo::new(state, PlayerId{id: 0}));
-    map.insert(PlayerId{id: 1}, PlayerInfo::new(state, PlayerId{id: 1}));
+    for player in players {
+        map.insert(player.id, PlayerInfo::new(state, player.id));
+    }
     map
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Core {
     state: State,
     players: Vec<Player>,
     current_player_id: PlayerId,
     db: Rc<Db>,
-    ai: Ai,
+    ais: HashMap<PlayerId, Box<Ai>>,
     players_info: HashMap<PlayerId, PlayerInfo>,
+
+    /// Orders of simultaneous turns that aren't carried out yet
+    plans: HashMap<PlayerId, Plan>,
+
+    /// Time left for the current human turn, None without time limits
+    turn_time_left: Option<Duration>,
+
+    /// Time left for all the turns of every human player,
+    /// empty without the game time limit
+    game_time_left: HashMap<PlayerId, Duration>,
+
     next_unit_id: UnitId,
     next_object_id: ObjectId,
+    seed: u64,
+    rng: CoreRng,
+    replay: Replay,
 }
 
 impl Core {
+    /// Panics if the options don't pass `Options::check`
     pub fn new(options: &Options) -> Core {
-        let db = Rc::new(Db::new());
+        Core::new_with_db(options, Db::new())
+    }
+
+    /// Starts a game with custom unit and weapon types
+    pub fn new_with_db(options: &Options, db: Db) -> Core {
+        match Core::try_new_with_db(options, db) {
+            Ok(core) => core,
+            Err(err) => panic!("Bad options: {}", err),
+        }
+    }
+
+    /// Like `new`, but bad options are returned as an error
+    pub fn try_new(options: &Options) -> Result<Core, OptionsError> {
+        Core::try_new_with_db(options, Db::new())
+    }
+
+    pub fn try_new_with_db(options: &Options, db: Db) -> Result<Core, OptionsError> {
+        options.check()?;
+        let seed = options.seed.unwrap_or_else(|| thread_rng().gen());
+        let mut rng = CoreRng::new(seed);
+        let db = Rc::new(db);
         let state = State::new_full(db.clone(), options);
-        let players_info = get_player_info_list(&state);
-        let ai = Ai::new(db.clone(), options, PlayerId{id:1});
+        let players = get_players_list(options);
+        let players_info = get_player_info_list(&state, &players);
+        let mut ais = HashMap::new();
+        for player in &players {
+            if player.class == PlayerClass::Ai {
+                let ai_rng = CoreRng::new(rng.gen());
+                let ai = match options.ais.get(&player.id) {
+                    Some(builder) => builder.build(db.clone(), options, player.id, ai_rng),
+                    None => SimpleAiBuilder.build(db.clone(), options, player.id, ai_rng),
+                };
+                ais.insert(player.id, ai);
+            }
+        }
         let next_object_id = ObjectId{id: state.objects().len() as i32};
-        Core {
+        let mut game_time_left = HashMap::new();
+        if let Some(limit) = options.game_time_limit {
+            for player in &players {
+                if player.class == PlayerClass::Human {
+                    game_time_left.insert(player.id, limit);
+                }
+            }
+        }
+        let replay = Replay::new(&Options{seed: Some(seed), .. options.clone()}, &db);
+        let mut core = Core {
             state: state,
-            players: get_players_list(options),
+            players: players,
             current_player_id: PlayerId{id: 0},
             db: db,
-            ai: ai,
+            ais: ais,
             players_info: players_info,
+            plans: HashMap::new(),
+            turn_time_left: None,
+            game_time_left: game_time_left,
             next_unit_id: UnitId{id: 0},
             next_object_id: next_object_id,
-        }
+            seed: seed,
+            rng: rng,
+            replay: replay,
+        };
+        core.start_turn_clock();
+        Ok(core)
     }
 
     pub fn db(&self) -> &Rc<Db> {
         &self.db
     }
 
+    pub fn save(&self, w: &mut Writer) {
+        write_header(w, "zoc_save", SAVE_VERSION);
+        w.new_line();
+        self.db.save(w);
+        w.new_line();
+        self.seed.serialize(w);
+        self.rng.serialize(w);
+        self.current_player_id.serialize(w);
+        self.next_unit_id.serialize(w);
+        self.next_object_id.serialize(w);
+        w.new_line();
+        self.players.serialize(w);
+        w.new_line();
+        self.state.save(w);
+        w.new_line();
+        (self.players.len() as u64).serialize(w);
+        for player in &self.players {
+            w.new_line();
+            self.players_info[&player.id].save(w);
+        }
+        w.new_line();
+        (self.ais.len() as u64).serialize(w);
+        for player in &self.players {
+            if let Some(ai) = self.ais.get(&player.id) {
+                let name = match self.replay.options.ais.get(&player.id) {
+                    Some(builder) => builder.name().to_owned(),
+                    None => SimpleAiBuilder.name().to_owned(),
+                };
+                w.new_line();
+                player.id.serialize(w);
+                w.write_str(&name);
+                ai.save(w);
+            }
+        }
+        w.new_line();
+        self.plans.serialize(w);
+        w.new_line();
+        self.turn_time_left.serialize(w);
+        self.game_time_left.serialize(w);
+        w.new_line();
+        self.replay.save(w);
+    }
+
+    /// `find_ai` must know all the AIs of the saved game,
+    /// `builtin_ai_builder` is enough if only the core's own AIs played
+    pub fn load(r: &mut Reader, find_ai: &AiFinder) -> Result<Core, DeserializeError> {
+        read_header(r, "zoc_save", SAVE_VERSION)?;
+        let db = Rc::new(Db::load(r)?);
+        let seed = r.read()?;
+        let rng = r.read()?;
+        let current_player_id = r.read()?;
+        let next_unit_id = r.read()?;
+        let next_object_id = r.read()?;
+        let players = r.read()?;
+        let state = State::load(r, db.clone())?;
+        let mut players_info = HashMap::new();
+        let players_info_count: u64 = r.read()?;
+        for _ in 0 .. players_info_count {
+            let info = PlayerInfo::load(r, db.clone())?;
+            players_info.insert(info.id(), info);
+        }
+        let mut ais = HashMap::new();
+        let ais_count: u64 = r.read()?;
+        for _ in 0 .. ais_count {
+            let player_id = r.read()?;
+            let builder = read_ai_builder(r, find_ai)?;
+            ais.insert(player_id, builder.load(r, db.clone())?);
+        }
+        let plans = r.read()?;
+        let turn_time_left = r.read()?;
+        let game_time_left = r.read()?;
+        let replay = Replay::load(r, find_ai)?;
+        Ok(Core {
+            state: state,
+            players: players,
+            current_player_id: current_player_id,
+            db: db,
+            ais: ais,
+            players_info: players_info,
+            plans: plans,
+            turn_time_left: turn_time_left,
+            game_time_left: game_time_left,
+            next_unit_id: next_unit_id,
+            next_object_id: next_object_id,
+            seed: seed,
+            rng: rng,
+            replay: replay,
+        })
+    }
+
+    /// Seed that was actually used, even if `Options::seed` was None
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// All human commands issued since the start of the game
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    /// Full state without the fog of war, not for showing to players
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
     fn get_new_unit_id(&mut self) -> UnitId {
         self.next_unit_id.id += 1;
         self.next_unit_id
@@ -128,12 +314,114 @@ impl Core {
     }
 
     pub fn get_event(&mut self) -> Option<CoreEvent> {
-        let mut i = self.players_info.get_mut(&self.current_player_id).unwrap();
+        let player_id = self.current_player_id;
+        self.get_event_for(player_id)
+    }
+
+    /// Lets humans watch AI turns as they are played
+    pub fn get_event_for(&mut self, player_id: PlayerId) -> Option<CoreEvent> {
+        let mut i = self.players_info.get_mut(&player_id).unwrap();
         i.get_event()
     }
 
+    /// Partial state with all that the player knows now, for clients
+    /// that have no state of their own: reconnected or just loaded ones.
+    ///
+    /// Events queued for the player are dropped, the snapshot has them.
+    pub fn snapshot_for(&mut self, player_id: PlayerId) -> State {
+        let info = self.players_info.get_mut(&player_id).unwrap();
+        info.split_events_off(0);
+        let units = filter::get_known_units(
+            &self.state, player_id, info.visible_enemies());
+        self.state.to_snapshot(player_id, info.fow().clone(), units)
+    }
+
+    /// Orders that the player has plotted in this simultaneous turn
+    pub fn plan(&self, player_id: PlayerId) -> Plan {
+        self.plans.get(&player_id).cloned().unwrap_or_else(Plan::new)
+    }
+
+    /// The AI doesn't play by itself: see `do_ai_step`
+    pub fn is_ai_turn(&self) -> bool {
+        self.player().class == PlayerClass::Ai
+    }
+
+    /// Time left for the current human turn, None if it's not limited
+    pub fn turn_time_left(&self) -> Option<Duration> {
+        self.turn_time_left
+    }
+
+    /// Has the current human run out of time? Then whoever
+    /// runs the clock ends the turn with `Command::TimeOut`.
+    pub fn is_time_out(&self) -> bool {
+        self.turn_time_left == Some(Duration::from_secs(0))
+    }
+
+    /// Runs the clock of the current human turn. The core doesn't look
+    /// at the real time itself: whoever waits for the human's commands
+    /// calls this and does `Command::TimeOut` when `is_time_out`.
+    pub fn pass_time(&mut self, time: Duration) {
+        let time_left = match self.turn_time_left {
+            Some(time_left) => time_left,
+            None => return,
+        };
+        // The turn can't take longer than its time
+        let time = cmp::min(time, time_left);
+        // One command per wait, not per frame
+        if let Some(&mut Command::PassTime{time: ref mut last}) = self.replay.commands.last_mut() {
+            *last += time;
+        } else {
+            self.replay.commands.push(Command::PassTime{time: time});
+        }
+        self.do_command_internal(Command::PassTime{time: time});
+    }
+
+    fn run_clock(&mut self, time: Duration) {
+        let old_time_left = self.turn_time_left.unwrap();
+        let zero = Duration::from_secs(0);
+        if let Some(game_time_left) = self.game_time_left.get_mut(&self.current_player_id) {
+            *game_time_left = game_time_left.checked_sub(time).unwrap_or(zero);
+        }
+        let time_left = old_time_left.checked_sub(time).unwrap_or(zero);
+        self.turn_time_left = Some(time_left);
+        if time_left == zero || old_time_left.as_secs() / TIME_LEFT_EVENT_INTERVAL_S
+            != time_left.as_secs() / TIME_LEFT_EVENT_INTERVAL_S
+        {
+            self.report_time_left();
+        }
+    }
+
+    /// Turns of humans take as long as the limits
+    /// and the player's time for the game allow
+    fn start_turn_clock(&mut self) {
+        let player_id = self.current_player_id;
+        self.turn_time_left = if self.player().class == PlayerClass::Human {
+            let game_time_left = self.game_time_left.get(&player_id).cloned();
+            match (self.replay.options.turn_time_limit, game_time_left) {
+                (Some(turn), Some(game)) => Some(cmp::min(turn, game)),
+                (Some(time), None) | (None, Some(time)) => Some(time),
+                (None, None) => None,
+            }
+        } else {
+            None
+        };
+        self.report_time_left();
+    }
+
+    fn report_time_left(&mut self) {
+        if let Some(time_left) = self.turn_time_left {
+            let player_id = self.current_player_id;
+            let game_time_left = self.game_time_left.get(&player_id).cloned();
+            self.do_core_event(&CoreEvent::TimeLeft {
+                player_id: player_id,
+                turn: time_left,
+                game: game_time_left,
+            });
+        }
+    }
+
     fn command_attack_unit_to_event(
-        &self,
+        &mut self,
         attacker_id: UnitId,
         defender_id: UnitId,
         fire_mode: event::FireMode,
@@ -151,13 +439,14 @@ impl Core {
         let suppression = hit_chance.n / 2;
         let killed = cmp::min(
             defender.count,
-            get_killed_count(&self.db, &self.state, attacker, defender),
+            get_killed_count(
+                &self.db, &self.state, attacker, defender, &mut self.rng),
         );
         let fow = self.players_info[&defender.player_id].fow();
         let is_visible = fow.is_visible(attacker);
         let ambush_chance = 70;
         let is_ambush = !is_visible
-            && thread_rng().gen_range(1, 100) <= ambush_chance;
+            && self.rng.gen_range(1, 100) <= ambush_chance;
         let per_death_suppression = 20;
         let defender_type = self.db.unit_type(defender.type_id);
         // TODO: destroyed helicopters must kill everyone
@@ -182,7 +471,7 @@ impl Core {
         defender: &Unit,
         attacker: &Unit,
     ) -> bool {
-        assert!(attacker.player_id != defender.player_id);
+        assert!(!self.state.is_ally(attacker.player_id, defender.player_id));
         if attacker.reaction_fire_mode == event::ReactionFireMode::HoldFire {
             return false;
         }
@@ -201,6 +490,20 @@ impl Core {
         check_attack_result.is_ok()
     }
 
+    fn reveal_hidden_units_at(&mut self, player_id: PlayerId, pos: MapPos) {
+        let mut events = Vec::new();
+        for unit in self.state.units_at(pos) {
+            if !self.players_info[&player_id].fow().is_visible(unit) {
+                events.push(CoreEvent::Reveal {
+                    unit_info: unit.clone(),
+                });
+            }
+        }
+        for event in &events {
+            self.do_core_event(event);
+        }
+    }
+
     fn reaction_fire_internal(
         &mut self,
         unit_id: UnitId,
@@ -209,31 +512,38 @@ impl Core {
         let unit_ids: Vec<_> = self.state.units().map(|(&id, _)| id).collect();
         let mut result = ReactionFireResult::None;
         for enemy_unit_id in unit_ids {
-            if unit::is_loaded_or_attached(self.state.unit(enemy_unit_id)) {
+            // Passengers die with their transporter
+            let enemy_unit = match self.state.unit_opt(enemy_unit_id) {
+                Some(unit) => unit,
+                None => continue,
+            };
+            if unit::is_loaded_or_attached(enemy_unit) {
                 continue;
             }
-            let event = {
+            {
                 let enemy_unit = self.state.unit(enemy_unit_id);
                 let unit = self.state.unit(unit_id);
-                if enemy_unit.player_id == unit.player_id {
+                if self.state.is_ally(enemy_unit.player_id, unit.player_id) {
                     continue;
                 }
                 if !self.can_unit_make_reaction_attack(unit, enemy_unit) {
                     continue;
                 }
-                let event = self.command_attack_unit_to_event(
-                    enemy_unit.id, unit_id, event::FireMode::Reactive);
-                if let Some(CoreEvent::AttackUnit{mut attack_info}) = event {
-                    let hit_chance = attack::hit_chance(
-                        &self.db, &self.state, enemy_unit, unit);
-                    let unit_type = self.db.unit_type(unit.type_id);
-                    if hit_chance.n > 15 && !unit_type.is_air && stop_on_attack {
-                        attack_info.remove_move_points = true;
-                    }
-                    CoreEvent::AttackUnit{attack_info: attack_info}
-                } else {
-                    continue;
+            }
+            let event = self.command_attack_unit_to_event(
+                enemy_unit_id, unit_id, event::FireMode::Reactive);
+            let event = if let Some(CoreEvent::AttackUnit{mut attack_info}) = event {
+                let enemy_unit = self.state.unit(enemy_unit_id);
+                let unit = self.state.unit(unit_id);
+                let hit_chance = attack::hit_chance(
+                    &self.db, &self.state, enemy_unit, unit);
+                let unit_type = self.db.unit_type(unit.type_id);
+                if hit_chance.n > 15 && !unit_type.is_air && stop_on_attack {
+                    attack_info.remove_move_points = true;
                 }
+                CoreEvent::AttackUnit{attack_info: attack_info}
+            } else {
+                continue;
             };
             self.do_core_event(&event);
             result = ReactionFireResult::Attacked;
@@ -258,20 +568,31 @@ impl Core {
         }}
     }
 
-    fn check_command(&mut self, command: &Command) {
+    fn check_command(&mut self, command: &Command) -> Result<(), CommandError> {
         let db = &self.db;
         let player_id = self.current_player_id;
-        let mut i = self.players_info.get_mut(&player_id).unwrap();
-        if let Err(err) = i.check_command(db, &mut self.state, command) {
-            panic!("Bad command: {:?} ({:?})", err, command);
+        match *command {
+            Command::PassTime{..} if self.turn_time_left.is_none() => {
+                return Err(CommandError::NoTurnTimeLimit);
+            },
+            Command::TimeOut if !self.is_time_out() => {
+                return Err(CommandError::TimeIsNotOut);
+            },
+            _ => {},
         }
+        let mut i = self.players_info.get_mut(&player_id).unwrap();
+        i.check_command(db, &mut self.state, command)
     }
 
     fn simulation_step(&mut self, command: Command) {
         match command {
-            Command::EndTurn => {
+            Command::EndTurn | Command::TimeOut => {
                 let old_id = self.current_player_id;
                 let new_id = self.next_player_id(old_id);
+                // The last player has plotted their orders
+                if self.state.turn_mode() == TurnMode::Simultaneous && new_id.id == 0 {
+                    self.carry_out_orders();
+                }
                 // TODO: extruct func
                 let mut end_turn_events = Vec::new();
                 for sector in self.state.sectors().values() {
@@ -302,6 +623,7 @@ impl Core {
                     old_id: old_id,
                     new_id: new_id,
                 });
+                self.start_turn_clock();
             },
             Command::CreateUnit{pos, type_id} => {
                 let event = {
@@ -330,7 +652,6 @@ impl Core {
                 self.do_core_event(&event);
             },
             Command::Move{unit_id, path, mode} => {
-                let player_id = self.state.unit(unit_id).player_id;
                 for window in path.windows(2) {
                     let from = window[0];
                     let to = window[1];
@@ -343,38 +664,15 @@ impl Core {
                         self.do_core_event(&event);
                         continue;
                     }
-                    let move_event = {
-                        let unit = self.state.unit(unit_id);
-                        let cost = MovePoints {
-                            n: tile_cost(&self.db, &self.state, unit, from, to).n
-                                * move_cost_modifier(mode)
-                        };
-                        CoreEvent::Move {
-                            unit_id: unit_id,
-                            from: from,
-                            to: to,
-                            mode: mode,
-                            cost: cost,
-                        }
-                    };
-                    let pre_visible_enemies = self.players_info[&player_id]
-                        .visible_enemies().clone();
-                    self.do_core_event(&move_event);
-                    let reaction_fire_result = self.reaction_fire_internal(
-                        unit_id, mode == event::MoveMode::Fast);
-                    if reaction_fire_result != ReactionFireResult::None {
-                        break;
-                    }
-                    let i = &self.players_info[&player_id];
-                    if &pre_visible_enemies != i.visible_enemies() {
+                    if !self.move_step(unit_id, from, to, mode) {
                         break;
                     }
                 }
             },
             Command::AttackUnit{attacker_id, defender_id} => {
-                if let Some(ref event) = self.command_attack_unit_to_event(
-                    attacker_id, defender_id, event::FireMode::Active)
-                {
+                let event = self.command_attack_unit_to_event(
+                    attacker_id, defender_id, event::FireMode::Active);
+                if let Some(ref event) = event {
                     self.do_core_event(event);
                     self.reaction_fire(attacker_id);
                 }
@@ -433,55 +731,328 @@ impl Core {
                 });
             },
             Command::Smoke{unit_id, pos} => {
-                let id = self.get_new_object_id();
-                self.do_core_event(&CoreEvent::Smoke {
-                    id: id,
-                    unit_id: Some(unit_id),
-                    pos: pos,
-                });
-                let mut dir = Dir::from_int(thread_rng().gen_range(0, 5));
-                let additional_smoke_count = {
-                    let unit = self.state.unit(unit_id);
-                    let unit_type = self.db.unit_type(unit.type_id);
-                    let weapon_type = self.db.weapon_type(unit_type.weapon_type_id);
-                    weapon_type.smoke.unwrap()
-                };
-                assert!(additional_smoke_count <= 3);
-                for _ in 0..additional_smoke_count {
-                    let mut dir_index = dir.to_int() + thread_rng().gen_range(1, 3);
-                    if dir_index > 5 {
-                        dir_index -= 6;
-                    }
-                    dir = Dir::from_int(dir_index);
-                    let id = self.get_new_object_id();
-                    self.do_core_event(&CoreEvent::Smoke {
-                        id: id,
-                        unit_id: Some(unit_id),
-                        pos: Dir::get_neighbour_pos(pos, dir),
-                    });
-                }
-                self.reaction_fire(unit_id);
+                self.smoke(unit_id, pos);
+            },
+            Command::SetOrders{unit_id, orders} => {
+                let player_id = self.current_player_id;
+                self.plans.entry(player_id)
+                    .or_insert_with(Plan::new)
+                    .set_orders(unit_id, orders);
+            },
+            Command::PassTime{time} => {
+                self.run_clock(time);
             },
         };
+        self.update_sectors();
+    }
+
+    fn update_sectors(&mut self) {
         let sector_events = check_sectors(&self.db, &self.state);
         for event in sector_events {
             self.do_core_event(&event);
         }
     }
 
+    /// Moves the unit to the next tile of its path,
+    /// returns false if the unit must stop
+    fn move_step(&mut self, unit_id: UnitId, from: ExactPos, to: ExactPos, mode: MoveMode) -> bool {
+        let player_id = self.state.unit(unit_id).player_id;
+        let (cost, move_points) = {
+            let unit = self.state.unit(unit_id);
+            let cost = MovePoints {
+                n: tile_cost(&self.db, &self.state, unit, from, to).n
+                    * move_cost_modifier(mode)
+            };
+            (cost, unit.move_points.unwrap())
+        };
+        // Hidden units make tiles more expensive
+        // than the player could know
+        if move_points.n < cost.n {
+            self.reveal_hidden_units_at(player_id, to.map_pos);
+            return false;
+        }
+        let move_event = CoreEvent::Move {
+            unit_id: unit_id,
+            from: from,
+            to: to,
+            mode: mode,
+            cost: cost,
+        };
+        let pre_visible_enemies = self.players_info[&player_id]
+            .visible_enemies().clone();
+        self.do_core_event(&move_event);
+        let reaction_fire_result = self.reaction_fire_internal(
+            unit_id, mode == MoveMode::Fast);
+        if reaction_fire_result != ReactionFireResult::None {
+            return false;
+        }
+        let i = &self.players_info[&player_id];
+        &pre_visible_enemies == i.visible_enemies()
+    }
+
+    fn smoke(&mut self, unit_id: UnitId, pos: MapPos) {
+        let id = self.get_new_object_id();
+        self.do_core_event(&CoreEvent::Smoke {
+            id: id,
+            unit_id: Some(unit_id),
+            pos: pos,
+        });
+        let mut dir = Dir::from_int(self.rng.gen_range(0, 5));
+        let additional_smoke_count = {
+            let unit = self.state.unit(unit_id);
+            let unit_type = self.db.unit_type(unit.type_id);
+            let weapon_type = self.db.weapon_type(unit_type.weapon_type_id);
+            weapon_type.smoke.unwrap()
+        };
+        assert!(additional_smoke_count <= MAX_SMOKE);
+        for _ in 0..additional_smoke_count {
+            let mut dir_index = dir.to_int() + self.rng.gen_range(1, 3);
+            if dir_index > 5 {
+                dir_index -= 6;
+            }
+            dir = Dir::from_int(dir_index);
+            let id = self.get_new_object_id();
+            self.do_core_event(&CoreEvent::Smoke {
+                id: id,
+                unit_id: Some(unit_id),
+                pos: Dir::get_neighbour_pos(pos, dir),
+            });
+        }
+        self.reaction_fire(unit_id);
+    }
+
+    /// Carries out the orders of all players together. In every step
+    /// each unit does one thing: moves a tile, fires or smokes.
+    ///
+    /// The player with the initiative is picked by the RNG,
+    /// so ties are broken the same way when the game is replayed.
+    /// Units act one player after another, starting with the player
+    /// with the initiative; a player's units act in order of their ids.
+    fn carry_out_orders(&mut self) {
+        let last_player_id = self.current_player_id;
+        let mut plans = mem::replace(&mut self.plans, HashMap::new());
+        let players_count = self.players.len() as i32;
+        let first_id = self.rng.gen_range(0, players_count);
+        let mut player_queues = Vec::new();
+        for i in 0 .. players_count {
+            let player_id = PlayerId{id: (first_id + i) % players_count};
+            let queue: Vec<_> = match plans.remove(&player_id) {
+                Some(plan) => plan.iter()
+                    .map(|(&unit_id, orders)| {
+                        (unit_id, orders.iter().cloned().collect::<VecDeque<_>>())
+                    })
+                    .collect(),
+                None => Vec::new(),
+            };
+            player_queues.push(queue);
+        }
+        let max_len = player_queues.iter().map(|queue| queue.len()).max().unwrap_or(0);
+        let mut queues = Vec::new();
+        for index in 0 .. max_len {
+            for player_queue in &player_queues {
+                if let Some(queue) = player_queue.get(index) {
+                    queues.push(queue.clone());
+                }
+            }
+        }
+        while queues.iter().any(|&(_, ref orders)| !orders.is_empty()) {
+            for &mut (unit_id, ref mut orders) in &mut queues {
+                if !orders.is_empty() {
+                    self.do_order_step(unit_id, orders);
+                }
+            }
+        }
+        self.current_player_id = last_player_id;
+        self.update_sectors();
+    }
+
+    fn do_order_step(&mut self, unit_id: UnitId, orders: &mut VecDeque<Order>) {
+        let player_id = match self.state.unit_opt(unit_id) {
+            Some(unit) if unit.is_alive => unit.player_id,
+            _ => {
+                orders.clear();
+                return;
+            },
+        };
+        // Hidden units are revealed to the player who bumps into them
+        self.current_player_id = player_id;
+        let is_done = match *orders.front_mut().unwrap() {
+            Order::Move{ref mut path, mode} => self.do_move_order_step(unit_id, path, mode),
+            Order::Attack{defender_id} => {
+                self.do_attack_order(unit_id, defender_id);
+                true
+            },
+            Order::Smoke{pos} => {
+                let can_smoke = {
+                    let unit = self.state.unit(unit_id);
+                    check_smoke(&self.db, &self.state, unit, pos).is_ok()
+                };
+                if can_smoke {
+                    self.smoke(unit_id, pos);
+                }
+                true
+            },
+        };
+        if is_done {
+            orders.pop_front();
+        }
+    }
+
+    /// Returns true when the unit is done with this move
+    fn do_move_order_step(
+        &mut self,
+        unit_id: UnitId,
+        path: &mut Vec<ExactPos>,
+        mode: MoveMode,
+    ) -> bool {
+        let from = self.state.unit(unit_id).pos;
+        // An earlier move was cut short
+        if path.len() < 2 || path[0] != from {
+            return true;
+        }
+        let to = path[1];
+        let can_place = {
+            let unit = self.state.unit(unit_id);
+            let unit_type = self.db.unit_type(unit.type_id);
+            can_place_unit(&self.state, unit_type, to)
+        };
+        if !can_place {
+            // Somebody else got there first
+            let player_id = self.state.unit(unit_id).player_id;
+            self.reveal_hidden_units_at(player_id, to.map_pos);
+            return true;
+        }
+        path.remove(0);
+        if !self.move_step(unit_id, from, to, mode) {
+            return true;
+        }
+        path.len() < 2
+    }
+
+    /// The defender may have moved out of sight or range since
+    /// the order was plotted, then the shot isn't fired
+    fn do_attack_order(&mut self, attacker_id: UnitId, defender_id: UnitId) {
+        let is_visible = match self.state.unit_opt(defender_id) {
+            Some(defender) => {
+                let player_id = self.state.unit(attacker_id).player_id;
+                self.players_info[&player_id].fow().is_visible(defender)
+            },
+            None => false,
+        };
+        if !is_visible {
+            return;
+        }
+        let event = self.command_attack_unit_to_event(
+            attacker_id, defender_id, event::FireMode::Active);
+        if let Some(ref event) = event {
+            self.do_core_event(event);
+            self.reaction_fire(attacker_id);
+        }
+    }
+
     pub fn do_command(&mut self, command: Command) {
-        self.check_command(&command);
+        assert!(!self.is_ai_turn(), "AI commands itself: see `do_ai_step`");
+        self.replay.commands.push(command.clone());
+        self.do_command_internal(command);
+    }
+
+    /// Like `do_command`, but a bad command is returned as an error
+    /// and doesn't change anything.
+    ///
+    /// On success returns the events that the command's author can see.
+    /// They are not left in the queue, so `get_event` won't return them.
+    pub fn try_command(
+        &mut self,
+        command: Command,
+    ) -> Result<Vec<CoreEvent>, CommandError> {
+        assert!(!self.is_ai_turn(), "AI commands itself: see `do_ai_step`");
+        self.check_command(&command)?;
+        let player_id = self.current_player_id;
+        let events_count = self.players_info[&player_id].events_count();
+        self.replay.commands.push(command.clone());
         self.simulation_step(command);
+        let i = self.players_info.get_mut(&player_id).unwrap();
+        Ok(i.split_events_off(events_count))
+    }
+
+    fn do_command_internal(&mut self, command: Command) {
+        if let Err(err) = self.check_command(&command) {
+            panic!("Bad command: {:?} ({:?})", err, command);
+        }
+        self.simulation_step(command);
+    }
+
+    /// Lets the current AI player think for about `budget` and do
+    /// the commands it has decided on. Call it again while `is_ai_turn`.
+    ///
+    /// AIs don't save their unfinished thoughts,
+    /// so games are saved between AI turns.
+    pub fn do_ai_step(&mut self, budget: Duration) {
+        let deadline = Instant::now() + budget;
+        self.do_ai_commands(Some(deadline));
+    }
+
+    /// Plays the current AI player's turn at once
+    pub fn do_ai_turn(&mut self) {
+        self.do_ai_commands(None);
+    }
+
+    /// Does the replay keep this player's commands?
+    fn is_recorded(&self, player_id: PlayerId) -> bool {
+        if self.players[player_id.id as usize].class == PlayerClass::Human {
+            return true;
+        }
+        match self.replay.options.ais.get(&player_id) {
+            Some(builder) => !builder.is_deterministic(),
+            None => false,
+        }
     }
 
-    fn do_ai(&mut self) {
+    /// Plays AI turns until it's the turn of a player whose commands
+    /// are recorded, does nothing if nobody's commands are recorded
+    fn do_ai_turns(&mut self) {
+        let is_anything_recorded = self.players.iter()
+            .any(|player| self.is_recorded(player.id));
+        if !is_anything_recorded {
+            return;
+        }
+        while self.is_ai_turn() && !self.is_recorded(self.player_id()) {
+            self.do_ai_turn();
+        }
+    }
+
+    /// Stops after the AI's turn or when the AI wants more time
+    fn do_ai_commands(&mut self, deadline: Option<Instant>) {
+        assert!(self.is_ai_turn());
+        let player_id = self.player_id();
         loop {
             while let Some(event) = self.get_event() {
-                self.ai.apply_event(&event);
+                self.ais.get_mut(&player_id).unwrap().apply_event(&event);
             }
-            let command = self.ai.get_command();
-            self.do_command(command.clone());
-            if command == Command::EndTurn {
+            let ai = self.ais.get_mut(&player_id).unwrap();
+            let command = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    let budget = if now < deadline {
+                        deadline - now
+                    } else {
+                        Duration::from_millis(0)
+                    };
+                    match ai.think(budget) {
+                        Some(command) => command,
+                        None => return,
+                    }
+                },
+                None => ai.get_command(),
+            };
+            if self.is_recorded(player_id) {
+                self.replay.commands.push(command.clone());
+            }
+            self.do_command_internal(command.clone());
+            if ends_turn(&command) {
+                return;
+            }
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
                 return;
             }
         }
@@ -496,11 +1067,6 @@ impl Core {
                 break;
             }
         }
-        if self.player().class == PlayerClass::Ai
-            && new_id == self.player_id()
-        {
-            self.do_ai();
-        }
     }
 
     fn filter_event(&mut self, player_id: PlayerId, event: &CoreEvent) {
@@ -508,10 +1074,35 @@ impl Core {
         i.filter_event(&self.state, event);
     }
 
+    /// Can the player know about this event at all?
+    ///
+    /// Must be called before the event is applied:
+    /// a killed defender is removed from the state.
+    fn is_event_known_to(&self, player_id: PlayerId, event: &CoreEvent) -> bool {
+        match *event {
+            CoreEvent::AttackUnit{ref attack_info} => {
+                // Fights between other players in the fog
+                let defender = self.state.unit(attack_info.defender_id);
+                let attacker_id = attack_info.attacker_id
+                    .expect("Core must know about everything");
+                let attacker = self.state.unit(attacker_id);
+                let fow = self.players_info[&player_id].fow();
+                defender.player_id == player_id
+                    || attacker.player_id == player_id
+                    || fow.is_visible(defender)
+            },
+            // Only the moving player has bumped into the hidden unit
+            CoreEvent::Reveal{..} => player_id == self.current_player_id,
+            _ => true,
+        }
+    }
+
     fn do_core_event(&mut self, event: &CoreEvent) {
-        self.state.apply_event(event);
         let player_ids: Vec<_> = self.players.iter()
-            .map(|player| player.id).collect();
+            .map(|player| player.id)
+            .filter(|&id| self.is_event_known_to(id, event))
+            .collect();
+        self.state.apply_event(event);
         for player_id in player_ids {
             self.filter_event(player_id, event);
         }
@@ -520,3 +1111,798 @@ impl Core {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap};
+    use std::time::{Duration};
+    use std::cell::{Cell};
+    use std::rc::{Rc};
+    use cgmath::{Vector2};
+    use Core;
+    use options::{Options, OptionsError, Difficulty, TurnMode};
+    use player::{PlayerId, PlayerClass, TeamId};
+    use scenario::{Scenario, test_scenario};
+    use event::{Command, CoreEvent, MoveMode, AttackInfo, FireMode};
+    use orders::{Order};
+    use position::{ExactPos, MapPos, SlotId};
+    use unit::{UnitId};
+    use serialize::{Writer, Reader, DeserializeError};
+    use check::{CommandError};
+    use ai::{Ai, AiBuilder, builtin_ai_builder};
+    use db::{Db};
+    use rng::{CoreRng};
+    use game_state::{State};
+    use filter::{filtered_unit};
+    use replay::{Replayer};
+
+    fn new_core(seed: u64) -> Core {
+        let players = vec![PlayerClass::Human, PlayerClass::Ai];
+        let mut core = Core::new(&Options {
+            seed: Some(seed),
+            .. Options::new(players, test_scenario())
+        });
+        let type_id = core.db().unit_type_id("soldier");
+        core.do_command(Command::CreateUnit {
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: 0, y: 0}},
+                slot_id: SlotId::Id(0),
+            },
+            type_id: type_id,
+        });
+        core
+    }
+
+    fn play_turns(core: &mut Core, turns_count: i32) -> Vec<String> {
+        let mut events = Vec::new();
+        for _ in 0..turns_count {
+            core.do_command(Command::EndTurn);
+            core.do_ai_turns();
+            while let Some(event) = core.get_event() {
+                events.push(format!("{:?}", event));
+            }
+        }
+        events
+    }
+
+    fn save_to_string(core: &Core) -> String {
+        let mut writer = Writer::new();
+        core.save(&mut writer);
+        writer.into_string()
+    }
+
+    fn load_from_string(text: &str) -> Core {
+        Core::load(&mut Reader::new(text), &builtin_ai_builder).unwrap()
+    }
+
+    #[test]
+    fn test_same_seed_same_events() {
+        assert_eq!(play_turns(&mut new_core(1), 10), play_turns(&mut new_core(1), 10));
+        assert_eq!(play_turns(&mut new_core(2), 10), play_turns(&mut new_core(2), 10));
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut core = new_core(3);
+        play_turns(&mut core, 3);
+        let saved = save_to_string(&core);
+        let mut loaded = load_from_string(&saved);
+        assert_eq!(save_to_string(&loaded), saved);
+        assert_eq!(play_turns(&mut core, 5), play_turns(&mut loaded, 5));
+    }
+
+    #[test]
+    fn test_try_command() {
+        let mut core = new_core(4);
+        while core.get_event().is_some() {}
+        let saved = save_to_string(&core);
+        let type_id = core.db().unit_type_id("soldier");
+        let result = core.try_command(Command::CreateUnit {
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: -1, y: 100}},
+                slot_id: SlotId::Id(0),
+            },
+            type_id: type_id,
+        });
+        assert_eq!(result.unwrap_err(), CommandError::BadPos);
+        assert_eq!(save_to_string(&core), saved);
+        let events = core.try_command(Command::EndTurn).unwrap();
+        assert!(!events.is_empty());
+        assert!(core.get_event_for(PlayerId{id: 0}).is_none());
+    }
+
+    /// Everything that the player may know
+    fn known_to(state: &State, player_id: PlayerId) -> String {
+        let units: Vec<_> = state.units().map(|(_, unit)| {
+            if unit.player_id == player_id {
+                unit.clone()
+            } else {
+                filtered_unit(unit)
+            }
+        }).collect();
+        let visible_tiles: Vec<_> = state.map().get_iter()
+            .filter(|&pos| state.is_ground_tile_visible(pos))
+            .collect();
+        format!("{:?} {:?} {:?} {:?} {:?} {:?}",
+            units,
+            visible_tiles,
+            state.objects(),
+            state.sectors(),
+            state.score().get(&player_id),
+            state.reinforcement_points().get(&player_id))
+    }
+
+    #[test]
+    fn test_snapshot() {
+        for seed in 0 .. 5 {
+            let mut core = new_core(seed);
+            let player_id = PlayerId{id: 0};
+            let options = core.replay().options.clone();
+            let mut state = State::new_partial(core.db().clone(), &options, player_id);
+            let type_id = core.db().unit_type_id("soldier");
+            for _ in 0 .. 15 {
+                while let Some(event) = core.get_event_for(player_id) {
+                    state.apply_event(&event);
+                }
+                // Gives the AI somebody to fight
+                for slot_id in 0 .. 3 {
+                    let command = Command::CreateUnit {
+                        pos: ExactPos {
+                            map_pos: MapPos{v: Vector2{x: 0, y: 0}},
+                            slot_id: SlotId::Id(slot_id),
+                        },
+                        type_id: type_id,
+                    };
+                    if let Ok(events) = core.try_command(command) {
+                        for event in &events {
+                            state.apply_event(event);
+                        }
+                    }
+                }
+                core.do_command(Command::EndTurn);
+                core.do_ai_turns();
+            }
+            // The snapshot must also have the events that are still queued
+            let mut copy = load_from_string(&save_to_string(&core));
+            while let Some(event) = copy.get_event_for(player_id) {
+                state.apply_event(&event);
+            }
+            let snapshot = core.snapshot_for(player_id);
+            assert!(core.get_event_for(player_id).is_none());
+            assert_eq!(known_to(&snapshot, player_id), known_to(&state, player_id));
+        }
+    }
+
+    #[test]
+    fn test_three_players() {
+        let mut scenario = test_scenario();
+        scenario.players_count = 3;
+        let players = vec![PlayerClass::Human, PlayerClass::Ai, PlayerClass::Ai];
+        let mut core = Core::new(&Options {
+            seed: Some(5),
+            .. Options::new(players, scenario)
+        });
+        core.do_command(Command::EndTurn);
+        core.do_ai_turns();
+        assert_eq!(core.player_id(), PlayerId{id: 0});
+        let mut new_ids = Vec::new();
+        while let Some(event) = core.get_event() {
+            if let CoreEvent::EndTurn{new_id, ..} = event {
+                new_ids.push(new_id.id);
+            }
+        }
+        assert_eq!(new_ids, vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn test_allies() {
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core = Core::new(&Options {
+            teams: vec![TeamId{id: 0}, TeamId{id: 0}],
+            shared_vision: true,
+            seed: Some(6),
+            .. Options::new(players, test_scenario())
+        });
+        let type_id = core.db().unit_type_id("soldier");
+        let pos = |x| ExactPos {
+            map_pos: MapPos{v: Vector2{x: x, y: 0}},
+            slot_id: SlotId::Id(0),
+        };
+        core.do_command(Command::CreateUnit{pos: pos(0), type_id: type_id});
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::CreateUnit{pos: pos(2), type_id: type_id});
+        let result = core.try_command(Command::AttackUnit {
+            attacker_id: UnitId{id: 2},
+            defender_id: UnitId{id: 1},
+        });
+        assert_eq!(result.unwrap_err(), CommandError::CanNotAttackAllies);
+    }
+
+    #[test]
+    fn test_ai_only() {
+        let mut core = Core::new(&Options {
+            seed: Some(7),
+            .. Options::new(vec![PlayerClass::Ai, PlayerClass::Ai], test_scenario())
+        });
+        for _ in 0..10 {
+            core.do_ai_turn();
+        }
+        assert_eq!(core.player_id(), PlayerId{id: 0});
+    }
+
+    #[test]
+    fn test_hard_ai_income() {
+        let players = vec![PlayerClass::Human, PlayerClass::Ai];
+        let mut core = Core::new(&Options {
+            difficulty: Difficulty::Hard,
+            seed: Some(9),
+            .. Options::new(players, test_scenario())
+        });
+        let human_id = PlayerId{id: 0};
+        let ai_id = PlayerId{id: 1};
+        assert_eq!(core.state().reinforcement_points()[&human_id].n, 10);
+        assert_eq!(core.state().reinforcement_points()[&ai_id].n, 15);
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.state().reinforcement_points()[&human_id].n, 20);
+    }
+
+    /// Never does anything, counts its turns
+    #[derive(Debug)]
+    struct PassiveAi {
+        turns: Rc<Cell<i32>>,
+    }
+
+    impl Ai for PassiveAi {
+        fn apply_event(&mut self, _: &CoreEvent) {}
+
+        fn get_command(&mut self) -> Command {
+            self.turns.set(self.turns.get() + 1);
+            Command::EndTurn
+        }
+
+        fn save(&self, _: &mut Writer) {}
+    }
+
+    #[derive(Debug)]
+    struct PassiveAiBuilder {
+        turns: Rc<Cell<i32>>,
+    }
+
+    impl AiBuilder for PassiveAiBuilder {
+        fn name(&self) -> &str {
+            "passive"
+        }
+
+        fn build(&self, _: Rc<Db>, _: &Options, _: PlayerId, _: CoreRng) -> Box<Ai> {
+            Box::new(PassiveAi{turns: self.turns.clone()})
+        }
+
+        fn load(&self, _: &mut Reader, _: Rc<Db>) -> Result<Box<Ai>, DeserializeError> {
+            Ok(Box::new(PassiveAi{turns: self.turns.clone()}))
+        }
+    }
+
+    fn new_passive_ai_core(turns: &Rc<Cell<i32>>) -> Core {
+        let mut ais: BTreeMap<_, Rc<AiBuilder>> = BTreeMap::new();
+        ais.insert(PlayerId{id: 1}, Rc::new(PassiveAiBuilder{turns: turns.clone()}));
+        let players = vec![PlayerClass::Human, PlayerClass::Ai];
+        Core::new(&Options {
+            ais: ais,
+            seed: Some(8),
+            .. Options::new(players, test_scenario())
+        })
+    }
+
+    #[test]
+    fn test_custom_ai() {
+        let turns = Rc::new(Cell::new(0));
+        let mut core = new_passive_ai_core(&turns);
+        for _ in 0..3 {
+            core.do_command(Command::EndTurn);
+            core.do_ai_turns();
+        }
+        assert_eq!(turns.get(), 3);
+        assert!(core.state().units().next().is_none());
+    }
+
+    #[test]
+    fn test_custom_ai_save_load() {
+        let turns = Rc::new(Cell::new(0));
+        let core = new_passive_ai_core(&turns);
+        let saved = save_to_string(&core);
+        let result = Core::load(&mut Reader::new(&saved), &builtin_ai_builder);
+        let err = DeserializeError::BadData("Unknown AI: passive".to_owned());
+        assert_eq!(result.unwrap_err(), err);
+        let builder = Rc::new(PassiveAiBuilder{turns: turns.clone()});
+        let find_ai = move |name: &str| -> Option<Rc<AiBuilder>> {
+            if name == builder.name() {
+                Some(builder.clone())
+            } else {
+                builtin_ai_builder(name)
+            }
+        };
+        let mut loaded = Core::load(&mut Reader::new(&saved), &find_ai).unwrap();
+        assert_eq!(save_to_string(&loaded), saved);
+        loaded.do_command(Command::EndTurn);
+        loaded.do_ai_turns();
+        assert_eq!(turns.get(), 1);
+    }
+
+    fn new_search_ai_core(seed: u64) -> Core {
+        let mut ais = BTreeMap::new();
+        ais.insert(PlayerId{id: 1}, builtin_ai_builder("search:10").unwrap());
+        let players = vec![PlayerClass::Human, PlayerClass::Ai];
+        Core::new(&Options {
+            ais: ais,
+            seed: Some(seed),
+            .. Options::new(players, test_scenario())
+        })
+    }
+
+    #[test]
+    fn test_search_ai_save_load() {
+        let mut core = new_search_ai_core(10);
+        play_turns(&mut core, 3);
+        let saved = save_to_string(&core);
+        let mut loaded = load_from_string(&saved);
+        assert_eq!(save_to_string(&loaded), saved);
+        assert_eq!(play_turns(&mut core, 5), play_turns(&mut loaded, 5));
+    }
+
+    #[test]
+    fn test_ai_steps() {
+        let mut core = new_search_ai_core(11);
+        let mut stepped_core = new_search_ai_core(11);
+        for _ in 0..3 {
+            core.do_command(Command::EndTurn);
+            core.do_ai_turns();
+            stepped_core.do_command(Command::EndTurn);
+            while stepped_core.is_ai_turn() {
+                stepped_core.do_ai_step(Duration::from_millis(0));
+            }
+        }
+        assert_eq!(save_to_string(&stepped_core), save_to_string(&core));
+    }
+
+    #[test]
+    fn test_ai_in_simultaneous_turns() {
+        let players = vec![PlayerClass::Human, PlayerClass::Ai];
+        let options = Options {
+            turn_mode: TurnMode::Simultaneous,
+            .. Options::new(players, test_scenario())
+        };
+        match Core::try_new(&options) {
+            Err(err) => assert_eq!(err, OptionsError::AiInSimultaneousTurns),
+            Ok(_) => panic!("The AI can't plot orders"),
+        }
+    }
+
+    /// Both players plot moves to the middle tile
+    /// and player 1 also plots a shot at where it expects the enemy
+    fn play_simultaneous_turn(seed: u64) -> (Core, Vec<String>) {
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core = Core::new(&Options {
+            turn_mode: TurnMode::Simultaneous,
+            seed: Some(seed),
+            .. Options::new(players, test_scenario())
+        });
+        let type_id = core.db().unit_type_id("soldier");
+        let pos = |x| ExactPos {
+            map_pos: MapPos{v: Vector2{x: x, y: 0}},
+            slot_id: SlotId::Id(0),
+        };
+        core.do_command(Command::CreateUnit{pos: pos(0), type_id: type_id});
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::CreateUnit{pos: pos(2), type_id: type_id});
+        core.do_command(Command::EndTurn);
+        let result = core.try_command(Command::Move {
+            unit_id: UnitId{id: 1},
+            path: vec![pos(0), pos(1)],
+            mode: MoveMode::Fast,
+        });
+        assert_eq!(result.unwrap_err(), CommandError::OrdersOnly);
+        let result = core.try_command(Command::LoadUnit {
+            transporter_id: UnitId{id: 1},
+            passenger_id: UnitId{id: 2},
+        });
+        assert_eq!(result.unwrap_err(), CommandError::NoTransportInSimultaneousTurns);
+        core.do_command(Command::SetOrders {
+            unit_id: UnitId{id: 1},
+            orders: vec![Order::Move{path: vec![pos(0), pos(1)], mode: MoveMode::Fast}],
+        });
+        core.do_command(Command::EndTurn);
+        assert_eq!(core.state().unit(UnitId{id: 1}).pos, pos(0));
+        let saved = save_to_string(&core);
+        let mut core = load_from_string(&saved);
+        assert_eq!(save_to_string(&core), saved);
+        let slot = ExactPos{slot_id: SlotId::Id(1), .. pos(1)};
+        core.do_command(Command::SetOrders {
+            unit_id: UnitId{id: 2},
+            orders: vec![Order::Move{path: vec![pos(2), slot], mode: MoveMode::Fast}],
+        });
+        core.do_command(Command::EndTurn);
+        let mut events = Vec::new();
+        for &id in &[0, 1] {
+            while let Some(event) = core.get_event_for(PlayerId{id: id}) {
+                events.push(format!("{:?}", event));
+            }
+        }
+        (core, events)
+    }
+
+    #[test]
+    fn test_simultaneous_turns() {
+        let (core, events) = play_simultaneous_turn(12);
+        assert_eq!(play_simultaneous_turn(12).1, events);
+        assert_eq!(core.player_id(), PlayerId{id: 0});
+        let moved_count = (1 .. 3)
+            .filter(|&id| core.state().unit_opt(UnitId{id: id})
+                .map_or(true, |unit| unit.pos.map_pos.v.x == 1))
+            .count();
+        assert_eq!(moved_count, 2);
+        assert!(events.iter().any(|event| event.contains("Reactive")));
+    }
+
+    fn line_pos(x: i32, slot_id: SlotId) -> ExactPos {
+        ExactPos {
+            map_pos: MapPos{v: Vector2{x: x, y: 0}},
+            slot_id: slot_id,
+        }
+    }
+
+    /// Player 0's soldier can't see player 1's helicopter
+    /// and player 2's soldier at the other end of the map
+    fn new_fog_core() -> Core {
+        let text = "
+            size 9 1
+            target_score 5
+            players_count 3
+            terrain
+                .........
+            reinforcement_sector 0 0 some 0
+            reinforcement_sector 8 0 some 1
+            reinforcement_sector 7 0 some 2
+        ";
+        let scenario = text.parse::<Scenario>().unwrap();
+        let players = vec![PlayerClass::Human; 3];
+        let mut core = Core::new(&Options {
+            seed: Some(3),
+            .. Options::new(players, scenario)
+        });
+        let pos = line_pos;
+        let soldier_id = core.db().unit_type_id("soldier");
+        let helicopter_id = core.db().unit_type_id("helicopter");
+        core.do_command(Command::CreateUnit {
+            pos: pos(0, SlotId::Id(0)),
+            type_id: soldier_id,
+        });
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::CreateUnit {
+            pos: pos(8, SlotId::Air),
+            type_id: helicopter_id,
+        });
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::CreateUnit {
+            pos: pos(7, SlotId::Id(0)),
+            type_id: soldier_id,
+        });
+        core.do_command(Command::EndTurn);
+        for id in 0 .. 3 {
+            while core.get_event_for(PlayerId{id: id}).is_some() {}
+        }
+        core
+    }
+
+    fn events_for(core: &mut Core, player_id: PlayerId) -> Vec<CoreEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = core.get_event_for(player_id) {
+            events.push(event);
+        }
+        events
+    }
+
+    #[test]
+    fn test_reveal() {
+        let mut core = new_fog_core();
+        let helicopter = core.state().unit(UnitId{id: 2}).clone();
+        assert!(!core.players_info[&PlayerId{id: 0}].fow().is_visible(&helicopter));
+        core.do_core_event(&CoreEvent::Reveal{unit_info: helicopter.clone()});
+        // Only once: the unit must not be shown again as a passive enemy
+        let events = events_for(&mut core, PlayerId{id: 0});
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            CoreEvent::ShowUnit{ref unit_info} => assert_eq!(unit_info.id, helicopter.id),
+            ref event => panic!("Unexpected event: {:?}", event),
+        }
+        // The air tile is revealed, not the ground one under it
+        let fow = core.players_info[&PlayerId{id: 0}].fow();
+        assert!(fow.is_visible(&helicopter));
+        assert!(!fow.is_ground_tile_visible(helicopter.pos.map_pos));
+        // Player 2 hasn't bumped into anything
+        assert!(events_for(&mut core, PlayerId{id: 2}).is_empty());
+    }
+
+    #[test]
+    fn test_fight_in_fog() {
+        let mut core = new_fog_core();
+        // Player 2's soldier gets its attack points
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::EndTurn);
+        for id in 0 .. 3 {
+            events_for(&mut core, PlayerId{id: id});
+        }
+        core.do_core_event(&CoreEvent::AttackUnit {
+            attack_info: AttackInfo {
+                attacker_id: Some(UnitId{id: 3}),
+                defender_id: UnitId{id: 2},
+                mode: FireMode::Active,
+                killed: 0,
+                suppression: 10,
+                remove_move_points: false,
+                is_ambush: false,
+                is_inderect: false,
+                leave_wrecks: false,
+            },
+        });
+        assert!(events_for(&mut core, PlayerId{id: 0}).is_empty());
+        assert_eq!(events_for(&mut core, PlayerId{id: 1}).len(), 1);
+    }
+
+    /// Player 0 has a truck and a soldier that can get into it,
+    /// player 1 watches them with the unit of `enemy_type`
+    fn new_transport_core(seed: u64, enemy_type: &str) -> Core {
+        let text = "
+            size 4 1
+            target_score 5
+            players_count 2
+            terrain
+                ....
+            reinforcement_sector 0 0 some 0
+            reinforcement_sector 3 0 some 1
+        ";
+        let scenario = text.parse::<Scenario>().unwrap();
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core = Core::new(&Options {
+            seed: Some(seed),
+            .. Options::new(players, scenario)
+        });
+        let truck_id = core.db().unit_type_id("truck");
+        let soldier_id = core.db().unit_type_id("soldier");
+        let enemy_type_id = core.db().unit_type_id(enemy_type);
+        core.do_command(Command::CreateUnit {
+            pos: line_pos(0, SlotId::Id(0)),
+            type_id: truck_id,
+        });
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::CreateUnit {
+            pos: line_pos(3, SlotId::Id(0)),
+            type_id: enemy_type_id,
+        });
+        core.do_command(Command::EndTurn);
+        // The passenger's id is bigger than the enemy's
+        core.do_command(Command::CreateUnit {
+            pos: line_pos(0, SlotId::Id(1)),
+            type_id: soldier_id,
+        });
+        // Everybody gets their move and attack points
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::EndTurn);
+        for id in 0 .. 2 {
+            events_for(&mut core, PlayerId{id: id});
+        }
+        core
+    }
+
+    #[test]
+    fn test_enemy_passengers() {
+        // Unarmed, so it can't shoot at the soldier
+        let mut core = new_transport_core(4, "truck");
+        let enemy_id = PlayerId{id: 1};
+        let truck_id = UnitId{id: 1};
+        let passenger_id = UnitId{id: 3};
+        core.do_command(Command::LoadUnit {
+            transporter_id: truck_id,
+            passenger_id: passenger_id,
+        });
+        // The enemy only sees the soldier disappear
+        let events = events_for(&mut core, enemy_id);
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            CoreEvent::HideUnit{unit_id} => assert_eq!(unit_id, passenger_id),
+            ref event => panic!("Unexpected event: {:?}", event),
+        }
+        let visible_enemies = core.players_info[&enemy_id].visible_enemies().clone();
+        assert!(visible_enemies.contains(&truck_id));
+        assert!(!visible_enemies.contains(&passenger_id));
+        core.do_command(Command::UnloadUnit {
+            transporter_id: truck_id,
+            passenger_id: passenger_id,
+            pos: line_pos(1, SlotId::Id(0)),
+        });
+        match events_for(&mut core, enemy_id)[0] {
+            CoreEvent::UnloadUnit{ref unit_info, ..} => {
+                assert_eq!(unit_info.id, passenger_id);
+                assert!(!unit_info.is_loaded);
+                assert!(unit_info.move_points.is_none());
+            },
+            ref event => panic!("Unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_loaded_transporter_killed_by_reaction_fire() {
+        let truck_id = UnitId{id: 1};
+        let passenger_id = UnitId{id: 3};
+        let mut kills_count = 0;
+        for seed in 0 .. 10 {
+            let mut core = new_transport_core(seed, "heavy_tank");
+            core.do_command(Command::LoadUnit {
+                transporter_id: truck_id,
+                passenger_id: passenger_id,
+            });
+            let path = vec![line_pos(0, SlotId::Id(0)), line_pos(1, SlotId::Id(0))];
+            core.do_command(Command::Move {
+                unit_id: truck_id,
+                path: path,
+                mode: MoveMode::Fast,
+            });
+            if !core.state().unit(truck_id).is_alive {
+                assert!(core.state().unit_opt(passenger_id).is_none());
+                kills_count += 1;
+            }
+        }
+        assert!(kills_count > 0);
+    }
+
+    /// Player 0's truck can tow a gun that player 1's soldier sees,
+    /// but the soldier can't see the truck before it comes to the gun
+    fn new_towing_core() -> Core {
+        let text = "
+            size 9 1
+            target_score 5
+            players_count 2
+            terrain
+                .........
+            reinforcement_sector 1 0 some 0
+            reinforcement_sector 2 0 some 0
+            reinforcement_sector 8 0 some 1
+        ";
+        let scenario = text.parse::<Scenario>().unwrap();
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core = Core::new(&Options {
+            seed: Some(2),
+            .. Options::new(players, scenario)
+        });
+        let truck_id = core.db().unit_type_id("truck");
+        let gun_id = core.db().unit_type_id("field_gun");
+        let soldier_id = core.db().unit_type_id("soldier");
+        core.do_command(Command::CreateUnit {
+            pos: line_pos(1, SlotId::Id(0)),
+            type_id: truck_id,
+        });
+        core.do_command(Command::CreateUnit {
+            pos: line_pos(2, SlotId::Id(0)),
+            type_id: gun_id,
+        });
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::CreateUnit {
+            pos: line_pos(8, SlotId::Id(0)),
+            type_id: soldier_id,
+        });
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::EndTurn);
+        for id in 0 .. 2 {
+            events_for(&mut core, PlayerId{id: id});
+        }
+        core
+    }
+
+    #[test]
+    fn test_attach_seen_by_enemy() {
+        let mut core = new_towing_core();
+        let enemy_id = PlayerId{id: 1};
+        let truck_id = UnitId{id: 1};
+        let gun_id = UnitId{id: 2};
+        core.do_command(Command::Attach {
+            transporter_id: truck_id,
+            attached_unit_id: gun_id,
+        });
+        let events = events_for(&mut core, enemy_id);
+        assert_eq!(events.len(), 2);
+        match events[0] {
+            CoreEvent::ShowUnit{ref unit_info} => assert_eq!(unit_info.id, truck_id),
+            ref event => panic!("Unexpected event: {:?}", event),
+        }
+        match events[1] {
+            CoreEvent::Attach{attached_unit_id, ..} => assert_eq!(attached_unit_id, gun_id),
+            ref event => panic!("Unexpected event: {:?}", event),
+        }
+        let visible_enemies = core.players_info[&enemy_id].visible_enemies().clone();
+        assert!(visible_enemies.contains(&truck_id));
+        assert!(visible_enemies.contains(&gun_id));
+    }
+
+    fn next_time_left(core: &mut Core, player_id: PlayerId) -> Option<(u64, Option<u64>)> {
+        match core.get_event_for(player_id) {
+            Some(CoreEvent::TimeLeft{turn, game, ..}) => {
+                Some((turn.as_secs(), game.map(|game| game.as_secs())))
+            },
+            Some(event) => panic!("Unexpected event: {:?}", event),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn test_time_limits() {
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core = Core::new(&Options {
+            turn_time_limit: Some(Duration::from_secs(30)),
+            game_time_limit: Some(Duration::from_secs(50)),
+            seed: Some(1),
+            .. Options::new(players, test_scenario())
+        });
+        let player_id = PlayerId{id: 0};
+        assert_eq!(next_time_left(&mut core, player_id), Some((30, Some(50))));
+        core.pass_time(Duration::from_secs(5));
+        assert_eq!(next_time_left(&mut core, player_id), Some((25, Some(45))));
+        core.pass_time(Duration::from_secs(2));
+        assert_eq!(next_time_left(&mut core, player_id), None);
+        assert_eq!(core.try_command(Command::TimeOut).unwrap_err(), CommandError::TimeIsNotOut);
+        // The clock doesn't end the turn by itself
+        core.pass_time(Duration::from_secs(30));
+        assert_eq!(next_time_left(&mut core, player_id), Some((0, Some(20))));
+        assert!(core.is_time_out());
+        assert_eq!(core.player_id(), player_id);
+        let time = Duration::from_secs(30);
+        assert_eq!(core.replay().commands, vec![Command::PassTime{time: time}]);
+        core.do_command(Command::TimeOut);
+        assert_eq!(core.player_id(), PlayerId{id: 1});
+        core.do_command(Command::EndTurn);
+        // Only the rest of the game's time is left
+        assert_eq!(core.turn_time_left(), Some(Duration::from_secs(20)));
+        let mut loaded = load_from_string(&save_to_string(&core));
+        assert_eq!(loaded.turn_time_left(), Some(Duration::from_secs(20)));
+        loaded.pass_time(Duration::from_secs(20));
+        loaded.do_command(Command::TimeOut);
+        loaded.do_command(Command::EndTurn);
+        // The player is out of time before the turn has begun
+        assert_eq!(loaded.player_id(), player_id);
+        assert!(loaded.is_time_out());
+        loaded.do_command(Command::TimeOut);
+        assert_eq!(loaded.player_id(), PlayerId{id: 1});
+        assert_eq!(loaded.turn_time_left(), Some(Duration::from_secs(30)));
+        let commands = loaded.replay().commands.clone();
+        let time = Duration::from_secs(20);
+        assert_eq!(&commands[commands.len() - 4 ..], &[
+            Command::PassTime{time: time},
+            Command::TimeOut,
+            Command::EndTurn,
+            Command::TimeOut,
+        ]);
+        // Replays run the clocks too
+        let mut replayer = Replayer::new(loaded.replay());
+        let mut timeouts_count = 0;
+        while let Some(step) = replayer.step() {
+            for event in &step.events[&player_id] {
+                if let CoreEvent::TimeLeft{turn, ..} = *event {
+                    if turn == Duration::from_secs(0) {
+                        timeouts_count += 1;
+                    }
+                }
+            }
+        }
+        assert_eq!(timeouts_count, 3);
+        // Nobody has any time, the turns go round as the players time out
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core = Core::new(&Options {
+            game_time_limit: Some(Duration::from_secs(0)),
+            .. Options::new(players, test_scenario())
+        });
+        assert_eq!(core.player_id(), player_id);
+        assert!(core.is_time_out());
+        core.do_command(Command::TimeOut);
+        assert!(core.is_time_out());
+        assert_eq!(core.player_id(), PlayerId{id: 1});
+    }
+}
@@ -4,6 +4,7 @@ use cgmath::{Vector2, Array};
 use types::{Size2};
 use dir::{Dir, DirIter, dirs};
 use position::{MapPos};
+use serialize::{Serialize, Deserialize, Writer, Reader, DeserializeError};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Distance{pub n: i32}
@@ -66,6 +67,35 @@ impl<T: Clone + Default> Map<T> {
     }
 }
 
+impl<T: Serialize> Serialize for Map<T> {
+    fn serialize(&self, w: &mut Writer) {
+        self.size.serialize(w);
+        for (i, tile) in self.tiles.iter().enumerate() {
+            if i as i32 % self.size.w == 0 {
+                w.new_line();
+            }
+            tile.serialize(w);
+        }
+    }
+}
+
+impl<T: Deserialize> Deserialize for Map<T> {
+    fn deserialize(r: &mut Reader) -> Result<Map<T>, DeserializeError> {
+        let size: Size2 = r.read()?;
+        if size.w <= 0 || size.h <= 0 {
+            return Err(DeserializeError::BadToken(format!("{}x{}", size.w, size.h)));
+        }
+        let mut tiles = Vec::new();
+        for _ in 0 .. size.w * size.h {
+            tiles.push(r.read()?);
+        }
+        Ok(Map {
+            tiles: tiles,
+            size: size,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct MapPosIter {
     cursor: MapPos,
@@ -0,0 +1,171 @@
+//! Enemies that the AI has seen and then lost from view
+
+use std::collections::{BTreeMap};
+use std::collections::btree_map::{Values};
+use game_state::{State};
+use unit::{Unit, UnitId};
+use event::{CoreEvent};
+use player::{PlayerId};
+
+/// Confidence in a unit that has just disappeared, in percents
+pub const FULL_CONFIDENCE: i32 = 100;
+
+/// Every turn a hidden unit may have moved away
+const CONFIDENCE_DECAY_PER_TURN: i32 = 25;
+
+#[derive(Clone, Debug)]
+pub struct RememberedUnit {
+    /// The unit as it was seen the last time
+    pub unit: Unit,
+
+    /// Chance that the unit is still there, in percents
+    pub confidence: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct EnemyMemory {
+    player_id: PlayerId,
+    units: BTreeMap<UnitId, RememberedUnit>,
+}
+
+impl EnemyMemory {
+    pub fn new(player_id: PlayerId) -> EnemyMemory {
+        EnemyMemory {
+            player_id: player_id,
+            units: BTreeMap::new(),
+        }
+    }
+
+    pub fn from_units(player_id: PlayerId, units: Vec<RememberedUnit>) -> EnemyMemory {
+        EnemyMemory {
+            player_id: player_id,
+            units: units.into_iter()
+                .map(|remembered| (remembered.unit.id, remembered))
+                .collect(),
+        }
+    }
+
+    pub fn player_id(&self) -> PlayerId {
+        self.player_id
+    }
+
+    pub fn units(&self) -> Values<UnitId, RememberedUnit> {
+        self.units.values()
+    }
+
+    /// Must be called before the event is applied to the player's state
+    pub fn apply_event(&mut self, state: &State, event: &CoreEvent) {
+        match *event {
+            CoreEvent::HideUnit{unit_id} => {
+                let unit = state.unit_info_opt(unit_id).expect("Bad unit id");
+                if unit.is_alive && !state.is_ally(unit.player_id, self.player_id) {
+                    self.units.insert(unit_id, RememberedUnit {
+                        unit: unit.clone(),
+                        confidence: FULL_CONFIDENCE,
+                    });
+                }
+            },
+            CoreEvent::ShowUnit{ref unit_info} |
+            CoreEvent::CreateUnit{ref unit_info} |
+            CoreEvent::UnloadUnit{ref unit_info, ..} => {
+                self.units.remove(&unit_info.id);
+            },
+            CoreEvent::EndTurn{new_id, ..} if new_id == self.player_id => {
+                for remembered in self.units.values_mut() {
+                    remembered.confidence -= CONFIDENCE_DECAY_PER_TURN;
+                }
+                self.forget(|remembered| remembered.confidence <= 0);
+            },
+            _ => {},
+        }
+    }
+
+    /// Forgets the units that would be visible where they were seen
+    /// if they were still there
+    pub fn forget_visible(&mut self, state: &State) {
+        self.forget(|remembered| {
+            state.is_unit_visible_at(&remembered.unit, remembered.unit.pos)
+        });
+    }
+
+    fn forget<F: Fn(&RememberedUnit) -> bool>(&mut self, f: F) {
+        let ids: Vec<UnitId> = self.units.iter()
+            .filter(|&(_, remembered)| f(remembered))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in ids {
+            self.units.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use Core;
+    use options::{Options};
+    use scenario::{test_scenario};
+    use player::{PlayerId, PlayerClass};
+    use position::{MapPos, ExactPos, SlotId};
+    use event::{Command, CoreEvent};
+    use unit::{UnitId};
+    use memory::{EnemyMemory, FULL_CONFIDENCE};
+
+    /// Player 0 has a soldier at one end of the map, player 1 at the other
+    fn new_core() -> Core {
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core = Core::new(&Options::new(players, test_scenario()));
+        let soldier_id = core.db().unit_type_id("soldier");
+        for &x in &[0, 2] {
+            core.do_command(Command::CreateUnit {
+                pos: ExactPos {
+                    map_pos: MapPos{v: Vector2{x: x, y: 0}},
+                    slot_id: SlotId::Id(0),
+                },
+                type_id: soldier_id,
+            });
+            core.do_command(Command::EndTurn);
+        }
+        core
+    }
+
+    fn end_turns(memory: &mut EnemyMemory, core: &Core, count: i32) {
+        let (us, them) = (PlayerId{id: 0}, PlayerId{id: 1});
+        for _ in 0..count {
+            memory.apply_event(core.state(), &CoreEvent::EndTurn{old_id: us, new_id: them});
+            memory.apply_event(core.state(), &CoreEvent::EndTurn{old_id: them, new_id: us});
+        }
+    }
+
+    fn confidence(memory: &EnemyMemory, unit_id: UnitId) -> Option<i32> {
+        memory.units()
+            .find(|remembered| remembered.unit.id == unit_id)
+            .map(|remembered| remembered.confidence)
+    }
+
+    #[test]
+    fn test_remember_fade_and_refresh() {
+        let core = new_core();
+        let our_id = UnitId{id: 1};
+        let enemy_id = UnitId{id: 2};
+        let mut memory = EnemyMemory::new(PlayerId{id: 0});
+        // Own units are never remembered
+        memory.apply_event(core.state(), &CoreEvent::HideUnit{unit_id: our_id});
+        assert_eq!(confidence(&memory, our_id), None);
+        memory.apply_event(core.state(), &CoreEvent::HideUnit{unit_id: enemy_id});
+        assert_eq!(confidence(&memory, enemy_id), Some(FULL_CONFIDENCE));
+        end_turns(&mut memory, &core, 2);
+        assert_eq!(confidence(&memory, enemy_id), Some(50));
+        // Seen again: no need to remember it while it's visible
+        let enemy = core.state().unit(enemy_id).clone();
+        memory.apply_event(core.state(), &CoreEvent::ShowUnit{unit_info: enemy});
+        assert_eq!(confidence(&memory, enemy_id), None);
+        // Lost again: the memory is fresh
+        memory.apply_event(core.state(), &CoreEvent::HideUnit{unit_id: enemy_id});
+        assert_eq!(confidence(&memory, enemy_id), Some(FULL_CONFIDENCE));
+        end_turns(&mut memory, &core, 3);
+        assert_eq!(confidence(&memory, enemy_id), Some(25));
+        end_turns(&mut memory, &core, 1);
+        assert_eq!(confidence(&memory, enemy_id), None);
+    }
+}
@@ -1,6 +1,6 @@
 use std::cmp;
 use std::sync::mpsc::{Receiver};
-use rand::{thread_rng, Rng};
+use rand::{Rng};
 
 pub fn clamp<T>(n: T, min: T, max: T) -> T
     where T: Copy + cmp::PartialOrd
@@ -13,9 +13,9 @@ pub fn clamp<T>(n: T, min: T, max: T) -> T
     }
 }
 
-pub fn get_shuffled_indices<T>(v: &[T]) -> Vec<usize> {
+pub fn get_shuffled_indices<T, R: Rng>(rng: &mut R, v: &[T]) -> Vec<usize> {
     let mut indices: Vec<_> = (0..v.len()).collect();
-    thread_rng().shuffle(&mut indices);
+    rng.shuffle(&mut indices);
     indices
 }
 
@@ -39,6 +39,7 @@ pub fn opt_rx_collect<T>(rx: &Option<Receiver<T>>) -> Vec<T> {
 mod tests {
     use std::sync::mpsc::{channel};
     use misc::{clamp, get_shuffled_indices, rx_collect, opt_rx_collect};
+    use rng::{CoreRng};
 
     #[test]
     fn test_clamp() {
@@ -52,7 +53,7 @@ mod tests {
     #[test]
     fn test_shuffle_touches_all_fields() {
         let mut v = [false; 10];
-        let indices = get_shuffled_indices(&v);
+        let indices = get_shuffled_indices(&mut CoreRng::new(0), &v);
         for i in indices {
             v[i] = true;
         }
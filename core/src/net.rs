@@ -0,0 +1,785 @@
+//! Games over TCP.
+//!
+//! The server owns the `Core` and checks every command, clients get
+//! only the events that their player may see, so they never have
+//! the full `State` and the seed.
+//!
+//! A client starts from a snapshot of what its player knows,
+//! so a player who has lost the connection can join again.
+//! The first `Welcome` of a seat carries its secret and only a client
+//! that says it in `Hello` gets the seat back.
+//!
+//! Every message is the length of its text in bytes on its own line
+//! followed by the text written by `Writer`.
+
+use std::{fmt, error, io, thread};
+use std::collections::{HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs, Shutdown};
+use std::rc::{Rc};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+use rand::{thread_rng, Rng};
+use Core;
+use db::{Db};
+use event::{CoreEvent, Command};
+use options::{Options};
+use game_state::{State};
+use player::{PlayerId, PlayerClass};
+use serialize::{Serialize, Deserialize, Writer, Reader, DeserializeError, read_options};
+
+/// Bump this when the layout of any message changes
+pub const PROTOCOL_VERSION: i32 = 6;
+
+pub const DEFAULT_PORT: u16 = 7412;
+
+/// How often the server runs the clock of a timed turn and answers
+/// the other clients while it waits for the current player's command
+const WAIT_STEP_MS: u64 = 100;
+
+/// How long a new client may take to say `Hello`
+const HELLO_TIMEOUT_MS: u64 = 10_000;
+
+/// Longest message that anybody may send, a `Welcome` with a big map
+/// is far shorter. Keeps a bad peer from making us allocate gigabytes.
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// The length line is just a number
+const MAX_LEN_LINE_LEN: u64 = 32;
+
+#[derive(Debug)]
+pub enum NetError {
+    Io(io::Error),
+    Deserialize(DeserializeError),
+    Refused(String),
+    UnexpectedMessage,
+    Disconnected,
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NetError::Io(ref err) => write!(f, "Network error: {}", err),
+            NetError::Deserialize(ref err) => write!(f, "Bad message: {}", err),
+            NetError::Refused(ref reason) => write!(f, "Refused: {}", reason),
+            NetError::UnexpectedMessage => write!(f, "Unexpected message"),
+            NetError::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+
+impl error::Error for NetError {
+    fn description(&self) -> &str {
+        "network error"
+    }
+}
+
+impl From<io::Error> for NetError {
+    fn from(err: io::Error) -> NetError {
+        NetError::Io(err)
+    }
+}
+
+impl From<DeserializeError> for NetError {
+    fn from(err: DeserializeError) -> NetError {
+        NetError::Deserialize(err)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    /// The first message of every client,
+    /// `secret` is `None` for a new seat
+    Hello{version: i32, secret: Option<String>},
+
+    Command(Command),
+}
+
+/// Answer to `Hello`, `options.seed` is always `None`
+/// and `options.ais` is empty: the AIs are the server's business.
+#[derive(Clone, Debug)]
+pub struct Welcome {
+    pub player_id: PlayerId,
+    pub current_player_id: PlayerId,
+
+    /// Gets the seat back after a lost connection
+    pub secret: String,
+
+    pub options: Options,
+    pub db: Rc<Db>,
+
+    /// Snapshot of what the player knows
+    pub state: State,
+}
+
+#[derive(Clone, Debug)]
+pub enum ServerMessage {
+    Welcome(Box<Welcome>),
+
+    /// The server doesn't want this client, the connection is closed
+    Refused{reason: String},
+
+    Event(CoreEvent),
+
+    /// The command was bad and changed nothing
+    Rejected{reason: String},
+}
+
+impl Serialize for ClientMessage {
+    fn serialize(&self, w: &mut Writer) {
+        match *self {
+            ClientMessage::Hello{version, ref secret} => {
+                w.write_tag("Hello");
+                version.serialize(w);
+                secret.serialize(w);
+            },
+            ClientMessage::Command(ref command) => {
+                w.write_tag("Command");
+                command.serialize(w);
+            },
+        }
+    }
+}
+
+impl Deserialize for ClientMessage {
+    fn deserialize(r: &mut Reader) -> Result<ClientMessage, DeserializeError> {
+        let tag = r.read_tag()?;
+        let message = match tag.as_str() {
+            "Hello" => ClientMessage::Hello{version: r.read()?, secret: r.read()?},
+            "Command" => ClientMessage::Command(r.read()?),
+            _ => return Err(DeserializeError::BadTag(tag)),
+        };
+        Ok(message)
+    }
+}
+
+impl Serialize for ServerMessage {
+    fn serialize(&self, w: &mut Writer) {
+        match *self {
+            ServerMessage::Welcome(ref welcome) => {
+                w.write_tag("Welcome");
+                welcome.player_id.serialize(w);
+                welcome.current_player_id.serialize(w);
+                welcome.secret.serialize(w);
+                w.new_line();
+                welcome.options.serialize(w);
+                w.new_line();
+                welcome.db.save(w);
+                w.new_line();
+                welcome.state.save(w);
+            },
+            ServerMessage::Refused{ref reason} => {
+                w.write_tag("Refused");
+                reason.serialize(w);
+            },
+            ServerMessage::Event(ref event) => {
+                w.write_tag("Event");
+                event.serialize(w);
+            },
+            ServerMessage::Rejected{ref reason} => {
+                w.write_tag("Rejected");
+                reason.serialize(w);
+            },
+        }
+    }
+}
+
+impl Deserialize for ServerMessage {
+    fn deserialize(r: &mut Reader) -> Result<ServerMessage, DeserializeError> {
+        let tag = r.read_tag()?;
+        let message = match tag.as_str() {
+            "Welcome" => {
+                let player_id = r.read()?;
+                let current_player_id = r.read()?;
+                let secret = r.read()?;
+                // No AIs to look up, see `ServerMessage::Welcome`
+                let options = read_options(r, &|_| None)?;
+                let db = Rc::new(Db::load(r)?);
+                let state = State::load(r, db.clone())?;
+                ServerMessage::Welcome(Box::new(Welcome {
+                    player_id: player_id,
+                    current_player_id: current_player_id,
+                    secret: secret,
+                    options: options,
+                    db: db,
+                    state: state,
+                }))
+            },
+            "Refused" => ServerMessage::Refused{reason: r.read()?},
+            "Event" => ServerMessage::Event(r.read()?),
+            "Rejected" => ServerMessage::Rejected{reason: r.read()?},
+            _ => return Err(DeserializeError::BadTag(tag)),
+        };
+        Ok(message)
+    }
+}
+
+pub fn send<T: Serialize>(stream: &mut Write, message: &T) -> Result<(), NetError> {
+    let mut w = Writer::new();
+    message.serialize(&mut w);
+    let text = w.into_string();
+    write!(stream, "{}\n{}", text.len(), text)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Blocks until the whole message is here
+pub fn receive_text(stream: &mut BufRead) -> Result<String, NetError> {
+    let mut line = String::new();
+    if (&mut *stream).take(MAX_LEN_LINE_LEN).read_line(&mut line)? == 0 {
+        return Err(NetError::Disconnected);
+    }
+    let len: usize = match line.trim().parse() {
+        Ok(len) => len,
+        Err(_) => return Err(DeserializeError::BadToken(line).into()),
+    };
+    if len > MAX_MESSAGE_LEN {
+        let text = format!("Message of {} bytes is too long", len);
+        return Err(DeserializeError::BadData(text).into());
+    }
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf)?;
+    match String::from_utf8(buf) {
+        Ok(text) => Ok(text),
+        Err(_) => Err(DeserializeError::BadData("Not UTF-8".to_owned()).into()),
+    }
+}
+
+pub fn parse<T: Deserialize>(text: &str) -> Result<T, NetError> {
+    let mut r = Reader::new(text);
+    let message = r.read()?;
+    if !r.is_finished() {
+        return Err(DeserializeError::BadData("Trailing data".to_owned()).into());
+    }
+    Ok(message)
+}
+
+pub fn receive<T: Deserialize>(stream: &mut BufRead) -> Result<T, NetError> {
+    parse(&receive_text(stream)?)
+}
+
+#[derive(Debug)]
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Result<Connection, NetError> {
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+}
+
+/// Owns the game and plays the AI turns
+#[derive(Debug)]
+pub struct Server {
+    core: Core,
+    options: Options,
+    connections: HashMap<PlayerId, Connection>,
+    secrets: HashMap<PlayerId, String>,
+}
+
+impl Server {
+    /// Waits for a client for every human player,
+    /// they get the player ids in order of connection
+    pub fn accept(core: Core, listener: &TcpListener) -> Result<Server, NetError> {
+        let mut options = core.replay().options.clone();
+        options.seed = None;
+        options.ais.clear();
+        let mut server = Server {
+            core: core,
+            options: options,
+            connections: HashMap::new(),
+            secrets: HashMap::new(),
+        };
+        server.wait_for_players(listener)?;
+        Ok(server)
+    }
+
+    pub fn core(&self) -> &Core {
+        &self.core
+    }
+
+    /// Gives every human player who has no client the next new one,
+    /// a seat that had a client before waits for the client with its secret
+    pub fn wait_for_players(&mut self, listener: &TcpListener) -> Result<(), NetError> {
+        loop {
+            let free_ids: Vec<PlayerId> = self.core.players.iter()
+                .filter(|player| player.class == PlayerClass::Human)
+                .map(|player| player.id)
+                .filter(|player_id| !self.connections.contains_key(player_id))
+                .collect();
+            if free_ids.is_empty() {
+                return Ok(());
+            }
+            let (stream, _) = listener.accept()?;
+            // A client that sends garbage, hangs up or says nothing
+            // loses only its own connection
+            if let Ok((player_id, connection)) = self.greet(stream, &free_ids) {
+                self.connections.insert(player_id, connection);
+            }
+        }
+    }
+
+    /// Waits for `Hello` and gives the client one of `free_ids`
+    fn greet(
+        &mut self,
+        stream: TcpStream,
+        free_ids: &[PlayerId],
+    ) -> Result<(PlayerId, Connection), NetError> {
+        let mut connection = Connection::new(stream)?;
+        let timeout = Duration::from_millis(HELLO_TIMEOUT_MS);
+        connection.reader.get_ref().set_read_timeout(Some(timeout))?;
+        let (version, secret) = match receive(&mut connection.reader)? {
+            ClientMessage::Hello{version, secret} => (version, secret),
+            ClientMessage::Command(_) => return Err(NetError::UnexpectedMessage),
+        };
+        connection.reader.get_ref().set_read_timeout(None)?;
+        if version != PROTOCOL_VERSION {
+            let reason = format!(
+                "Protocol version {} is not {}", version, PROTOCOL_VERSION);
+            return refuse(&mut connection, reason);
+        }
+        let player_id = match secret {
+            Some(secret) => free_ids.iter()
+                .find(|player_id| self.secrets.get(player_id) == Some(&secret)),
+            None => free_ids.iter()
+                .find(|player_id| !self.secrets.contains_key(player_id)),
+        };
+        let player_id = match player_id {
+            Some(&player_id) => player_id,
+            None => return refuse(&mut connection, "No free seat".to_owned()),
+        };
+        let secret = self.secrets.entry(player_id)
+            .or_insert_with(new_secret)
+            .clone();
+        send(&mut connection.writer, &ServerMessage::Welcome(Box::new(Welcome {
+            player_id: player_id,
+            current_player_id: self.core.player_id(),
+            secret: secret,
+            options: self.options.clone(),
+            db: self.core.db().clone(),
+            state: self.core.snapshot_for(player_id),
+        })))?;
+        Ok((player_id, connection))
+    }
+
+    /// Plays until somebody wins or it's the turn of a player whose
+    /// client is lost, then `wait_for_players` lets the game go on.
+    /// Other players lose only their own seats.
+    pub fn run(&mut self) -> Result<(), NetError> {
+        loop {
+            self.send_events();
+            if self.core.state().winner_team_id().is_some() {
+                return Ok(());
+            }
+            let player_id = self.core.player_id();
+            if !self.core.is_ai_turn() && !self.connections.contains_key(&player_id) {
+                return Err(NetError::Disconnected);
+            }
+            self.step()?;
+        }
+    }
+
+    /// Plays one AI turn or does one command of the current human,
+    /// it may only run the clock and answer the other clients.
+    ///
+    /// An error means that the current human's client is lost.
+    pub fn step(&mut self) -> Result<(), NetError> {
+        if self.core.is_ai_turn() {
+            self.core.do_ai_turn();
+            return Ok(());
+        }
+        self.reject_other_commands();
+        let player_id = self.core.player_id();
+        let result = {
+            let connection = self.connections.get_mut(&player_id).unwrap();
+            do_client_command(&mut self.core, connection)
+        };
+        if result.is_err() {
+            self.connections.remove(&player_id);
+        }
+        result
+    }
+
+    /// Players can't command in other players' turns
+    fn reject_other_commands(&mut self) {
+        let current_player_id = self.core.player_id();
+        let mut lost_ids = Vec::new();
+        for (&player_id, connection) in &mut self.connections {
+            if player_id != current_player_id && reject_commands(connection).is_err() {
+                lost_ids.push(player_id);
+            }
+        }
+        for player_id in lost_ids {
+            self.connections.remove(&player_id);
+        }
+    }
+
+    fn send_events(&mut self) {
+        let mut player_ids: Vec<PlayerId> = self.connections.keys().cloned().collect();
+        player_ids.sort();
+        for player_id in player_ids {
+            let mut result = Ok(());
+            {
+                let connection = self.connections.get_mut(&player_id).unwrap();
+                while let Some(event) = self.core.get_event_for(player_id) {
+                    result = send(&mut connection.writer, &ServerMessage::Event(event));
+                    if result.is_err() {
+                        break;
+                    }
+                }
+            }
+            if result.is_err() {
+                self.connections.remove(&player_id);
+            }
+        }
+    }
+}
+
+fn refuse<T>(connection: &mut Connection, reason: String) -> Result<T, NetError> {
+    send(&mut connection.writer, &ServerMessage::Refused{reason: reason.clone()})?;
+    Err(NetError::Refused(reason))
+}
+
+fn new_secret() -> String {
+    let mut rng = thread_rng();
+    format!("{:016x}{:016x}", rng.gen::<u64>(), rng.gen::<u64>())
+}
+
+/// Waits a bit of the turn's time for the command, so the server can
+/// end a timed turn when its time is out
+fn do_client_command(core: &mut Core, connection: &mut Connection) -> Result<(), NetError> {
+    let start = Instant::now();
+    let is_ready = wait_for_data(connection, Duration::from_millis(WAIT_STEP_MS))?;
+    core.pass_time(start.elapsed());
+    if core.is_time_out() {
+        core.do_command(Command::TimeOut);
+        return Ok(());
+    }
+    if !is_ready {
+        return Ok(());
+    }
+    let command = match receive(&mut connection.reader)? {
+        ClientMessage::Command(command) => command,
+        ClientMessage::Hello{..} => return Err(NetError::UnexpectedMessage),
+    };
+    // Only the server runs the clocks
+    match command {
+        Command::PassTime{..} | Command::TimeOut => {
+            let reason = "Not a client's command".to_owned();
+            return send(&mut connection.writer, &ServerMessage::Rejected{reason: reason});
+        },
+        _ => {},
+    }
+    match core.try_command(command) {
+        Ok(events) => {
+            for event in events {
+                send(&mut connection.writer, &ServerMessage::Event(event))?;
+            }
+        },
+        Err(err) => {
+            let reason = format!("{:?}", err);
+            send(&mut connection.writer, &ServerMessage::Rejected{reason: reason})?;
+        },
+    }
+    Ok(())
+}
+
+/// Answers every command that has already come with `Rejected`
+fn reject_commands(connection: &mut Connection) -> Result<(), NetError> {
+    while wait_for_data(connection, Duration::from_millis(1))? {
+        match receive(&mut connection.reader)? {
+            ClientMessage::Command(_) => {
+                let reason = "Not your turn".to_owned();
+                send(&mut connection.writer, &ServerMessage::Rejected{reason: reason})?;
+            },
+            ClientMessage::Hello{..} => return Err(NetError::UnexpectedMessage),
+        }
+    }
+    Ok(())
+}
+
+/// Waits up to `timeout` for the client to start sending something.
+/// A closed connection counts too: the next read gives the error.
+fn wait_for_data(connection: &mut Connection, timeout: Duration) -> Result<bool, NetError> {
+    connection.reader.get_ref().set_read_timeout(Some(timeout))?;
+    let result = connection.reader.fill_buf().map(|_| ());
+    connection.reader.get_ref().set_read_timeout(None)?;
+    match result {
+        Ok(()) => Ok(true),
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock
+            || err.kind() == io::ErrorKind::TimedOut => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The human's side of a network game.
+///
+/// Messages are read by a separate thread, so `get_message` never blocks.
+#[derive(Debug)]
+pub struct Client {
+    player_id: PlayerId,
+    current_player_id: PlayerId,
+    secret: String,
+    options: Options,
+    db: Rc<Db>,
+    state: State,
+    writer: TcpStream,
+    rx: Receiver<Result<String, NetError>>,
+}
+
+impl Client {
+    /// Takes a new seat or, with `secret` of `Client::secret`, the old one
+    pub fn connect<A: ToSocketAddrs>(
+        addr: A,
+        secret: Option<&str>,
+    ) -> Result<Client, NetError> {
+        let stream = TcpStream::connect(addr)?;
+        let mut connection = Connection::new(stream)?;
+        send(&mut connection.writer, &ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+            secret: secret.map(|secret| secret.to_owned()),
+        })?;
+        let welcome = match receive(&mut connection.reader)? {
+            ServerMessage::Welcome(welcome) => *welcome,
+            ServerMessage::Refused{reason} => return Err(NetError::Refused(reason)),
+            _ => return Err(NetError::UnexpectedMessage),
+        };
+        let (tx, rx) = channel();
+        let mut reader = connection.reader;
+        thread::spawn(move || {
+            loop {
+                let result = receive_text(&mut reader);
+                let is_err = result.is_err();
+                if tx.send(result).is_err() || is_err {
+                    return;
+                }
+            }
+        });
+        Ok(Client {
+            player_id: welcome.player_id,
+            current_player_id: welcome.current_player_id,
+            secret: welcome.secret,
+            options: welcome.options,
+            db: welcome.db,
+            state: welcome.state,
+            writer: connection.writer,
+            rx: rx,
+        })
+    }
+
+    pub fn player_id(&self) -> PlayerId {
+        self.player_id
+    }
+
+    /// Whose turn it was when the client connected
+    pub fn current_player_id(&self) -> PlayerId {
+        self.current_player_id
+    }
+
+    /// Gets the seat back, see `Client::connect`
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn db(&self) -> &Rc<Db> {
+        &self.db
+    }
+
+    /// What the player knew when the client connected
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// The answer comes later as events or `ServerMessage::Rejected`
+    pub fn send_command(&mut self, command: Command) -> Result<(), NetError> {
+        send(&mut self.writer, &ClientMessage::Command(command))
+    }
+
+    /// `None` if nothing has come yet
+    pub fn get_message(&mut self) -> Result<Option<ServerMessage>, NetError> {
+        match self.rx.try_recv() {
+            Ok(text) => Ok(Some(parse(&text?)?)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(NetError::Disconnected),
+        }
+    }
+}
+
+impl Drop for Client {
+    /// Also stops the reading thread
+    fn drop(&mut self) {
+        let _ = self.writer.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::{thread};
+    use std::time::{Duration};
+    use Core;
+    use options::{Options};
+    use player::{PlayerId, PlayerClass};
+    use scenario::{test_scenario};
+    use event::{Command, CoreEvent};
+    use position::{ExactPos, MapPos, SlotId};
+    use cgmath::{Vector2};
+    use serialize::{DeserializeError};
+    use net::{Server, Client, ServerMessage, ClientMessage, NetError};
+    use net::{send, receive_text, MAX_MESSAGE_LEN};
+
+    fn wait_for_message(client: &mut Client) -> ServerMessage {
+        loop {
+            if let Some(message) = client.get_message().unwrap() {
+                return message;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_too_long_message() {
+        let text = format!("{}\nHello 4", MAX_MESSAGE_LEN + 1);
+        match receive_text(&mut Cursor::new(text)) {
+            Err(NetError::Deserialize(DeserializeError::BadData(_))) => {},
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        // A length line that never ends
+        let text = "1".repeat(1000);
+        assert!(receive_text(&mut Cursor::new(text)).is_err());
+    }
+
+    #[test]
+    fn test_bad_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Queued before the server starts accepting
+        let mut garbage = TcpStream::connect(addr).unwrap();
+        garbage.write_all(b"5\nhello").unwrap();
+        let mut no_hello = TcpStream::connect(addr).unwrap();
+        send(&mut no_hello, &ClientMessage::Command(Command::EndTurn)).unwrap();
+        drop(TcpStream::connect(addr).unwrap());
+        let server_thread = thread::spawn(move || {
+            let players = vec![PlayerClass::Human, PlayerClass::Ai];
+            let core = Core::new(&Options::new(players, test_scenario()));
+            Server::accept(core, &listener).unwrap();
+        });
+        let client = Client::connect(addr, None).unwrap();
+        assert_eq!(client.player_id(), PlayerId{id: 0});
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_loopback_game() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_thread = thread::spawn(move || {
+            let players = vec![PlayerClass::Human, PlayerClass::Ai];
+            let core = Core::new(&Options {
+                seed: Some(12),
+                .. Options::new(players, test_scenario())
+            });
+            let mut server = Server::accept(core, &listener).unwrap();
+            // Ends when the client disconnects
+            assert!(server.run().is_err());
+            server.wait_for_players(&listener).unwrap();
+            assert!(server.run().is_err());
+        });
+        let mut client = Client::connect(addr, None).unwrap();
+        assert_eq!(client.player_id(), PlayerId{id: 0});
+        assert_eq!(client.options().seed, None);
+        let secret = client.secret().to_owned();
+        let type_id = client.db().unit_type_id("soldier");
+        client.send_command(Command::CreateUnit {
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: -1, y: 100}},
+                slot_id: SlotId::Id(0),
+            },
+            type_id: type_id,
+        }).unwrap();
+        match wait_for_message(&mut client) {
+            ServerMessage::Rejected{..} => {},
+            message => panic!("Unexpected message: {:?}", message),
+        }
+        client.send_command(Command::EndTurn).unwrap();
+        let mut new_ids = Vec::new();
+        while new_ids.last() != Some(&0) {
+            if let ServerMessage::Event(CoreEvent::EndTurn{new_id, ..})
+                = wait_for_message(&mut client)
+            {
+                new_ids.push(new_id.id);
+            }
+        }
+        assert_eq!(new_ids, vec![1, 0]);
+        drop(client);
+        // The seat is taken, even though its client is lost
+        match Client::connect(addr, None) {
+            Err(NetError::Refused(_)) => {},
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match Client::connect(addr, Some("bad secret")) {
+            Err(NetError::Refused(_)) => {},
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let mut client = Client::connect(addr, Some(&secret)).unwrap();
+        assert_eq!(client.secret(), secret);
+        assert_eq!(client.player_id(), PlayerId{id: 0});
+        assert_eq!(client.current_player_id(), PlayerId{id: 0});
+        assert!(client.state().is_partial());
+        client.send_command(Command::EndTurn).unwrap();
+        loop {
+            if let ServerMessage::Event(CoreEvent::EndTurn{new_id, ..})
+                = wait_for_message(&mut client)
+            {
+                if new_id.id == 0 {
+                    break;
+                }
+            }
+        }
+        drop(client);
+        server_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_other_players_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_thread = thread::spawn(move || {
+            let players = vec![PlayerClass::Human, PlayerClass::Human];
+            let core = Core::new(&Options::new(players, test_scenario()));
+            let mut server = Server::accept(core, &listener).unwrap();
+            // Stops only when it's the lost client's turn
+            assert!(server.run().is_err());
+            assert_eq!(server.core().player_id(), PlayerId{id: 1});
+        });
+        let mut client_0 = Client::connect(addr, None).unwrap();
+        let mut client_1 = Client::connect(addr, None).unwrap();
+        assert_eq!(client_1.player_id(), PlayerId{id: 1});
+        client_1.send_command(Command::EndTurn).unwrap();
+        match wait_for_message(&mut client_1) {
+            ServerMessage::Rejected{..} => {},
+            message => panic!("Unexpected message: {:?}", message),
+        }
+        drop(client_1);
+        client_0.send_command(Command::EndTurn).unwrap();
+        loop {
+            if let ServerMessage::Event(CoreEvent::EndTurn{new_id, ..})
+                = wait_for_message(&mut client_0)
+            {
+                assert_eq!(new_id, PlayerId{id: 1});
+                break;
+            }
+        }
+        server_thread.join().unwrap();
+    }
+}
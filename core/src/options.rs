@@ -1,18 +1,139 @@
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
-pub enum GameType {
-    Hotseat,
-    SingleVsAi,
+use std::{fmt, error};
+use std::collections::{BTreeMap};
+use std::rc::{Rc};
+use std::time::{Duration};
+use scenario::{Scenario};
+use player::{PlayerId, PlayerClass, TeamId};
+use ai::{AiBuilder};
+
+/// How well `PlayerClass::Ai` players play
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+    /// Makes random mistakes and moves carelessly
+    Easy,
+
+    Normal,
+
+    /// Picks targets and positions more carefully
+    /// and gets more reinforcement points
+    Hard,
+}
+
+/// Who acts when
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurnMode {
+    /// Players play their turns one after another
+    Alternate,
+
+    /// Every player plots orders for their units in turn, and the orders
+    /// of all players are carried out together after the last one.
+    /// Only for humans: the AI can't plot orders.
+    Simultaneous,
 }
 
-impl Default for GameType {
-    fn default() -> GameType {
-        GameType::Hotseat
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptionsError {
+    /// The scenario is for another number of players
+    BadPlayersCount,
+
+    BadTeamsCount,
+
+    /// The AI can't plot orders
+    AiInSimultaneousTurns,
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OptionsError::BadPlayersCount => {
+                write!(f, "The scenario is for another number of players")
+            },
+            OptionsError::BadTeamsCount => {
+                write!(f, "Every player must have a team")
+            },
+            OptionsError::AiInSimultaneousTurns => {
+                write!(f, "The AI can`t play simultaneous turns")
+            },
+        }
+    }
+}
+
+impl error::Error for OptionsError {
+    fn description(&self) -> &str {
+        "bad options"
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Options {
-    pub game_type: GameType,
-    pub map_name: String,
-    pub players_count: i32, // TODO: must it be defined by map/scenario?
+    /// Player ids are indices in this vector
+    pub players: Vec<PlayerClass>,
+
+    /// Team of each player, must be as long as `players`
+    pub teams: Vec<TeamId>,
+
+    /// Allies see everything that any of them sees
+    pub shared_vision: bool,
+
+    pub turn_mode: TurnMode,
+
+    /// Longest turn of a human player, the core ends turns that take longer
+    pub turn_time_limit: Option<Duration>,
+
+    /// Time for all the turns of a human player, like a chess clock.
+    /// Once it's out, the player's turns are ended right away.
+    pub game_time_limit: Option<Duration>,
+
+    /// AIs of `PlayerClass::Ai` players, `SimpleAi` plays for the missing ones.
+    ///
+    /// Saves and replays keep only the names of the AIs,
+    /// see `ai::AiFinder`.
+    pub ais: BTreeMap<PlayerId, Rc<AiBuilder>>,
+
+    pub difficulty: Difficulty,
+
+    pub scenario: Scenario,
+
+    /// Seed of the core's RNG, a random one is picked if this is None
+    pub seed: Option<u64>,
+}
+
+impl Options {
+    /// Free for all game of the `players` without time limits
+    pub fn new(players: Vec<PlayerClass>, scenario: Scenario) -> Options {
+        let teams = Options::free_for_all_teams(players.len());
+        Options {
+            players: players,
+            teams: teams,
+            shared_vision: false,
+            turn_mode: TurnMode::Alternate,
+            turn_time_limit: None,
+            game_time_limit: None,
+            ais: BTreeMap::new(),
+            difficulty: Difficulty::Normal,
+            scenario: scenario,
+            seed: None,
+        }
+    }
+
+    /// Can a game be played with these options?
+    pub fn check(&self) -> Result<(), OptionsError> {
+        if self.players.len() as i32 != self.scenario.players_count {
+            return Err(OptionsError::BadPlayersCount);
+        }
+        if self.players.len() != self.teams.len() {
+            return Err(OptionsError::BadTeamsCount);
+        }
+        if self.turn_mode == TurnMode::Simultaneous
+            && self.players.contains(&PlayerClass::Ai)
+        {
+            return Err(OptionsError::AiInSimultaneousTurns);
+        }
+        Ok(())
+    }
+
+    /// Every player is in a team of their own
+    pub fn free_for_all_teams(players_count: usize) -> Vec<TeamId> {
+        (0 .. players_count).map(|id| TeamId{id: id as i32}).collect()
+    }
 }
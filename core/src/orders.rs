@@ -0,0 +1,280 @@
+//! Orders of simultaneous turns: players plot them during the planning
+//! phase and the core carries out the orders of all players together.
+//!
+//! There are no orders to load, unload, attach or detach units yet,
+//! so these commands are refused in simultaneous turns.
+
+use std::collections::{BTreeMap};
+use std::collections::btree_map;
+use db::{Db};
+use game_state::{State};
+use unit::{Unit, UnitId};
+use position::{ExactPos, MapPos};
+use event::{Command, MoveMode, FireMode};
+use movement::{path_cost, move_cost_modifier};
+use attack::{AttackPoints};
+use player::{PlayerId};
+use check::{CommandError, check_move, check_smoke, check_attack};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Order {
+    /// The path starts where the unit is after its previous orders
+    Move{path: Vec<ExactPos>, mode: MoveMode},
+
+    /// One shot, the defender must be visible when it's fired
+    Attack{defender_id: UnitId},
+
+    /// Takes all attack points, like `Command::Smoke`
+    Smoke{pos: MapPos},
+}
+
+/// Orders of one player's units. A unit carries out
+/// its own orders one after another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plan {
+    orders: BTreeMap<UnitId, Vec<Order>>,
+}
+
+impl Plan {
+    pub fn new() -> Plan {
+        Plan {
+            orders: BTreeMap::new(),
+        }
+    }
+
+    pub fn orders(&self, unit_id: UnitId) -> &[Order] {
+        match self.orders.get(&unit_id) {
+            Some(orders) => orders,
+            None => &[],
+        }
+    }
+
+    /// No orders remove the unit from the plan
+    pub fn set_orders(&mut self, unit_id: UnitId, orders: Vec<Order>) {
+        if orders.is_empty() {
+            self.orders.remove(&unit_id);
+        } else {
+            self.orders.insert(unit_id, orders);
+        }
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<UnitId, Vec<Order>> {
+        self.orders.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.orders.clear();
+    }
+}
+
+impl Default for Plan {
+    fn default() -> Plan {
+        Plan::new()
+    }
+}
+
+/// The order that the command is plotted as in simultaneous turns,
+/// with the id of the unit that takes it
+pub fn command_to_order(command: &Command) -> Option<(UnitId, Order)> {
+    match *command {
+        Command::Move{unit_id, ref path, mode} => {
+            Some((unit_id, Order::Move{path: path.clone(), mode: mode}))
+        },
+        Command::AttackUnit{attacker_id, defender_id} => {
+            Some((attacker_id, Order::Attack{defender_id: defender_id}))
+        },
+        Command::Smoke{unit_id, pos} => Some((unit_id, Order::Smoke{pos: pos})),
+        _ => None,
+    }
+}
+
+/// The unit as it'll be if all its orders go well
+pub fn planned_unit(db: &Db, state: &State, unit: &Unit, orders: &[Order]) -> Unit {
+    let mut unit = unit.clone();
+    for order in orders {
+        apply_order(db, state, &mut unit, order);
+    }
+    unit
+}
+
+fn apply_order(db: &Db, state: &State, unit: &mut Unit, order: &Order) {
+    match *order {
+        Order::Move{ref path, mode} => {
+            let cost = path_cost(db, state, unit, path).n * move_cost_modifier(mode);
+            if let Some(ref mut move_points) = unit.move_points {
+                move_points.n -= cost;
+            }
+            unit.pos = *path.last().unwrap();
+        },
+        Order::Attack{..} => {
+            if let Some(ref mut attack_points) = unit.attack_points {
+                attack_points.n -= 1;
+            }
+        },
+        Order::Smoke{..} => {
+            unit.attack_points = Some(AttackPoints{n: 0});
+        },
+    }
+}
+
+/// Can the unit carry out these orders as the player sees the game now?
+pub fn check_orders(
+    db: &Db,
+    player_id: PlayerId,
+    state: &State,
+    unit_id: UnitId,
+    orders: &[Order],
+) -> Result<(), CommandError> {
+    let mut unit = match state.unit_opt(unit_id) {
+        Some(unit) => unit.clone(),
+        None => return Err(CommandError::BadUnitId),
+    };
+    if !unit.is_alive {
+        return Err(CommandError::UnitIsDead);
+    }
+    if unit.player_id != player_id {
+        return Err(CommandError::CanNotCommandEnemyUnits);
+    }
+    for order in orders {
+        match *order {
+            Order::Move{ref path, mode} => {
+                check_move(db, state, &unit, path, mode)?;
+            },
+            Order::Attack{defender_id} => {
+                let defender = match state.unit_opt(defender_id) {
+                    Some(defender) => defender,
+                    None => return Err(CommandError::BadDefenderId),
+                };
+                if state.is_ally(defender.player_id, player_id) {
+                    return Err(CommandError::CanNotAttackAllies);
+                }
+                check_attack(db, state, &unit, defender, FireMode::Active)?;
+            },
+            Order::Smoke{pos} => {
+                check_smoke(db, state, &unit, pos)?;
+            },
+        }
+        apply_order(db, state, &mut unit, order);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use Core;
+    use options::{Options};
+    use scenario::{test_scenario};
+    use player::{PlayerId, PlayerClass};
+    use position::{MapPos, ExactPos, SlotId};
+    use event::{Command, MoveMode};
+    use unit::{UnitId};
+    use movement::{path_cost, move_cost_modifier};
+    use check::{CommandError};
+    use orders::{Order, command_to_order, planned_unit, check_orders};
+
+    const SOLDIER_ID: UnitId = UnitId{id: 1};
+    const OTHER_SOLDIER_ID: UnitId = UnitId{id: 2};
+    const ENEMY_ID: UnitId = UnitId{id: 3};
+
+    fn pos(x: i32, slot_id: u8) -> ExactPos {
+        ExactPos {
+            map_pos: MapPos{v: Vector2{x: x, y: 0}},
+            slot_id: SlotId::Id(slot_id),
+        }
+    }
+
+    /// Player 0 has two soldiers in the plain, player 1 has one behind the trees
+    fn new_core() -> Core {
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core = Core::new(&Options::new(players, test_scenario()));
+        let type_id = core.db().unit_type_id("soldier");
+        core.do_command(Command::CreateUnit{pos: pos(0, 0), type_id: type_id});
+        core.do_command(Command::CreateUnit{pos: pos(0, 1), type_id: type_id});
+        core.do_command(Command::EndTurn);
+        core.do_command(Command::CreateUnit{pos: pos(2, 0), type_id: type_id});
+        core.do_command(Command::EndTurn);
+        core
+    }
+
+    #[test]
+    fn test_command_to_order() {
+        let path = vec![pos(0, 0), pos(1, 0)];
+        let command = Command::Move {
+            unit_id: SOLDIER_ID,
+            path: path.clone(),
+            mode: MoveMode::Fast,
+        };
+        assert_eq!(command_to_order(&command),
+            Some((SOLDIER_ID, Order::Move{path: path, mode: MoveMode::Fast})));
+        let command = Command::AttackUnit{attacker_id: SOLDIER_ID, defender_id: ENEMY_ID};
+        assert_eq!(command_to_order(&command),
+            Some((SOLDIER_ID, Order::Attack{defender_id: ENEMY_ID})));
+        let map_pos = pos(2, 0).map_pos;
+        let command = Command::Smoke{unit_id: SOLDIER_ID, pos: map_pos};
+        assert_eq!(command_to_order(&command),
+            Some((SOLDIER_ID, Order::Smoke{pos: map_pos})));
+        // Transport commands are refused in simultaneous turns
+        let command = Command::LoadUnit{transporter_id: SOLDIER_ID, passenger_id: ENEMY_ID};
+        assert_eq!(command_to_order(&command), None);
+        assert_eq!(command_to_order(&Command::EndTurn), None);
+    }
+
+    #[test]
+    fn test_planned_unit() {
+        let core = new_core();
+        let (db, state) = (core.db(), core.state());
+        let unit = state.unit(SOLDIER_ID);
+        let path = vec![pos(0, 0), pos(1, 0)];
+        let cost = path_cost(db, state, unit, &path).n * move_cost_modifier(MoveMode::Fast);
+        let orders = [
+            Order::Move{path: path, mode: MoveMode::Fast},
+            Order::Attack{defender_id: ENEMY_ID},
+        ];
+        let planned = planned_unit(db, state, unit, &orders);
+        assert_eq!(planned.pos, pos(1, 0));
+        assert_eq!(planned.move_points.unwrap().n, unit.move_points.unwrap().n - cost);
+        assert_eq!(planned.attack_points.unwrap().n, unit.attack_points.unwrap().n - 1);
+        // Smoke takes all attack points
+        let orders = [Order::Smoke{pos: pos(2, 0).map_pos}];
+        let planned = planned_unit(db, state, unit, &orders);
+        assert_eq!(planned.pos, unit.pos);
+        assert_eq!(planned.attack_points.unwrap().n, 0);
+    }
+
+    #[test]
+    fn test_check_orders() {
+        let core = new_core();
+        let (db, state) = (core.db(), core.state());
+        let player_id = PlayerId{id: 0};
+        let there = Order::Move{path: vec![pos(0, 0), pos(1, 0)], mode: MoveMode::Fast};
+        // The first slot is still taken by the soldier itself
+        let back = Order::Move{path: vec![pos(1, 0), pos(0, 2)], mode: MoveMode::Fast};
+        let there_again = Order::Move{path: vec![pos(0, 2), pos(1, 0)], mode: MoveMode::Fast};
+        assert_eq!(check_orders(db, player_id, state, SOLDIER_ID, &[there.clone()]), Ok(()));
+        // Every move starts where the previous one has ended
+        let orders = [there.clone(), back.clone()];
+        assert_eq!(check_orders(db, player_id, state, SOLDIER_ID, &orders), Ok(()));
+        assert_eq!(check_orders(db, player_id, state, SOLDIER_ID, &[back.clone()]),
+            Err(CommandError::BadPath));
+        // And takes the move points that are left
+        let mut orders = vec![there];
+        for _ in 0..10 {
+            orders.push(back.clone());
+            orders.push(there_again.clone());
+        }
+        assert_eq!(check_orders(db, player_id, state, SOLDIER_ID, &orders),
+            Err(CommandError::NotEnoughMovePoints));
+        assert_eq!(check_orders(db, player_id, state, UnitId{id: 100}, &[]),
+            Err(CommandError::BadUnitId));
+        assert_eq!(check_orders(db, player_id, state, ENEMY_ID, &[]),
+            Err(CommandError::CanNotCommandEnemyUnits));
+        let attack_ally = Order::Attack{defender_id: OTHER_SOLDIER_ID};
+        assert_eq!(check_orders(db, player_id, state, SOLDIER_ID, &[attack_ally]),
+            Err(CommandError::CanNotAttackAllies));
+    }
+}
@@ -0,0 +1,374 @@
+//! Play-by-email: every player has the whole game and sends
+//! the commands of their turn to the others in a turn file.
+//!
+//! The first turn file also has the game's setup,
+//! so the other players can start the same game from it.
+//!
+//! A turn file is refused if it is damaged, out of order, has commands
+//! that are illegal or doesn't give the author's state. That is all:
+//! the checksum is an unkeyed FNV-1a hash and `player_id` is never
+//! authenticated, so anyone with the game can write a valid turn file
+//! for any player, the other player's included.
+
+use std::{fmt, error};
+use Core;
+use db::{Db};
+use event::{Command};
+use options::{Options};
+use player::{PlayerId};
+use check::{CommandError, ends_turn};
+use serialize::{Serialize, Writer, Reader, DeserializeError, read_options};
+use serialize::{write_header, read_header};
+use ai::{AiFinder, builtin_ai_builder};
+
+/// Bump this when the layout of turn files changes
+const TURN_FILE_VERSION: i32 = 4;
+
+const CHECKSUM_TAG: &'static str = "checksum";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TurnFileError {
+    Deserialize(DeserializeError),
+    BadChecksum,
+    OtherGame,
+
+    /// Somebody's turn is missing or was already imported
+    OutOfOrder{expected: u64, got: u64},
+
+    NotPlayersTurn(PlayerId),
+
+    /// The commands don't end with the only `Command::EndTurn` or `Command::TimeOut`
+    NotWholeTurn,
+
+    BadCommand(CommandError),
+
+    /// The commands gave another state than the author's one
+    Desync,
+}
+
+impl fmt::Display for TurnFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TurnFileError::Deserialize(ref err) => write!(f, "Bad turn file: {}", err),
+            TurnFileError::BadChecksum => write!(f, "The turn file is damaged"),
+            TurnFileError::OtherGame => write!(f, "The turn is from another game"),
+            TurnFileError::OutOfOrder{expected, got} => {
+                write!(f, "Expected the turn after command {}, got the one after {}",
+                    expected, got)
+            },
+            TurnFileError::NotPlayersTurn(player_id) => {
+                write!(f, "It's not the turn of player {}", player_id.id)
+            },
+            TurnFileError::NotWholeTurn => write!(f, "The file has no whole turn"),
+            TurnFileError::BadCommand(ref err) => write!(f, "Bad command: {}", err),
+            TurnFileError::Desync => write!(f, "The game went differently for the author"),
+        }
+    }
+}
+
+impl error::Error for TurnFileError {
+    fn description(&self) -> &str {
+        "bad turn file"
+    }
+}
+
+impl From<DeserializeError> for TurnFileError {
+    fn from(err: DeserializeError) -> TurnFileError {
+        TurnFileError::Deserialize(err)
+    }
+}
+
+/// What players need to start the game on their side
+#[derive(Clone, Debug)]
+pub struct Setup {
+    pub options: Options,
+    pub db: Db,
+}
+
+#[derive(Clone, Debug)]
+pub struct TurnFile {
+    /// Only in the file of the game's first turn
+    pub setup: Option<Setup>,
+
+    /// Tells games with different options or dbs apart
+    pub game_id: u64,
+
+    pub player_id: PlayerId,
+
+    /// Number of human commands that were done before this turn
+    pub first_command_index: u64,
+
+    pub commands: Vec<Command>,
+
+    /// Hash of the full state right after the turn
+    pub state_hash: u64,
+}
+
+impl TurnFile {
+    fn save(&self, w: &mut Writer) {
+        write_header(w, "zoc_turn", TURN_FILE_VERSION);
+        self.game_id.serialize(w);
+        self.player_id.serialize(w);
+        self.first_command_index.serialize(w);
+        self.state_hash.serialize(w);
+        w.new_line();
+        match self.setup {
+            Some(ref setup) => {
+                w.write_tag("setup");
+                w.new_line();
+                setup.options.serialize(w);
+                w.new_line();
+                setup.db.save(w);
+            },
+            None => w.write_tag("no_setup"),
+        }
+        w.new_line();
+        self.commands.serialize(w);
+        w.new_line();
+    }
+
+    fn load(r: &mut Reader, find_ai: &AiFinder) -> Result<TurnFile, DeserializeError> {
+        read_header(r, "zoc_turn", TURN_FILE_VERSION)?;
+        let game_id = r.read()?;
+        let player_id = r.read()?;
+        let first_command_index = r.read()?;
+        let state_hash = r.read()?;
+        let tag = r.read_tag()?;
+        let setup = match tag.as_str() {
+            "setup" => Some(Setup {
+                options: read_options(r, find_ai)?,
+                db: Db::load(r)?,
+            }),
+            "no_setup" => None,
+            _ => return Err(DeserializeError::BadTag(tag)),
+        };
+        Ok(TurnFile {
+            setup: setup,
+            game_id: game_id,
+            player_id: player_id,
+            first_command_index: first_command_index,
+            commands: r.read()?,
+            state_hash: state_hash,
+        })
+    }
+
+    /// The text with the checksum at the end
+    pub fn to_text(&self) -> String {
+        let mut w = Writer::new();
+        self.save(&mut w);
+        let body = w.into_string();
+        format!("{}{} {}\n", body, CHECKSUM_TAG, hash(body.as_bytes()))
+    }
+
+    /// `find_ai` is only needed for the setup of the game's first turn
+    pub fn from_text(text: &str, find_ai: &AiFinder) -> Result<TurnFile, TurnFileError> {
+        let checksum_pos = match text.rfind(CHECKSUM_TAG) {
+            Some(pos) => pos,
+            None => return Err(TurnFileError::BadChecksum),
+        };
+        let (body, checksum_text) = text.split_at(checksum_pos);
+        let mut r = Reader::new(checksum_text);
+        r.expect_tag(CHECKSUM_TAG)?;
+        let checksum: u64 = r.read()?;
+        if !r.is_finished() || checksum != hash(body.as_bytes()) {
+            return Err(TurnFileError::BadChecksum);
+        }
+        let mut r = Reader::new(body);
+        let turn = TurnFile::load(&mut r, find_ai)?;
+        if !r.is_finished() {
+            return Err(DeserializeError::BadData("Trailing data".to_owned()).into());
+        }
+        Ok(turn)
+    }
+}
+
+/// FNV-1a, only catches mistakes, see the module docs
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn game_id(core: &Core) -> u64 {
+    let replay = core.replay();
+    let mut w = Writer::new();
+    replay.options.serialize(&mut w);
+    replay.db.save(&mut w);
+    hash(w.into_string().as_bytes())
+}
+
+fn state_hash(core: &Core) -> u64 {
+    let mut w = Writer::new();
+    core.state().save(&mut w);
+    hash(w.into_string().as_bytes())
+}
+
+/// Number of human commands done so far, the next turn starts here
+pub fn commands_count(core: &Core) -> usize {
+    core.replay().commands.len()
+}
+
+/// Turn file with the commands done since `first_command_index`,
+/// call it right after the player's `Command::EndTurn` or `Command::TimeOut`
+pub fn export_turn(core: &Core, player_id: PlayerId, first_command_index: usize) -> String {
+    let commands = core.replay().commands[first_command_index ..].to_vec();
+    assert!(commands.last().map_or(false, ends_turn));
+    let setup = if first_command_index == 0 {
+        Some(Setup {
+            options: core.replay().options.clone(),
+            db: core.replay().db.clone(),
+        })
+    } else {
+        None
+    };
+    let turn = TurnFile {
+        setup: setup,
+        game_id: game_id(core),
+        player_id: player_id,
+        first_command_index: first_command_index as u64,
+        commands: commands,
+        state_hash: state_hash(core),
+    };
+    turn.to_text()
+}
+
+/// Starts the game from the setup in its first turn file,
+/// the turn itself is left for `import_turn`
+pub fn new_game(turn: &TurnFile) -> Result<Core, TurnFileError> {
+    match turn.setup {
+        Some(ref setup) => Ok(Core::new_with_db(&setup.options, setup.db.clone())),
+        None => Err(TurnFileError::OutOfOrder {
+            expected: 0,
+            got: turn.first_command_index,
+        }),
+    }
+}
+
+/// Does the commands of the next player's turn, the events come
+/// as usual. The game is changed only if the whole turn is good.
+pub fn import_turn(core: &mut Core, turn: &TurnFile) -> Result<(), TurnFileError> {
+    if turn.game_id != game_id(core) {
+        return Err(TurnFileError::OtherGame);
+    }
+    let expected = commands_count(core) as u64;
+    if turn.first_command_index != expected {
+        return Err(TurnFileError::OutOfOrder {
+            expected: expected,
+            got: turn.first_command_index,
+        });
+    }
+    if core.is_ai_turn() || core.player_id() != turn.player_id {
+        return Err(TurnFileError::NotPlayersTurn(turn.player_id));
+    }
+    let end_turns_count = turn.commands.iter()
+        .filter(|&command| ends_turn(command))
+        .count();
+    if end_turns_count != 1 || !turn.commands.last().map_or(false, ends_turn) {
+        return Err(TurnFileError::NotWholeTurn);
+    }
+    // Checks the turn on a copy of the game
+    let mut w = Writer::new();
+    core.save(&mut w);
+    let text = w.into_string();
+    let builders = core.replay().options.ais.clone();
+    let find_ai = move |name: &str| {
+        builders.values()
+            .find(|builder| builder.name() == name)
+            .cloned()
+            .or_else(|| builtin_ai_builder(name))
+    };
+    let mut copy = Core::load(&mut Reader::new(&text), &find_ai)?;
+    for command in &turn.commands {
+        if let Err(err) = copy.try_command(command.clone()) {
+            return Err(TurnFileError::BadCommand(err));
+        }
+    }
+    if state_hash(&copy) != turn.state_hash {
+        return Err(TurnFileError::Desync);
+    }
+    for command in &turn.commands {
+        core.do_command(command.clone());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use Core;
+    use options::{Options};
+    use player::{PlayerId, PlayerClass};
+    use scenario::{test_scenario};
+    use event::{Command, CoreEvent};
+    use position::{ExactPos, MapPos, SlotId};
+    use ai::{builtin_ai_builder};
+    use pbem::{
+        TurnFile,
+        TurnFileError,
+        commands_count,
+        export_turn,
+        import_turn,
+        new_game,
+    };
+
+    fn play_turn(core: &mut Core) -> String {
+        let player_id = core.player_id();
+        let first_command_index = commands_count(core);
+        let type_id = core.db().unit_type_id("soldier");
+        let x = if player_id.id == 0 { 0 } else { 2 };
+        let _ = core.try_command(Command::CreateUnit {
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: x, y: 0}},
+                slot_id: SlotId::Id(0),
+            },
+            type_id: type_id,
+        });
+        core.do_command(Command::EndTurn);
+        export_turn(core, player_id, first_command_index)
+    }
+
+    #[test]
+    fn test_turn_files() {
+        let players = vec![PlayerClass::Human, PlayerClass::Human];
+        let mut core_a = Core::new(&Options::new(players, test_scenario()));
+        let text = play_turn(&mut core_a);
+        let first_turn = TurnFile::from_text(&text, &builtin_ai_builder).unwrap();
+        let mut core_b = new_game(&first_turn).unwrap();
+        import_turn(&mut core_b, &first_turn).unwrap();
+        assert_eq!(import_turn(&mut core_b, &first_turn),
+            Err(TurnFileError::OutOfOrder{expected: 2, got: 0}));
+        let mut is_unit_created = false;
+        while let Some(event) = core_b.get_event_for(PlayerId{id: 1}) {
+            if let CoreEvent::CreateUnit{..} = event {
+                is_unit_created = true;
+            }
+        }
+        // Player 1 can't see the unit of player 0 yet
+        assert!(!is_unit_created);
+        let mut texts = Vec::new();
+        for _ in 0 .. 3 {
+            texts.push(play_turn(&mut core_b));
+            let turn = TurnFile::from_text(texts.last().unwrap(), &builtin_ai_builder).unwrap();
+            import_turn(&mut core_a, &turn).unwrap();
+            texts.push(play_turn(&mut core_a));
+            let turn = TurnFile::from_text(texts.last().unwrap(), &builtin_ai_builder).unwrap();
+            import_turn(&mut core_b, &turn).unwrap();
+        }
+        let turn = TurnFile::from_text(&texts[0], &builtin_ai_builder).unwrap();
+        let expected = commands_count(&core_a) as u64;
+        assert_eq!(import_turn(&mut core_a, &turn),
+            Err(TurnFileError::OutOfOrder{expected: expected, got: 2}));
+        let tampered = texts[2].replacen("EndTurn", "EndTurn\nEndTurn", 1);
+        let result = TurnFile::from_text(&tampered, &builtin_ai_builder);
+        assert_eq!(result.unwrap_err(), TurnFileError::BadChecksum);
+        let mut w = ::serialize::Writer::new();
+        core_b.state().save(&mut w);
+        let state_b = w.into_string();
+        let mut w = ::serialize::Writer::new();
+        core_a.state().save(&mut w);
+        assert_eq!(w.into_string(), state_b);
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeSet, VecDeque};
+use std::rc::{Rc};
 use event::{CoreEvent};
 use unit::{UnitId};
 use fow::{Fow};
@@ -7,10 +8,15 @@ use game_state::{State};
 use check::{CommandError, check_command};
 use event::{Command};
 use filter;
+use serialize::{Serialize, Writer, Reader, DeserializeError};
 
-#[derive(PartialOrd, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct PlayerId{pub id: i32}
 
+/// Players of the same team are allies
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct TeamId{pub id: i32}
+
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub enum PlayerClass {
     Human,
@@ -21,13 +27,14 @@ pub enum PlayerClass {
 pub struct Player {
     pub id: PlayerId,
     pub class: PlayerClass,
+    pub team_id: TeamId,
 }
 
 #[derive(Clone, Debug)]
 pub struct PlayerInfo {
     id: PlayerId,
     events: VecDeque<CoreEvent>,
-    visible_enemies: HashSet<UnitId>,
+    visible_enemies: BTreeSet<UnitId>,
 
     // This filed is optional because we need to temporary
     // put its Fow into Core's State for filtering events.
@@ -43,11 +50,38 @@ impl PlayerInfo {
             id: id,
             fow: Some(fow),
             events: VecDeque::new(),
-            visible_enemies: HashSet::new(),
+            visible_enemies: BTreeSet::new(),
         }
     }
 
+    pub fn save(&self, w: &mut Writer) {
+        self.id.serialize(w);
+        self.visible_enemies.serialize(w);
+        w.new_line();
+        self.fow().save(w);
+        w.new_line();
+        self.events.serialize(w);
+    }
+
+    pub fn load(r: &mut Reader, db: Rc<Db>) -> Result<PlayerInfo, DeserializeError> {
+        let id = r.read()?;
+        let visible_enemies = r.read()?;
+        let fow = Fow::load(r, db)?;
+        let events = r.read()?;
+        Ok(PlayerInfo {
+            id: id,
+            fow: Some(fow),
+            events: events,
+            visible_enemies: visible_enemies,
+        })
+    }
+
     pub fn filter_event(&mut self, state: &State, event: &CoreEvent) {
+        if let CoreEvent::Reveal{..} = *event {
+            // Players get ShowUnit instead of Reveal, but the revealed unit
+            // must stay visible for Core's checks too
+            self.fow_mut().apply_event(state, event);
+        }
         let (filtered_events, active_unit_ids) = filter::filter_events(
             state, self.id, self.fow(), event);
         for filtered_event in filtered_events {
@@ -62,14 +96,27 @@ impl PlayerInfo {
         }
     }
 
+    pub fn id(&self) -> PlayerId {
+        self.id
+    }
+
     pub fn get_event(&mut self) -> Option<CoreEvent> {
         self.events.pop_front()
     }
 
-    pub fn visible_enemies(&self) -> &HashSet<UnitId> {
+    pub fn visible_enemies(&self) -> &BTreeSet<UnitId> {
         &self.visible_enemies
     }
 
+    pub fn events_count(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Takes away all the events that were added after the first `count` ones
+    pub fn split_events_off(&mut self, count: usize) -> Vec<CoreEvent> {
+        self.events.split_off(count).into_iter().collect()
+    }
+
     pub fn check_command(
         &mut self,
         db: &Db,
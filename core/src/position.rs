@@ -1,5 +1,5 @@
 use std::{fmt};
-use std::collections::{HashMap};
+use std::collections::{BTreeMap};
 use cgmath::{Vector2};
 use dir::{Dir};
 use game_state::{State, ObjectsAtIter};
@@ -132,13 +132,13 @@ pub fn get_unit_ids_at(state: &State, pos: MapPos) -> Vec<UnitId> {
     ids
 }
 
-pub fn objects_at(objects: &HashMap<ObjectId, Object>, pos: MapPos) -> ObjectsAtIter {
+pub fn objects_at(objects: &BTreeMap<ObjectId, Object>, pos: MapPos) -> ObjectsAtIter {
     ObjectsAtIter::new(objects, pos)
 }
 
 pub fn get_free_slot_for_building(
     map: &Map<Terrain>,
-    objects: &HashMap<ObjectId, Object>,
+    objects: &BTreeMap<ObjectId, Object>,
     pos: MapPos,
 ) -> Option<SlotId> {
     let mut slots = [false, false, false];
@@ -0,0 +1,176 @@
+//! What reinforcements the AI buys: units that can hurt the known enemies
+//! and survive their fire, in a mix that suits the map
+
+use std::cmp;
+use std::collections::{HashMap};
+use game_state::{State, ReinforcementPoints};
+use unit::{UnitTypeId};
+use map::{Terrain};
+use db::{Db};
+use player::{PlayerId};
+use attack::{type_hit_chance};
+
+/// Every unit of a type that the player already has makes
+/// one more unit of that type this much less useful
+const MIX_PENALTY: f32 = 0.5;
+
+/// Hit chances below this are equally harmless
+const MIN_EXPOSURE: f32 = 10.0;
+
+/// The AI doesn't wait for a unit longer than this number of turns
+pub const MAX_SAVING_TURNS: i32 = 2;
+
+/// Nobody waits for anything when a team is this close to `target_score`
+const ENDGAME_SCORE_GAP: i32 = 3;
+
+/// Unit types from the most to the least useful for the player now.
+///
+/// `enemies` are the types of the known enemy units with their strength.
+pub fn rank_unit_types(
+    db: &Db,
+    state: &State,
+    player_id: PlayerId,
+    enemies: &[(UnitTypeId, f32)],
+) -> Vec<UnitTypeId> {
+    let all_types: Vec<(UnitTypeId, f32)>;
+    let enemies = if enemies.is_empty() {
+        // Nothing is known yet, so expect anything
+        all_types = (0..db.unit_types().len())
+            .map(|id| (UnitTypeId{id: id as i32}, 1.0))
+            .collect();
+        &all_types
+    } else {
+        enemies
+    };
+    let mut own_counts = HashMap::new();
+    for (_, unit) in state.units() {
+        if unit.is_alive && unit.player_id == player_id {
+            *own_counts.entry(unit.type_id.id).or_insert(0) += 1;
+        }
+    }
+    let cover_bonus = average_cover_bonus(state);
+    let mut scores: Vec<(f32, UnitTypeId)> = (0..db.unit_types().len())
+        .map(|id| {
+            let type_id = UnitTypeId{id: id as i32};
+            let own_count = *own_counts.get(&type_id.id).unwrap_or(&0);
+            let score = unit_type_score(db, type_id, enemies, cover_bonus)
+                / (1.0 + MIX_PENALTY * own_count as f32);
+            (score, type_id)
+        })
+        .collect();
+    scores.sort_by(|a, b| {
+        let order = b.0.partial_cmp(&a.0).unwrap_or(cmp::Ordering::Equal);
+        order.then(a.1.id.cmp(&b.1.id))
+    });
+    scores.into_iter().map(|(_, type_id)| type_id).collect()
+}
+
+/// Firepower against the enemies times the hits that the unit can take
+/// from them, for the square root of the cost so that strong units
+/// are worth waiting for
+fn unit_type_score(
+    db: &Db,
+    type_id: UnitTypeId,
+    enemies: &[(UnitTypeId, f32)],
+    cover_bonus: i32,
+) -> f32 {
+    let unit_type = db.unit_type(type_id);
+    let cover_bonus = if unit_type.is_infantry { cover_bonus } else { 0 };
+    let mut total_weight = 0.0;
+    let mut firepower = 0.0;
+    let mut exposure = 0.0;
+    for &(enemy_type_id, weight) in enemies {
+        total_weight += weight;
+        firepower += weight * hit_chance(db, type_id, enemy_type_id, 0);
+        exposure += weight * hit_chance(db, enemy_type_id, type_id, cover_bonus);
+    }
+    if total_weight <= 0.0 {
+        return 0.0;
+    }
+    firepower *= unit_type.attack_points.n as f32 / total_weight;
+    exposure = f32::max(exposure / total_weight, MIN_EXPOSURE);
+    // Free units are as good as the cheapest paid ones
+    let cost = cmp::max(unit_type.cost.n, 1);
+    firepower * unit_type.count as f32 / exposure / (cost as f32).sqrt()
+}
+
+/// Zero if the attacker's weapon can't reach the defender
+fn hit_chance(
+    db: &Db,
+    attacker_type_id: UnitTypeId,
+    defender_type_id: UnitTypeId,
+    cover_bonus: i32,
+) -> f32 {
+    let attacker_type = db.unit_type(attacker_type_id);
+    let weapon_type = db.weapon_type(attacker_type.weapon_type_id);
+    if db.unit_type(defender_type_id).is_air && weapon_type.max_air_distance.is_none() {
+        return 0.0;
+    }
+    type_hit_chance(db, attacker_type_id, defender_type_id, cover_bonus).n as f32
+}
+
+/// Cover that infantry finds on an average tile of the map
+fn average_cover_bonus(state: &State) -> i32 {
+    let map = state.map();
+    let mut total = 0;
+    let mut tiles_count = 0;
+    for pos in map.get_iter() {
+        total += match *map.tile(pos) {
+            Terrain::Plain | Terrain::Water => 0,
+            Terrain::Trees => 2,
+            Terrain::City => 3,
+        };
+        tiles_count += 1;
+    }
+    (total + tiles_count / 2) / cmp::max(tiles_count, 1)
+}
+
+/// Some team is about to win, so there's no time to save points
+pub fn is_endgame(state: &State) -> bool {
+    let target_score = state.target_score();
+    state.score().keys().any(|&player_id| {
+        let score = state.team_score(state.team_id(player_id));
+        target_score.n - score.n <= ENDGAME_SCORE_GAP
+    })
+}
+
+/// Turns of income that the player needs to afford the cost
+pub fn turns_to_afford(state: &State, player_id: PlayerId, cost: ReinforcementPoints) -> i32 {
+    let points = state.reinforcement_points()[&player_id];
+    let income = state.reinforcement_income()[&player_id];
+    let lack = cost.n - points.n;
+    if lack <= 0 {
+        0
+    } else if income.n <= 0 {
+        i32::max_value()
+    } else {
+        (lack + income.n - 1) / income.n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::{Rc};
+    use db::{Db};
+    use game_state::{State};
+    use options::{Options};
+    use player::{PlayerId, PlayerClass};
+    use scenario::{test_scenario};
+    use serialize::{Writer, Reader};
+    use purchase::{rank_unit_types};
+
+    #[test]
+    fn test_free_units() {
+        let mut w = Writer::new();
+        Db::new().save(&mut w);
+        // The truck can't shoot, so this used to give it a NaN score
+        let text = w.into_string().replace("cost 4", "cost 0");
+        let db = Rc::new(Db::load(&mut Reader::new(&text)).unwrap());
+        let players = vec![PlayerClass::Ai, PlayerClass::Ai];
+        let options = Options::new(players, test_scenario());
+        let id = PlayerId{id: 0};
+        let state = State::new_partial(db.clone(), &options, id);
+        let type_ids = rank_unit_types(&db, &state, id, &[]);
+        assert_eq!(type_ids.len(), db.unit_types().len());
+    }
+}
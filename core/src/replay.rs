@@ -0,0 +1,205 @@
+use std::collections::{HashMap};
+use Core;
+use options::{Options};
+use db::{Db};
+use event::{CoreEvent, Command};
+use player::{PlayerId, PlayerClass};
+use serialize::{Serialize, Writer, Reader, DeserializeError, read_options};
+use serialize::{write_header, read_header};
+use ai::{AiFinder};
+
+/// Bump this when the layout written by `Replay::save` changes
+const REPLAY_VERSION: i32 = 9;
+
+/// Everything needed to play a game again: the starting options
+/// (with the actually used seed), the db and all recorded commands.
+///
+/// Commands of deterministic AIs are not recorded: such an AI
+/// makes them again by itself. See `AiBuilder::is_deterministic`.
+#[derive(Clone, Debug)]
+pub struct Replay {
+    pub options: Options,
+    pub db: Db,
+    pub commands: Vec<Command>,
+}
+
+impl Replay {
+    pub fn new(options: &Options, db: &Db) -> Replay {
+        Replay {
+            options: options.clone(),
+            db: db.clone(),
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        write_header(w, "zoc_replay", REPLAY_VERSION);
+        w.new_line();
+        self.options.serialize(w);
+        w.new_line();
+        self.db.save(w);
+        w.new_line();
+        (self.commands.len() as u64).serialize(w);
+        for command in &self.commands {
+            w.new_line();
+            command.serialize(w);
+        }
+        w.new_line();
+    }
+
+    pub fn load(r: &mut Reader, find_ai: &AiFinder) -> Result<Replay, DeserializeError> {
+        read_header(r, "zoc_replay", REPLAY_VERSION)?;
+        let options = read_options(r, find_ai)?;
+        let db = Db::load(r)?;
+        let commands = r.read()?;
+        Ok(Replay {
+            options: options,
+            db: db,
+            commands: commands,
+        })
+    }
+}
+
+/// Results of one replayed command
+#[derive(Clone, Debug)]
+pub struct ReplayStep {
+    pub command: Command,
+
+    /// Events that human players got, AI's events are consumed by the AI
+    pub events: HashMap<PlayerId, Vec<CoreEvent>>,
+}
+
+/// Feeds recorded commands back into a fresh `Core` one by one
+#[derive(Debug)]
+pub struct Replayer {
+    core: Core,
+    commands: Vec<Command>,
+    next_command_index: usize,
+}
+
+impl Replayer {
+    pub fn new(replay: &Replay) -> Replayer {
+        let mut core = Core::new_with_db(&replay.options, replay.db.clone());
+        core.do_ai_turns();
+        Replayer {
+            core: core,
+            commands: replay.commands.clone(),
+            next_command_index: 0,
+        }
+    }
+
+    pub fn core(&self) -> &Core {
+        &self.core
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_command_index == self.commands.len()
+    }
+
+    pub fn step(&mut self) -> Option<ReplayStep> {
+        if self.is_finished() {
+            return None;
+        }
+        let command = self.commands[self.next_command_index].clone();
+        self.next_command_index += 1;
+        if self.core.is_ai_turn() {
+            self.core.replay.commands.push(command.clone());
+            self.core.do_command_internal(command.clone());
+        } else {
+            self.core.do_command(command.clone());
+        }
+        self.core.do_ai_turns();
+        let mut events = HashMap::new();
+        let human_ids: Vec<_> = self.core.players.iter()
+            .filter(|player| player.class == PlayerClass::Human)
+            .map(|player| player.id)
+            .collect();
+        for player_id in human_ids {
+            let info = self.core.players_info.get_mut(&player_id).unwrap();
+            let mut player_events = Vec::new();
+            while let Some(event) = info.get_event() {
+                player_events.push(event);
+            }
+            events.insert(player_id, player_events);
+        }
+        Some(ReplayStep {
+            command: command,
+            events: events,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::{Rc};
+    use std::time::{Duration};
+    use cgmath::{Vector2};
+    use Core;
+    use options::{Options};
+    use player::{PlayerId, PlayerClass};
+    use scenario::{test_scenario};
+    use event::{Command};
+    use position::{ExactPos, MapPos, SlotId};
+    use serialize::{Writer, Reader};
+    use ai::{builtin_ai_builder};
+    use search_ai::{SearchAiBuilder, SearchBudget};
+    use replay::{Replay, Replayer};
+
+    fn state_to_string(core: &Core) -> String {
+        let mut writer = Writer::new();
+        core.state.save(&mut writer);
+        writer.into_string()
+    }
+
+    #[test]
+    fn test_replay_gives_same_state() {
+        let players = vec![PlayerClass::Human, PlayerClass::Ai];
+        let mut core = Core::new(&Options::new(players, test_scenario()));
+        let type_id = core.db().unit_type_id("soldier");
+        core.do_command(Command::CreateUnit {
+            pos: ExactPos {
+                map_pos: MapPos{v: Vector2{x: 0, y: 0}},
+                slot_id: SlotId::Id(0),
+            },
+            type_id: type_id,
+        });
+        for _ in 0..5 {
+            core.do_command(Command::EndTurn);
+            core.do_ai_turns();
+        }
+        let mut writer = Writer::new();
+        core.replay().save(&mut writer);
+        let text = writer.into_string();
+        let replay = Replay::load(&mut Reader::new(&text), &builtin_ai_builder).unwrap();
+        assert_eq!(replay.commands.len(), 6);
+        let mut replayer = Replayer::new(&replay);
+        while let Some(step) = replayer.step() {
+            assert_eq!(step.events.len(), 1);
+        }
+        assert_eq!(state_to_string(replayer.core()), state_to_string(&core));
+    }
+
+    #[test]
+    fn test_replay_records_commands_of_time_limited_ai() {
+        let players = vec![PlayerClass::Human, PlayerClass::Ai];
+        let mut options = Options::new(players, test_scenario());
+        let budget = SearchBudget::Time(Duration::from_millis(1));
+        options.ais.insert(PlayerId{id: 1}, Rc::new(SearchAiBuilder::new(budget)));
+        let mut core = Core::new(&options);
+        for _ in 0..3 {
+            core.do_command(Command::EndTurn);
+            while core.is_ai_turn() {
+                core.do_ai_turn();
+            }
+        }
+        let mut writer = Writer::new();
+        core.replay().save(&mut writer);
+        let text = writer.into_string();
+        let replay = Replay::load(&mut Reader::new(&text), &builtin_ai_builder).unwrap();
+        // Every AI turn ends with a recorded EndTurn at least
+        assert!(replay.commands.len() >= 6);
+        let mut replayer = Replayer::new(&replay);
+        while replayer.step().is_some() {}
+        assert_eq!(state_to_string(replayer.core()), state_to_string(&core));
+    }
+}
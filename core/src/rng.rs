@@ -0,0 +1,103 @@
+use rand::{Rng};
+use serialize::{Serialize, Deserialize, Writer, Reader, DeserializeError};
+
+/// Xorshift128 generator that drives every random decision of the core.
+///
+/// Unlike `thread_rng` it is seeded explicitly and its whole state
+/// is a plain value, so the same seed and the same commands always
+/// give the same game.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoreRng {
+    state: [u32; 4],
+}
+
+impl CoreRng {
+    pub fn new(seed: u64) -> CoreRng {
+        // splitmix64 spreads even a tiny seed over all the state words
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let a = next();
+        let b = next();
+        let mut state = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32];
+        if state == [0; 4] {
+            // all-zero state is a fixed point of xorshift
+            state[0] = 1;
+        }
+        CoreRng{state: state}
+    }
+}
+
+impl Rng for CoreRng {
+    fn next_u32(&mut self) -> u32 {
+        let x = self.state[0];
+        let t = x ^ (x << 11);
+        self.state[0] = self.state[1];
+        self.state[1] = self.state[2];
+        self.state[2] = self.state[3];
+        let w = self.state[3];
+        self.state[3] = w ^ (w >> 19) ^ (t ^ (t >> 8));
+        self.state[3]
+    }
+}
+
+impl Serialize for CoreRng {
+    fn serialize(&self, w: &mut Writer) {
+        for n in &self.state {
+            n.serialize(w);
+        }
+    }
+}
+
+impl Deserialize for CoreRng {
+    fn deserialize(r: &mut Reader) -> Result<CoreRng, DeserializeError> {
+        let mut state = [0; 4];
+        for n in &mut state {
+            *n = r.read()?;
+        }
+        if state == [0; 4] {
+            // `CoreRng::new` never makes it, the generator would be stuck
+            return Err(DeserializeError::BadData("All-zero rng state".to_owned()));
+        }
+        Ok(CoreRng{state: state})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng};
+    use rng::{CoreRng};
+    use serialize::{Reader, DeserializeError};
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = CoreRng::new(42);
+        let mut b = CoreRng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.gen_range(0, 100), b.gen_range(0, 100));
+        }
+    }
+
+    #[test]
+    fn test_different_seeds_differ() {
+        let mut a = CoreRng::new(1);
+        let mut b = CoreRng::new(2);
+        let a: Vec<u32> = (0..10).map(|_| a.next_u32()).collect();
+        let b: Vec<u32> = (0..10).map(|_| b.next_u32()).collect();
+        assert!(a != b);
+    }
+
+    #[test]
+    fn test_zero_state() {
+        let result = Reader::new("0 0 0 0").read::<CoreRng>();
+        match result {
+            Err(DeserializeError::BadData(_)) => {},
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use std::{fmt, error};
+use std::str::{FromStr};
+use std::collections::{BTreeMap};
+use cgmath::{Vector2};
+use types::{Size2};
+use map::{Map, Terrain, distance};
+use dir::{Dir};
+use sector::{Sector, SectorId};
+use position::{self, MapPos, ExactPos, SlotId};
+use player::{PlayerId};
+use object::{ObjectId, Object, ObjectClass};
+use game_state::{Score};
+use serialize::{Serialize, Deserialize, Writer, Reader, DeserializeError};
+
+/// The client has colors for this many players only.
+pub const MAX_PLAYERS_COUNT: i32 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScenarioError {
+    Syntax(DeserializeError),
+    BadSize,
+    BadPlayersCount,
+    BadPlayerId(PlayerId),
+    BadTerrain(char),
+    BadRowLength{row: i32},
+    PosIsOutOfMap(MapPos),
+    NoFreeSlot(MapPos),
+    BadRoad{from: MapPos, to: MapPos},
+    EmptySector,
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScenarioError::Syntax(ref err) => {
+                write!(f, "Syntax error: {}", err)
+            },
+            ScenarioError::BadSize => {
+                write!(f, "Map size must be positive")
+            },
+            ScenarioError::BadPlayersCount => {
+                write!(f, "There must be from 2 to {} players", MAX_PLAYERS_COUNT)
+            },
+            ScenarioError::BadPlayerId(id) => {
+                write!(f, "No player with id {}", id.id)
+            },
+            ScenarioError::BadTerrain(c) => {
+                write!(f, "Unknown terrain: '{}'", c)
+            },
+            ScenarioError::BadRowLength{row} => {
+                write!(f, "Row {} has wrong length", row)
+            },
+            ScenarioError::PosIsOutOfMap(pos) => {
+                write!(f, "Position {} is out of the map", pos)
+            },
+            ScenarioError::NoFreeSlot(pos) => {
+                write!(f, "No free slot for a building at {}", pos)
+            },
+            ScenarioError::BadRoad{from, to} => {
+                write!(f, "Road tiles {} and {} are not neighbours", from, to)
+            },
+            ScenarioError::EmptySector => {
+                write!(f, "Sector has no tiles")
+            },
+        }
+    }
+}
+
+impl error::Error for ScenarioError {
+    fn description(&self) -> &str {
+        "bad scenario"
+    }
+}
+
+impl From<DeserializeError> for ScenarioError {
+    fn from(err: DeserializeError) -> ScenarioError {
+        ScenarioError::Syntax(err)
+    }
+}
+
+/// Map and objectives of a game.
+///
+/// Scenario files look like this:
+///
+/// ```text
+/// # comments start with '#'
+/// size 3 1
+/// target_score 5
+/// players_count 2
+/// terrain
+///     .T.
+/// reinforcement_sector 0 0 some 0
+/// reinforcement_sector 2 0 some 1
+/// buildings 1 0 2
+/// big_building 2 0
+/// road 0 0 1 0 end
+/// sector 1 0 2 0 end
+/// ```
+///
+/// Terrain rows use `.` for plain, `T` for trees, `W` for water
+/// and `C` for city. Tiles with buildings become cities automatically.
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    pub map: Map<Terrain>,
+    pub objects: BTreeMap<ObjectId, Object>,
+    pub sectors: BTreeMap<SectorId, Sector>,
+    pub target_score: Score,
+    pub players_count: i32,
+}
+
+impl FromStr for Scenario {
+    type Err = ScenarioError;
+
+    fn from_str(text: &str) -> Result<Scenario, ScenarioError> {
+        let mut r = Reader::new(text);
+        r.expect_tag("size")?;
+        let map_size: Size2 = r.read()?;
+        if map_size.w <= 0 || map_size.h <= 0 {
+            return Err(ScenarioError::BadSize);
+        }
+        r.expect_tag("target_score")?;
+        let target_score = r.read()?;
+        r.expect_tag("players_count")?;
+        let players_count = r.read()?;
+        if players_count < 2 || players_count > MAX_PLAYERS_COUNT {
+            return Err(ScenarioError::BadPlayersCount);
+        }
+        r.expect_tag("terrain")?;
+        let mut map = Map::new(map_size);
+        for y in 0 .. map_size.h {
+            let row = r.read_tag()?;
+            if row.chars().count() != map_size.w as usize {
+                return Err(ScenarioError::BadRowLength{row: y});
+            }
+            for (x, c) in row.chars().enumerate() {
+                let pos = MapPos{v: Vector2{x: x as i32, y: y}};
+                *map.tile_mut(pos) = char_to_terrain(c)?;
+            }
+        }
+        let mut objects = BTreeMap::new();
+        let mut sectors = BTreeMap::new();
+        while !r.is_finished() {
+            let tag = r.read_tag()?;
+            match tag.as_str() {
+                "buildings" => {
+                    let pos = read_pos(&mut r, &map)?;
+                    let count = r.read()?;
+                    add_buildings(&mut map, &mut objects, pos, count)?;
+                },
+                "big_building" => {
+                    let pos = read_pos(&mut r, &map)?;
+                    add_big_building(&mut map, &mut objects, pos);
+                },
+                "road" => {
+                    let path = read_positions(&mut r, &map)?;
+                    add_road(&mut objects, &path)?;
+                },
+                "reinforcement_sector" => {
+                    let pos = read_pos(&mut r, &map)?;
+                    let owner_id: Option<PlayerId> = r.read()?;
+                    if let Some(id) = owner_id {
+                        if id.id < 0 || id.id >= players_count {
+                            return Err(ScenarioError::BadPlayerId(id));
+                        }
+                    }
+                    add_reinforcement_sector(&mut objects, pos, owner_id);
+                },
+                "sector" => {
+                    let positions = read_positions(&mut r, &map)?;
+                    if positions.is_empty() {
+                        return Err(ScenarioError::EmptySector);
+                    }
+                    let id = SectorId{id: sectors.len() as i32};
+                    sectors.insert(id, Sector {
+                        positions: positions,
+                        owner_id: None,
+                    });
+                },
+                _ => return Err(DeserializeError::BadTag(tag).into()),
+            }
+        }
+        Ok(Scenario {
+            map: map,
+            objects: objects,
+            sectors: sectors,
+            target_score: target_score,
+            players_count: players_count,
+        })
+    }
+}
+
+impl Serialize for Scenario {
+    fn serialize(&self, w: &mut Writer) {
+        self.target_score.serialize(w);
+        self.players_count.serialize(w);
+        w.new_line();
+        self.map.serialize(w);
+        w.new_line();
+        self.objects.serialize(w);
+        w.new_line();
+        self.sectors.serialize(w);
+    }
+}
+
+impl Deserialize for Scenario {
+    fn deserialize(r: &mut Reader) -> Result<Scenario, DeserializeError> {
+        let target_score = r.read()?;
+        let players_count: i32 = r.read()?;
+        if players_count < 2 || players_count > MAX_PLAYERS_COUNT {
+            return Err(DeserializeError::BadData(
+                format!("Bad players count: {}", players_count)));
+        }
+        let map = r.read()?;
+        let objects = r.read()?;
+        let sectors = r.read()?;
+        Ok(Scenario {
+            map: map,
+            objects: objects,
+            sectors: sectors,
+            target_score: target_score,
+            players_count: players_count,
+        })
+    }
+}
+
+fn char_to_terrain(c: char) -> Result<Terrain, ScenarioError> {
+    match c {
+        '.' => Ok(Terrain::Plain),
+        'T' => Ok(Terrain::Trees),
+        'W' => Ok(Terrain::Water),
+        'C' => Ok(Terrain::City),
+        c => Err(ScenarioError::BadTerrain(c)),
+    }
+}
+
+fn read_pos(r: &mut Reader, map: &Map<Terrain>) -> Result<MapPos, ScenarioError> {
+    let pos = r.read()?;
+    if !map.is_inboard(pos) {
+        return Err(ScenarioError::PosIsOutOfMap(pos));
+    }
+    Ok(pos)
+}
+
+/// Reads positions till the `end` tag
+fn read_positions(r: &mut Reader, map: &Map<Terrain>) -> Result<Vec<MapPos>, ScenarioError> {
+    let mut positions = Vec::new();
+    loop {
+        let token = r.read_tag()?;
+        if token == "end" {
+            return Ok(positions);
+        }
+        let x = match token.parse() {
+            Ok(x) => x,
+            Err(_) => return Err(DeserializeError::BadToken(token).into()),
+        };
+        let y = r.read()?;
+        let pos = MapPos{v: Vector2{x: x, y: y}};
+        if !map.is_inboard(pos) {
+            return Err(ScenarioError::PosIsOutOfMap(pos));
+        }
+        positions.push(pos);
+    }
+}
+
+// TODO: create trees, buildings and roads like units - using event system
+fn add_object(objects: &mut BTreeMap<ObjectId, Object>, object: Object) {
+    let id = ObjectId{id: objects.len() as i32 + 1};
+    objects.insert(id, object);
+}
+
+fn add_road(
+    objects: &mut BTreeMap<ObjectId, Object>,
+    path: &[MapPos],
+) -> Result<(), ScenarioError> {
+    for window in path.windows(2) {
+        let from = window[0];
+        let to = window[1];
+        if distance(from, to).n != 1 {
+            return Err(ScenarioError::BadRoad{from: from, to: to});
+        }
+        let dir = Dir::get_dir_from_to(from, to);
+        let object = Object {
+            class: ObjectClass::Road,
+            pos: ExactPos {
+                map_pos: from,
+                slot_id: SlotId::TwoTiles(dir),
+            },
+            timer: None,
+            owner_id: None,
+        };
+        add_object(objects, object);
+    }
+    Ok(())
+}
+
+fn add_reinforcement_sector(
+    objects: &mut BTreeMap<ObjectId, Object>,
+    pos: MapPos,
+    owner_id: Option<PlayerId>,
+) {
+    let object = Object {
+        class: ObjectClass::ReinforcementSector,
+        pos: ExactPos {
+            map_pos: pos,
+            slot_id: SlotId::WholeTile,
+        },
+        timer: None,
+        owner_id: owner_id,
+    };
+    add_object(objects, object);
+}
+
+fn add_buildings(
+    map: &mut Map<Terrain>,
+    objects: &mut BTreeMap<ObjectId, Object>,
+    pos: MapPos,
+    count: i32,
+) -> Result<(), ScenarioError> {
+    *map.tile_mut(pos) = Terrain::City;
+    for _ in 0 .. count {
+        let slot_id = match position::get_free_slot_for_building(map, objects, pos) {
+            Some(slot_id) => slot_id,
+            None => return Err(ScenarioError::NoFreeSlot(pos)),
+        };
+        let obj_pos = ExactPos{map_pos: pos, slot_id: slot_id};
+        let object = Object {
+            class: ObjectClass::Building,
+            pos: obj_pos,
+            timer: None,
+            owner_id: None,
+        };
+        add_object(objects, object);
+    }
+    Ok(())
+}
+
+fn add_big_building(
+    map: &mut Map<Terrain>,
+    objects: &mut BTreeMap<ObjectId, Object>,
+    pos: MapPos,
+) {
+    *map.tile_mut(pos) = Terrain::City;
+    let object = Object {
+        class: ObjectClass::Building,
+        pos: ExactPos {
+            map_pos: pos,
+            slot_id: SlotId::WholeTile,
+        },
+        timer: None,
+        owner_id: None,
+    };
+    add_object(objects, object);
+}
+
+/// Tiny map that tests of other modules play on
+#[cfg(test)]
+pub fn test_scenario() -> Scenario {
+    let text = "
+        size 3 1
+        target_score 5
+        players_count 2
+        terrain
+            .T.
+        reinforcement_sector 0 0 some 0
+        reinforcement_sector 2 0 some 1
+    ";
+    text.parse::<Scenario>().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2};
+    use map::{Terrain};
+    use position::{MapPos};
+    use scenario::{Scenario, ScenarioError, test_scenario};
+
+    #[test]
+    fn test_load() {
+        let scenario = test_scenario();
+        assert_eq!(scenario.map.size().w, 3);
+        assert_eq!(*scenario.map.tile(MapPos{v: Vector2{x: 1, y: 0}}), Terrain::Trees);
+        assert_eq!(scenario.objects.len(), 2);
+        assert_eq!(scenario.target_score.n, 5);
+    }
+
+    #[test]
+    fn test_bad_terrain() {
+        let text = "size 2 1 target_score 1 players_count 2 terrain .X";
+        let err = text.parse::<Scenario>().unwrap_err();
+        assert_eq!(err, ScenarioError::BadTerrain('X'));
+    }
+
+    #[test]
+    fn test_bad_row_length() {
+        let text = "size 2 2 target_score 1 players_count 2 terrain .. ...";
+        let err = text.parse::<Scenario>().unwrap_err();
+        assert_eq!(err, ScenarioError::BadRowLength{row: 1});
+    }
+
+    #[test]
+    fn test_too_many_buildings() {
+        let text = "size 1 1 target_score 1 players_count 2 terrain . buildings 0 0 4";
+        let err = text.parse::<Scenario>().unwrap_err();
+        let pos = MapPos{v: Vector2{x: 0, y: 0}};
+        assert_eq!(err, ScenarioError::NoFreeSlot(pos));
+    }
+
+    #[test]
+    fn test_too_many_players() {
+        let text = "size 1 1 target_score 1 players_count 5 terrain .";
+        let err = text.parse::<Scenario>().unwrap_err();
+        assert_eq!(err, ScenarioError::BadPlayersCount);
+    }
+}
@@ -0,0 +1,656 @@
+//! AI that plays out many variants of its turn and picks the best one.
+//!
+//! Rollouts are simulated on copies of the AI's own partial `State`:
+//! the AI never looks into the fog and never touches the core's RNG,
+//! results of attacks are sampled from `hit_chance` with the AI's own RNG.
+//! Enemy turns are not simulated, the final position is scored instead.
+
+use std::collections::{VecDeque};
+use std::rc::{Rc};
+use std::time::{Duration, Instant};
+use rand::{Rng};
+use game_state::{State};
+use movement::{MovePoints, Pathfinder, tile_cost, move_cost_modifier};
+use position::{ExactPos};
+use unit::{Unit, UnitId, is_commandable};
+use map::{Terrain};
+use db::{Db};
+use check::{check_command};
+use event::{CoreEvent, Command, MoveMode, AttackInfo, FireMode};
+use player::{PlayerId};
+use options::{Options};
+use rng::{CoreRng};
+use serialize::{Serialize, Writer, Reader, DeserializeError, duration_to_ms};
+use ai::{
+    Ai,
+    AiBuilder,
+    SimpleAi,
+    MIN_HIT_CHANCE,
+    is_in_range,
+    distance_to_goal,
+    attack_score,
+};
+use attack;
+
+/// Budget of the `SearchAiBuilder` that is used for the "search" name
+pub const DEFAULT_ROLLOUTS: i32 = 100;
+
+/// Destinations that are tried for every unit, not counting staying put
+const MAX_MOVE_CANDIDATES: usize = 4;
+
+/// Every command with a random outcome makes the AI search again,
+/// this limits the searches in one turn
+const MAX_SEARCHES_PER_TURN: i32 = 40;
+
+/// Part of the enemy's expected damage that is counted as lost
+const THREAT_WEIGHT: f32 = 0.5;
+
+/// Value of one tile of distance to the nearest goal
+const GOAL_DISTANCE_WEIGHT: f32 = 0.3;
+
+/// Value of a unit that stands in a sector that we don't own yet
+const SECTOR_BONUS: f32 = 3.0;
+
+/// How long `SearchAi` thinks before each plan
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchBudget {
+    /// Number of simulated turns.
+    ///
+    /// The AI stays deterministic, so replays work.
+    Rollouts(i32),
+
+    /// Simulates turns until the time is up.
+    ///
+    /// The results depend on the machine's speed,
+    /// so replays record the AI's commands.
+    Time(Duration),
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchAiBuilder {
+    budget: SearchBudget,
+    name: String,
+}
+
+impl SearchAiBuilder {
+    pub fn new(budget: SearchBudget) -> SearchAiBuilder {
+        let name = match budget {
+            SearchBudget::Rollouts(n) => format!("search:{}", n),
+            SearchBudget::Time(time) => format!("search:{}ms", duration_to_ms(time)),
+        };
+        SearchAiBuilder {
+            budget: budget,
+            name: name,
+        }
+    }
+
+    /// Parses names made by `AiBuilder::name`, "search" means the default budget
+    pub fn from_name(name: &str) -> Option<SearchAiBuilder> {
+        if name == "search" {
+            return Some(SearchAiBuilder::new(SearchBudget::Rollouts(DEFAULT_ROLLOUTS)));
+        }
+        if !name.starts_with("search:") {
+            return None;
+        }
+        let budget = &name["search:".len() ..];
+        if budget.ends_with("ms") {
+            match budget[.. budget.len() - 2].parse() {
+                Ok(ms) => Some(SearchAiBuilder::new(
+                    SearchBudget::Time(Duration::from_millis(ms)))),
+                Err(_) => None,
+            }
+        } else {
+            match budget.parse() {
+                Ok(n) if n > 0 => Some(SearchAiBuilder::new(SearchBudget::Rollouts(n))),
+                _ => None,
+            }
+        }
+    }
+}
+
+impl AiBuilder for SearchAiBuilder {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn build(&self, db: Rc<Db>, options: &Options, id: PlayerId, mut rng: CoreRng) -> Box<Ai> {
+        let search_rng = CoreRng::new(rng.gen());
+        let simple = SimpleAi::new(db.clone(), options, id, rng);
+        Box::new(SearchAi::new(db, simple, self.budget, search_rng))
+    }
+
+    fn load(&self, r: &mut Reader, db: Rc<Db>) -> Result<Box<Ai>, DeserializeError> {
+        Ok(Box::new(SearchAi::load(r, db)?))
+    }
+
+    fn is_deterministic(&self) -> bool {
+        match self.budget {
+            SearchBudget::Rollouts(_) => true,
+            SearchBudget::Time(_) => false,
+        }
+    }
+}
+
+/// Ways to move one unit, the first one is the greedy choice
+#[derive(Clone, Debug)]
+struct UnitOptions {
+    unit_id: UnitId,
+    paths: Vec<Vec<ExactPos>>,
+}
+
+/// A search that may take several `Ai::think` calls
+#[derive(Clone, Debug)]
+struct Search {
+    units: Vec<UnitOptions>,
+    rollouts: i32,
+
+    /// Only the time spent on rollouts counts against `SearchBudget::Time`
+    time: Duration,
+
+    best_score: f32,
+    best_plan: VecDeque<Command>,
+}
+
+/// Searches for the best attacks and moves,
+/// everything else is left to `SimpleAi`
+#[derive(Clone, Debug)]
+pub struct SearchAi {
+    simple: SimpleAi,
+    pathfinder: Pathfinder,
+    db: Rc<Db>,
+    budget: SearchBudget,
+    rng: CoreRng,
+    plan: VecDeque<Command>,
+    needs_search: bool,
+    searches_left: i32,
+    search: Option<Search>,
+
+    /// Where the last moved unit should be if nothing has stopped it
+    expected_pos: Option<(UnitId, ExactPos)>,
+}
+
+impl SearchAi {
+    pub fn new(db: Rc<Db>, simple: SimpleAi, budget: SearchBudget, rng: CoreRng) -> SearchAi {
+        let map_size = simple.state().map().size();
+        SearchAi {
+            simple: simple,
+            pathfinder: Pathfinder::new(db.clone(), map_size),
+            db: db,
+            budget: budget,
+            rng: rng,
+            plan: VecDeque::new(),
+            needs_search: true,
+            searches_left: MAX_SEARCHES_PER_TURN,
+            search: None,
+            expected_pos: None,
+        }
+    }
+
+    pub fn load(r: &mut Reader, db: Rc<Db>) -> Result<SearchAi, DeserializeError> {
+        let budget = r.read()?;
+        let rng = r.read()?;
+        let simple = SimpleAi::load(r, db.clone())?;
+        // Plans live only during the AI's turn, so the AI is never saved in the middle of one
+        Ok(SearchAi::new(db, simple, budget, rng))
+    }
+
+    fn id(&self) -> PlayerId {
+        self.simple.id()
+    }
+
+    fn state(&self) -> &State {
+        self.simple.state()
+    }
+
+    fn get_unit_options(&mut self) -> Vec<UnitOptions> {
+        let mut units = Vec::new();
+        for (_, unit) in self.simple.state().units() {
+            if !is_commandable(self.simple.id(), unit) {
+                continue;
+            }
+            units.push(unit.clone());
+        }
+        units.into_iter().map(|unit| UnitOptions {
+            unit_id: unit.id,
+            paths: self.get_move_candidates(&unit),
+        }).collect()
+    }
+
+    /// Reachable positions that are closest to the goal or give cover,
+    /// garrisons are left to `SimpleAi`
+    fn get_move_candidates(&mut self, unit: &Unit) -> Vec<Vec<ExactPos>> {
+        let move_points = unit.move_points.unwrap();
+        if move_points.n <= 0 || self.simple.is_garrison(unit) {
+            return Vec::new();
+        }
+        let state = self.simple.state();
+        let id = self.simple.id();
+        self.pathfinder.fill_map(state, unit);
+        let is_infantry = self.db.unit_type(unit.type_id).is_infantry;
+        let mut candidates = Vec::new();
+        for map_pos in state.map().get_iter() {
+            let tile = self.pathfinder.get_map().tile(map_pos);
+            if tile.parent().is_none() || tile.cost().n > move_points.n {
+                continue;
+            }
+            let distance = match distance_to_goal(state, id, map_pos) {
+                Some(distance) => distance.n,
+                None => 0,
+            };
+            let cover = match *state.map().tile(map_pos) {
+                Terrain::Trees | Terrain::City if is_infantry => 2,
+                _ => 0,
+            };
+            let pos = ExactPos{map_pos: map_pos, slot_id: tile.slot_id()};
+            candidates.push((distance - cover, pos));
+        }
+        candidates.sort_by_key(|&(score, _)| score);
+        candidates.iter()
+            .filter_map(|&(_, pos)| self.pathfinder.get_path(pos))
+            .take(MAX_MOVE_CANDIDATES)
+            .collect()
+    }
+
+    fn start_search(&mut self) -> Search {
+        let units = self.get_unit_options();
+        let start_time = Instant::now();
+        let (score, plan) = self.rollout(&units, true);
+        Search {
+            units: units,
+            rollouts: 1,
+            time: start_time.elapsed(),
+            best_score: score,
+            best_plan: plan,
+        }
+    }
+
+    /// Looks for the best variant of the rest of the turn until
+    /// the budget or the deadline is over.
+    ///
+    /// Returns `true` and sets the plan when the search is finished,
+    /// at least one rollout is done per call.
+    fn continue_search(&mut self, deadline: Option<Instant>) -> bool {
+        let mut search = match self.search.take() {
+            Some(search) => search,
+            None => self.start_search(),
+        };
+        loop {
+            let is_over = match self.budget {
+                SearchBudget::Rollouts(n) => search.rollouts >= n,
+                SearchBudget::Time(time) => search.time >= time,
+            };
+            if is_over {
+                self.plan = search.best_plan;
+                return true;
+            }
+            let start_time = Instant::now();
+            let (score, plan) = self.rollout(&search.units, false);
+            search.time += start_time.elapsed();
+            search.rollouts += 1;
+            if score > search.best_score {
+                search.best_score = score;
+                search.best_plan = plan;
+            }
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                self.search = Some(search);
+                return false;
+            }
+        }
+    }
+
+    /// Plays out one variant of the turn on a copy of the state.
+    ///
+    /// The greedy rollout moves every unit that is not
+    /// in contact to the goal and makes only the best attacks.
+    fn rollout(&mut self, units: &[UnitOptions], is_greedy: bool) -> (f32, VecDeque<Command>) {
+        let mut state = self.state().clone();
+        let mut plan = VecDeque::new();
+        let mut order: Vec<usize> = (0 .. units.len()).collect();
+        if !is_greedy {
+            self.rng.shuffle(&mut order);
+        }
+        for index in order {
+            let options = &units[index];
+            if state.unit_opt(options.unit_id).is_none() {
+                continue;
+            }
+            let attack_first = !is_greedy && self.rng.gen_range(0, 2) == 0;
+            if attack_first {
+                self.simulate_attacks(&mut state, &mut plan, options.unit_id, is_greedy);
+            }
+            let path_index = if is_greedy {
+                let unit = state.unit(options.unit_id);
+                if self.is_close_to_enemies(&state, unit) { None } else { Some(0) }
+            } else {
+                // The last index means staying in place
+                let n = self.rng.gen_range(0, options.paths.len() + 1);
+                if n < options.paths.len() { Some(n) } else { None }
+            };
+            if let Some(path) = path_index.and_then(|n| options.paths.get(n)) {
+                self.simulate_move(&mut state, &mut plan, options.unit_id, path);
+            }
+            if state.unit_opt(options.unit_id).is_some() {
+                self.simulate_attacks(&mut state, &mut plan, options.unit_id, is_greedy);
+            }
+        }
+        (self.evaluate(&state), plan)
+    }
+
+    fn simulate_move(
+        &self,
+        state: &mut State,
+        plan: &mut VecDeque<Command>,
+        unit_id: UnitId,
+        path: &[ExactPos],
+    ) {
+        let mode = MoveMode::Fast;
+        let command = Command::Move {
+            unit_id: unit_id,
+            path: path.to_vec(),
+            mode: mode,
+        };
+        if check_command(&self.db, self.id(), state, &command).is_err() {
+            return;
+        }
+        for window in path.windows(2) {
+            let event = {
+                let unit = state.unit(unit_id);
+                let cost = tile_cost(&self.db, state, unit, window[0], window[1]);
+                CoreEvent::Move {
+                    unit_id: unit_id,
+                    from: window[0],
+                    to: window[1],
+                    mode: mode,
+                    cost: MovePoints{n: cost.n * move_cost_modifier(mode)},
+                }
+            };
+            state.apply_event(&event);
+        }
+        plan.push_back(command);
+    }
+
+    /// Shoots while there are targets worth the attack points
+    fn simulate_attacks(
+        &mut self,
+        state: &mut State,
+        plan: &mut VecDeque<Command>,
+        unit_id: UnitId,
+        is_greedy: bool,
+    ) {
+        loop {
+            let targets = self.get_targets(state, unit_id);
+            if targets.is_empty() {
+                return;
+            }
+            let target_id = if is_greedy {
+                targets.iter()
+                    .fold(None, |best: Option<(f32, UnitId)>, &(score, id)| {
+                        match best {
+                            Some((best_score, _)) if best_score >= score => best,
+                            _ => Some((score, id)),
+                        }
+                    })
+                    .unwrap().1
+            } else {
+                let index = self.rng.gen_range(0, targets.len());
+                targets[index].1
+            };
+            let event = {
+                let attacker = state.unit(unit_id);
+                let defender = state.unit(target_id);
+                let attacker_type = self.db.unit_type(attacker.type_id);
+                let defender_type = self.db.unit_type(defender.type_id);
+                let weapon_type = self.db.weapon_type(attacker_type.weapon_type_id);
+                let hit_chance = attack::hit_chance(&self.db, state, attacker, defender);
+                let killed = attack::get_killed_count(
+                    &self.db, state, attacker, defender, &mut self.rng);
+                CoreEvent::AttackUnit {
+                    attack_info: AttackInfo {
+                        attacker_id: Some(unit_id),
+                        defender_id: target_id,
+                        mode: FireMode::Active,
+                        killed: killed,
+                        suppression: hit_chance.n / 2 + 20 * killed,
+                        remove_move_points: false,
+                        is_ambush: false,
+                        is_inderect: weapon_type.is_inderect,
+                        leave_wrecks: !defender_type.is_infantry && !defender_type.is_air,
+                    },
+                }
+            };
+            state.apply_event(&event);
+            plan.push_back(Command::AttackUnit {
+                attacker_id: unit_id,
+                defender_id: target_id,
+            });
+        }
+    }
+
+    fn get_targets(&self, state: &State, unit_id: UnitId) -> Vec<(f32, UnitId)> {
+        let mut targets = Vec::new();
+        let unit = state.unit(unit_id);
+        if unit.attack_points.unwrap().n <= 0 {
+            return targets;
+        }
+        for (_, target) in state.units() {
+            if state.is_ally(target.player_id, self.id()) || !target.is_alive {
+                continue;
+            }
+            let hit_chance = attack::hit_chance(&self.db, state, unit, target);
+            if hit_chance.n < MIN_HIT_CHANCE {
+                continue;
+            }
+            let command = Command::AttackUnit {
+                attacker_id: unit_id,
+                defender_id: target.id,
+            };
+            if check_command(&self.db, self.id(), state, &command).is_err() {
+                continue;
+            }
+            targets.push((attack_score(&self.db, state, unit, target), target.id));
+        }
+        targets
+    }
+
+    fn is_close_to_enemies(&self, state: &State, unit: &Unit) -> bool {
+        state.units().any(|(_, enemy)| {
+            !state.is_ally(enemy.player_id, self.id())
+                && enemy.is_alive
+                && is_in_range(&self.db, unit, enemy)
+        })
+    }
+
+    /// Value of our units minus value of the visible enemies,
+    /// the damage they are expected to do next turn and the distance to the goals
+    fn evaluate(&self, state: &State) -> f32 {
+        let id = self.id();
+        let mut score = 0.0;
+        for (_, unit) in state.units() {
+            if !unit.is_alive {
+                continue;
+            }
+            let unit_type = self.db.unit_type(unit.type_id);
+            let member_cost = unit_type.cost.n as f32 / unit_type.count as f32;
+            let value = member_cost * unit.count as f32;
+            if !state.is_ally(unit.player_id, id) {
+                score -= value;
+                continue;
+            }
+            score += value;
+            if unit.player_id != id || unit.is_loaded || unit.is_attached {
+                continue;
+            }
+            for (_, enemy) in state.units() {
+                if state.is_ally(enemy.player_id, id) || !enemy.is_alive {
+                    continue;
+                }
+                if is_in_range(&self.db, enemy, unit) {
+                    let killed = attack::expected_killed_count(&self.db, state, enemy, unit);
+                    score -= killed * member_cost * THREAT_WEIGHT;
+                }
+            }
+            if let Some(distance) = distance_to_goal(state, id, unit.pos.map_pos) {
+                score -= distance.n as f32 * GOAL_DISTANCE_WEIGHT;
+            }
+            for sector in state.sectors().values() {
+                let is_ours = sector.owner_id.map_or(false, |owner_id| {
+                    state.is_ally(owner_id, id)
+                });
+                if !is_ours && sector.positions.contains(&unit.pos.map_pos) {
+                    score += SECTOR_BONUS;
+                }
+            }
+        }
+        score
+    }
+
+    /// Next command of the plan, searches for a new plan when needed.
+    ///
+    /// Returns `None` with `self.search` set if the search isn't finished.
+    fn try_get_planned_command(&mut self, deadline: Option<Instant>) -> Option<Command> {
+        if self.search.is_some() {
+            if !self.continue_search(deadline) {
+                return None;
+            }
+        } else if let Some((unit_id, pos)) = self.expected_pos.take() {
+            // Reaction fire or newly spotted enemies have stopped the unit
+            let is_stopped = self.state().unit_opt(unit_id)
+                .map_or(true, |unit| unit.pos != pos);
+            if is_stopped {
+                self.needs_search = true;
+            }
+        }
+        loop {
+            if self.needs_search && self.searches_left > 0 {
+                self.needs_search = false;
+                self.searches_left -= 1;
+                if !self.continue_search(deadline) {
+                    return None;
+                }
+            }
+            let command = match self.plan.pop_front() {
+                Some(command) => command,
+                None => return None,
+            };
+            if check_command(&self.db, self.id(), self.state(), &command).is_err() {
+                // The plan is outdated: the other units still deserve a new one
+                self.plan.clear();
+                self.needs_search = true;
+                continue;
+            }
+            match command {
+                Command::AttackUnit{..} => self.needs_search = true,
+                Command::Move{unit_id, ref path, ..} => {
+                    self.expected_pos = Some((unit_id, *path.last().unwrap()));
+                },
+                _ => {},
+            }
+            return Some(command);
+        }
+    }
+
+    /// Commands that don't need a search
+    fn try_get_simple_command(&mut self) -> Option<Command> {
+        self.simple.update_influence();
+        if let Some(cmd) = self.simple.try_get_smoke_command() {
+            Some(cmd)
+        } else if let Some(cmd) = self.simple.try_get_unload_command() {
+            Some(cmd)
+        } else if let Some(cmd) = self.simple.try_get_detach_command() {
+            Some(cmd)
+        } else if let Some(cmd) = self.simple.try_get_load_command() {
+            Some(cmd)
+        } else if let Some(cmd) = self.simple.try_get_attach_command() {
+            Some(cmd)
+        } else {
+            self.simple.try_get_garrison_command()
+        }
+    }
+
+    /// `None` means that the search isn't finished by the deadline.
+    ///
+    /// A resumed search goes straight to the plan: the simple commands
+    /// were already checked and are not asked again, so the AI's RNG
+    /// is used the same way however the search is split.
+    fn get_next_command(&mut self, deadline: Option<Instant>) -> Option<Command> {
+        if self.search.is_none() {
+            if let Some(cmd) = self.try_get_simple_command() {
+                return Some(cmd);
+            }
+        }
+        if let Some(cmd) = self.try_get_planned_command(deadline) {
+            Some(cmd)
+        } else if self.search.is_some() {
+            None
+        } else if let Some(cmd) = self.simple.try_get_reaction_fire_mode_command() {
+            Some(cmd)
+        } else if let Some(cmd) = self.simple.try_get_create_unit_command() {
+            Some(cmd)
+        } else {
+            Some(Command::EndTurn)
+        }
+    }
+}
+
+impl Ai for SearchAi {
+    fn apply_event(&mut self, event: &CoreEvent) {
+        if let CoreEvent::EndTurn{new_id, ..} = *event {
+            if new_id == self.id() {
+                self.plan.clear();
+                self.needs_search = true;
+                self.searches_left = MAX_SEARCHES_PER_TURN;
+                self.search = None;
+                self.expected_pos = None;
+            }
+        }
+        self.simple.apply_event(event);
+    }
+
+    fn get_command(&mut self) -> Command {
+        self.get_next_command(None).expect("Search without a deadline must finish")
+    }
+
+    fn think(&mut self, budget: Duration) -> Option<Command> {
+        self.get_next_command(Some(Instant::now() + budget))
+    }
+
+    fn save(&self, w: &mut Writer) {
+        self.budget.serialize(w);
+        self.rng.serialize(w);
+        w.new_line();
+        self.simple.save(w);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::{Rc};
+    use db::{Db};
+    use ai::{SimpleAi};
+    use options::{Options};
+    use player::{PlayerId, PlayerClass};
+    use scenario::{test_scenario};
+    use rng::{CoreRng};
+    use event::{Command};
+    use unit::{UnitId};
+    use search_ai::{SearchAi, SearchBudget, MAX_SEARCHES_PER_TURN};
+
+    #[test]
+    fn test_outdated_plan() {
+        let db = Rc::new(Db::new());
+        let players = vec![PlayerClass::Human, PlayerClass::Ai];
+        let options = Options::new(players, test_scenario());
+        let id = PlayerId{id: 1};
+        let simple = SimpleAi::new(db.clone(), &options, id, CoreRng::new(1));
+        let mut ai = SearchAi::new(db, simple, SearchBudget::Rollouts(1), CoreRng::new(2));
+        ai.needs_search = false;
+        ai.plan.push_back(Command::AttackUnit {
+            attacker_id: UnitId{id: 1},
+            defender_id: UnitId{id: 2},
+        });
+        // The failed command doesn't end the AI's turn: it searches again
+        assert_eq!(ai.try_get_planned_command(None), None);
+        assert_eq!(ai.searches_left, MAX_SEARCHES_PER_TURN - 1);
+        assert!(!ai.needs_search);
+    }
+}
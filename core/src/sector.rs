@@ -1,4 +1,4 @@
-use std::collections::{HashSet};
+use std::collections::{HashSet, BTreeSet};
 use cgmath::{Vector2};
 use db::{Db};
 use game_state::{State};
@@ -6,7 +6,7 @@ use position::{MapPos};
 use event::{CoreEvent};
 use player::{PlayerId};
 
-#[derive(PartialOrd, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub struct SectorId{pub id: i32}
 
 #[derive(Clone, Debug)]
@@ -32,22 +32,32 @@ impl Sector {
     }
 }
 
+/// A sector belongs to a team if only this team's units are in it.
+///
+/// The sector's owner is one of the team's players: the old owner
+/// if they are still in the team that holds the sector, otherwise
+/// the claimer with the lowest id.
 pub fn check_sectors(db: &Db, state: &State) -> Vec<CoreEvent> {
     let mut events = Vec::new();
     for (&sector_id, sector) in state.sectors() {
-        let mut claimers = HashSet::new();
+        let mut claimers = BTreeSet::new();
+        let mut teams = HashSet::new();
         for &pos in &sector.positions {
             for unit in state.units_at(pos) {
                 let unit_type = db.unit_type(unit.type_id);
                 if !unit_type.is_air && unit.is_alive {
                     claimers.insert(unit.player_id);
+                    teams.insert(state.team_id(unit.player_id));
                 }
             }
         }
-        let owner_id = if claimers.len() != 1 {
+        let owner_id = if teams.len() != 1 {
             None
         } else {
-            Some(claimers.into_iter().next().unwrap())
+            match sector.owner_id {
+                Some(id) if teams.contains(&state.team_id(id)) => Some(id),
+                _ => claimers.into_iter().next(),
+            }
         };
         if sector.owner_id != owner_id {
             events.push(CoreEvent::SectorOwnerChanged {
@@ -0,0 +1,2017 @@
+use std::{fmt, error, str};
+use std::hash::{Hash};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::time::{Duration};
+use cgmath::{Vector2};
+use types::{Size2};
+use unit::{Unit, UnitId, UnitTypeId};
+use position::{MapPos, ExactPos, SlotId};
+use dir::{Dir};
+use event::{CoreEvent, Command, AttackInfo, FireMode, ReactionFireMode, MoveMode};
+use player::{PlayerId, PlayerClass, Player, TeamId};
+use sector::{Sector, SectorId};
+use object::{Object, ObjectId, ObjectClass};
+use map::{Terrain, Distance};
+use movement::{MovePoints};
+use attack::{AttackPoints};
+use game_state::{ReinforcementPoints, Score};
+use options::{Options, Difficulty, TurnMode};
+use orders::{Order, Plan};
+use ai::{AiFinder, read_ai_builder};
+use search_ai::{SearchBudget};
+use memory::{EnemyMemory, RememberedUnit};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeserializeError {
+    UnexpectedEnd,
+    BadToken(String),
+    BadTag(String),
+    BadVersion(i32),
+    BadData(String),
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeserializeError::UnexpectedEnd => {
+                write!(f, "Unexpected end of data")
+            },
+            DeserializeError::BadToken(ref token) => {
+                write!(f, "Bad token: '{}'", token)
+            },
+            DeserializeError::BadTag(ref tag) => {
+                write!(f, "Unknown tag: '{}'", tag)
+            },
+            DeserializeError::BadVersion(version) => {
+                write!(f, "Unsupported version: {}", version)
+            },
+            DeserializeError::BadData(ref message) => {
+                write!(f, "Bad data: {}", message)
+            },
+        }
+    }
+}
+
+impl error::Error for DeserializeError {
+    fn description(&self) -> &str {
+        "deserialization error"
+    }
+}
+
+/// Version of the encoding itself, saves and messages have their own
+pub const FORMAT_VERSION: i32 = 1;
+
+/// Starts the header of every encoded value, text and binary ones
+const FORMAT_MAGIC: &'static str = "zoc";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    /// Whitespace separated tokens, one collection element per line
+    Text,
+
+    /// Variable length integers and length prefixed strings.
+    /// Every tag is written in full only once and then by its index.
+    Binary,
+}
+
+#[derive(Clone, Debug)]
+pub struct Writer {
+    format: Format,
+    buf: Vec<u8>,
+    tags: HashMap<String, u64>,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Writer::new()
+    }
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::with_format(Format::Text)
+    }
+
+    pub fn with_format(format: Format) -> Writer {
+        Writer {
+            format: format,
+            buf: Vec::new(),
+            tags: HashMap::new(),
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn into_string(self) -> String {
+        assert_eq!(self.format, Format::Text);
+        String::from_utf8(self.buf).unwrap()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn write<T: Serialize>(&mut self, value: &T) {
+        value.serialize(self);
+    }
+
+    /// Bare word, used for enum variant names and section headers
+    pub fn write_tag(&mut self, tag: &str) {
+        assert!(!tag.is_empty());
+        assert!(!tag.contains(char::is_whitespace) && !tag.contains('"'));
+        match self.format {
+            Format::Text => {
+                self.separate();
+                self.push_str(tag);
+            },
+            Format::Binary => {
+                // Zero is followed by a new tag, known tags are
+                // written as their index plus one
+                if let Some(&index) = self.tags.get(tag) {
+                    self.write_varint(index + 1);
+                } else {
+                    let index = self.tags.len() as u64;
+                    self.tags.insert(tag.to_owned(), index);
+                    self.write_varint(0);
+                    self.write_bytes(tag.as_bytes());
+                }
+            },
+        }
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        if self.format == Format::Binary {
+            self.write_bytes(s.as_bytes());
+            return;
+        }
+        self.separate();
+        self.buf.push(b'"');
+        for c in s.chars() {
+            match c {
+                '"' => self.push_str("\\\""),
+                '\\' => self.push_str("\\\\"),
+                '\n' => self.push_str("\\n"),
+                c => self.push_str(c.encode_utf8(&mut [0; 4])),
+            }
+        }
+        self.buf.push(b'"');
+    }
+
+    pub fn write_int(&mut self, n: i64) {
+        match self.format {
+            Format::Text => self.write_tag(&n.to_string()),
+            // Zigzag encoding keeps small negative numbers short
+            Format::Binary => self.write_varint(((n << 1) ^ (n >> 63)) as u64),
+        }
+    }
+
+    pub fn write_uint(&mut self, n: u64) {
+        match self.format {
+            Format::Text => self.write_tag(&n.to_string()),
+            Format::Binary => self.write_varint(n),
+        }
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        match self.format {
+            Format::Text => self.write_tag(if value { "true" } else { "false" }),
+            Format::Binary => self.buf.push(value as u8),
+        }
+    }
+
+    /// Only makes the text easier to read
+    pub fn new_line(&mut self) {
+        if self.format == Format::Text {
+            self.buf.push(b'\n');
+        }
+    }
+
+    fn separate(&mut self) {
+        if !self.buf.is_empty() && self.buf.last() != Some(&b'\n') {
+            self.buf.push(b' ');
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    /// Seven bits per byte, the high bit is set in all bytes but the last
+    fn write_varint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.buf.push(byte);
+                return;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_varint(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Reader<'a> {
+    format: Format,
+    text: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    tags: Vec<String>,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(text: &'a str) -> Reader<'a> {
+        Reader {
+            format: Format::Text,
+            text: text,
+            bytes: text.as_bytes(),
+            pos: 0,
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn new_binary(bytes: &'a [u8]) -> Reader<'a> {
+        Reader {
+            format: Format::Binary,
+            text: "",
+            bytes: bytes,
+            pos: 0,
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn read<T: Deserialize>(&mut self) -> Result<T, DeserializeError> {
+        T::deserialize(self)
+    }
+
+    pub fn read_tag(&mut self) -> Result<String, DeserializeError> {
+        if self.format == Format::Binary {
+            let index = self.read_varint()?;
+            if index == 0 {
+                let tag = self.read_bytes_as_str()?;
+                self.tags.push(tag.clone());
+                return Ok(tag);
+            }
+            return match self.tags.get(index as usize - 1) {
+                Some(tag) => Ok(tag.clone()),
+                None => Err(DeserializeError::BadData(
+                    format!("unknown tag index {}", index))),
+            };
+        }
+        let token = self.next_token()?;
+        if token.starts_with('"') {
+            return Err(DeserializeError::BadToken(token));
+        }
+        Ok(token)
+    }
+
+    pub fn expect_tag(&mut self, expected: &str) -> Result<(), DeserializeError> {
+        let tag = self.read_tag()?;
+        if tag != expected {
+            return Err(DeserializeError::BadTag(tag));
+        }
+        Ok(())
+    }
+
+    pub fn read_str(&mut self) -> Result<String, DeserializeError> {
+        if self.format == Format::Binary {
+            return self.read_bytes_as_str();
+        }
+        let token = self.next_token()?;
+        if !token.starts_with('"') {
+            return Err(DeserializeError::BadToken(token));
+        }
+        let mut s = String::new();
+        let mut chars = token[1 .. token.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                s.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => s.push('\n'),
+                Some(c) => s.push(c),
+                None => return Err(DeserializeError::BadToken(token.clone())),
+            }
+        }
+        Ok(s)
+    }
+
+    pub fn read_int(&mut self) -> Result<i64, DeserializeError> {
+        match self.format {
+            Format::Text => parse_token(self),
+            Format::Binary => {
+                let n = self.read_varint()?;
+                Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+            },
+        }
+    }
+
+    pub fn read_uint(&mut self) -> Result<u64, DeserializeError> {
+        match self.format {
+            Format::Text => parse_token(self),
+            Format::Binary => self.read_varint(),
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, DeserializeError> {
+        match self.format {
+            Format::Text => parse_token(self),
+            Format::Binary => match self.next_byte()? {
+                0 => Ok(false),
+                1 => Ok(true),
+                byte => Err(DeserializeError::BadData(format!("bad bool {}", byte))),
+            },
+        }
+    }
+
+    pub fn is_finished(&mut self) -> bool {
+        if self.format == Format::Text {
+            self.skip_whitespace_and_comments();
+        }
+        self.pos == self.bytes.len()
+    }
+
+    /// Comments start with '#' and last till the end of the line
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            let rest = &self.text[self.pos ..];
+            let start = rest.find(|c: char| !c.is_whitespace())
+                .unwrap_or_else(|| rest.len());
+            let rest = &rest[start ..];
+            self.pos += start;
+            if !rest.starts_with('#') {
+                return;
+            }
+            self.pos += rest.find('\n').unwrap_or_else(|| rest.len());
+        }
+    }
+
+    fn next_token(&mut self) -> Result<String, DeserializeError> {
+        self.skip_whitespace_and_comments();
+        let rest = &self.text[self.pos ..];
+        if rest.is_empty() {
+            return Err(DeserializeError::UnexpectedEnd);
+        }
+        let len = if rest.starts_with('"') {
+            let mut is_escaped = false;
+            let mut end = None;
+            for (i, c) in rest.char_indices().skip(1) {
+                if is_escaped {
+                    is_escaped = false;
+                } else if c == '\\' {
+                    is_escaped = true;
+                } else if c == '"' {
+                    end = Some(i + 1);
+                    break;
+                }
+            }
+            match end {
+                Some(end) => end,
+                None => return Err(DeserializeError::UnexpectedEnd),
+            }
+        } else {
+            rest.find(char::is_whitespace).unwrap_or_else(|| rest.len())
+        };
+        self.pos += len;
+        Ok(rest[.. len].to_owned())
+    }
+
+    fn next_byte(&mut self) -> Result<u8, DeserializeError> {
+        match self.bytes.get(self.pos) {
+            Some(&byte) => {
+                self.pos += 1;
+                Ok(byte)
+            },
+            None => Err(DeserializeError::UnexpectedEnd),
+        }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, DeserializeError> {
+        let mut n = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.next_byte()?;
+            if shift > 63 || (shift == 63 && byte > 1) {
+                return Err(DeserializeError::BadData("too long number".into()));
+            }
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_bytes_as_str(&mut self) -> Result<String, DeserializeError> {
+        let len = self.read_varint()?;
+        if len > (self.bytes.len() - self.pos) as u64 {
+            return Err(DeserializeError::UnexpectedEnd);
+        }
+        let end = self.pos + len as usize;
+        let bytes = &self.bytes[self.pos .. end];
+        self.pos = end;
+        match str::from_utf8(bytes) {
+            Ok(s) => Ok(s.to_owned()),
+            Err(_) => Err(DeserializeError::BadData("bad utf-8 string".into())),
+        }
+    }
+}
+
+/// The format's name and version, followed by the value
+pub fn encode<T: Serialize>(value: &T, format: Format) -> Vec<u8> {
+    let mut w = Writer::with_format(format);
+    write_header(&mut w, FORMAT_MAGIC, FORMAT_VERSION);
+    w.new_line();
+    value.serialize(&mut w);
+    w.new_line();
+    w.into_bytes()
+}
+
+/// Reads both formats written by `encode`
+pub fn decode<T: Deserialize>(data: &[u8]) -> Result<T, DeserializeError> {
+    let text;
+    let mut r = if data.starts_with(FORMAT_MAGIC.as_bytes()) {
+        text = match str::from_utf8(data) {
+            Ok(text) => text,
+            Err(_) => return Err(DeserializeError::BadData("bad utf-8 text".into())),
+        };
+        Reader::new(text)
+    } else {
+        Reader::new_binary(data)
+    };
+    read_header(&mut r, FORMAT_MAGIC, FORMAT_VERSION)?;
+    let value = r.read()?;
+    if !r.is_finished() {
+        return Err(DeserializeError::BadData("data after the end".into()));
+    }
+    Ok(value)
+}
+
+/// Name and version that start an encoded value, a save or a replay
+pub fn write_header(w: &mut Writer, name: &str, version: i32) {
+    w.write_tag(name);
+    version.serialize(w);
+}
+
+pub fn read_header(
+    r: &mut Reader,
+    name: &str,
+    version: i32,
+) -> Result<(), DeserializeError> {
+    r.expect_tag(name)?;
+    let found_version = r.read()?;
+    if found_version != version {
+        return Err(DeserializeError::BadVersion(found_version));
+    }
+    Ok(())
+}
+
+pub trait Serialize {
+    fn serialize(&self, w: &mut Writer);
+}
+
+pub trait Deserialize: Sized {
+    fn deserialize(r: &mut Reader) -> Result<Self, DeserializeError>;
+}
+
+fn parse_token<T: ::std::str::FromStr>(r: &mut Reader) -> Result<T, DeserializeError> {
+    let token = r.read_tag()?;
+    match token.parse() {
+        Ok(value) => Ok(value),
+        Err(_) => Err(DeserializeError::BadToken(token)),
+    }
+}
+
+fn out_of_range<T: fmt::Display>(n: T) -> DeserializeError {
+    DeserializeError::BadData(format!("{} is out of range", n))
+}
+
+/// For the unsigned types that are shorter than `u64`
+fn read_uint_up_to(r: &mut Reader, max: u64) -> Result<u64, DeserializeError> {
+    let n = r.read_uint()?;
+    if n > max {
+        return Err(out_of_range(n));
+    }
+    Ok(n)
+}
+
+impl Serialize for i32 {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_int(*self as i64);
+    }
+}
+
+impl Deserialize for i32 {
+    fn deserialize(r: &mut Reader) -> Result<i32, DeserializeError> {
+        let n = r.read_int()?;
+        if n < i32::min_value() as i64 || n > i32::max_value() as i64 {
+            return Err(out_of_range(n));
+        }
+        Ok(n as i32)
+    }
+}
+
+impl Serialize for u8 {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_uint(*self as u64);
+    }
+}
+
+impl Deserialize for u8 {
+    fn deserialize(r: &mut Reader) -> Result<u8, DeserializeError> {
+        Ok(read_uint_up_to(r, u8::max_value() as u64)? as u8)
+    }
+}
+
+impl Serialize for u32 {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_uint(*self as u64);
+    }
+}
+
+impl Deserialize for u32 {
+    fn deserialize(r: &mut Reader) -> Result<u32, DeserializeError> {
+        Ok(read_uint_up_to(r, u32::max_value() as u64)? as u32)
+    }
+}
+
+impl Serialize for u64 {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_uint(*self);
+    }
+}
+
+impl Deserialize for u64 {
+    fn deserialize(r: &mut Reader) -> Result<u64, DeserializeError> {
+        r.read_uint()
+    }
+}
+
+impl Serialize for bool {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_bool(*self);
+    }
+}
+
+impl Deserialize for bool {
+    fn deserialize(r: &mut Reader) -> Result<bool, DeserializeError> {
+        r.read_bool()
+    }
+}
+
+impl Serialize for String {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_str(self);
+    }
+}
+
+impl Deserialize for String {
+    fn deserialize(r: &mut Reader) -> Result<String, DeserializeError> {
+        r.read_str()
+    }
+}
+
+impl<T: Serialize> Serialize for Option<T> {
+    fn serialize(&self, w: &mut Writer) {
+        match *self {
+            Some(ref value) => {
+                w.write_tag("some");
+                value.serialize(w);
+            },
+            None => w.write_tag("none"),
+        }
+    }
+}
+
+impl<T: Deserialize> Deserialize for Option<T> {
+    fn deserialize(r: &mut Reader) -> Result<Option<T>, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "some" => Ok(Some(r.read()?)),
+            "none" => Ok(None),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+/// Whole milliseconds, the rest is dropped
+pub fn duration_to_ms(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+/// In whole milliseconds
+impl Serialize for Duration {
+    fn serialize(&self, w: &mut Writer) {
+        duration_to_ms(*self).serialize(w);
+    }
+}
+
+impl Deserialize for Duration {
+    fn deserialize(r: &mut Reader) -> Result<Duration, DeserializeError> {
+        Ok(Duration::from_millis(r.read()?))
+    }
+}
+
+impl<A: Serialize, B: Serialize> Serialize for (A, B) {
+    fn serialize(&self, w: &mut Writer) {
+        self.0.serialize(w);
+        self.1.serialize(w);
+    }
+}
+
+impl<A: Deserialize, B: Deserialize> Deserialize for (A, B) {
+    fn deserialize(r: &mut Reader) -> Result<(A, B), DeserializeError> {
+        let a = r.read()?;
+        let b = r.read()?;
+        Ok((a, b))
+    }
+}
+
+fn serialize_seq<'a, T, I>(w: &mut Writer, len: usize, items: I)
+    where T: Serialize + 'a, I: Iterator<Item = &'a T>
+{
+    (len as u64).serialize(w);
+    for item in items {
+        w.new_line();
+        item.serialize(w);
+    }
+}
+
+fn deserialize_seq<T: Deserialize>(r: &mut Reader) -> Result<Vec<T>, DeserializeError> {
+    let len: u64 = r.read()?;
+    let mut items = Vec::new();
+    for _ in 0 .. len {
+        items.push(r.read()?);
+    }
+    Ok(items)
+}
+
+impl<T: Serialize> Serialize for Vec<T> {
+    fn serialize(&self, w: &mut Writer) {
+        serialize_seq(w, self.len(), self.iter());
+    }
+}
+
+impl<T: Deserialize> Deserialize for Vec<T> {
+    fn deserialize(r: &mut Reader) -> Result<Vec<T>, DeserializeError> {
+        deserialize_seq(r)
+    }
+}
+
+impl<T: Serialize> Serialize for VecDeque<T> {
+    fn serialize(&self, w: &mut Writer) {
+        serialize_seq(w, self.len(), self.iter());
+    }
+}
+
+impl<T: Deserialize> Deserialize for VecDeque<T> {
+    fn deserialize(r: &mut Reader) -> Result<VecDeque<T>, DeserializeError> {
+        Ok(deserialize_seq(r)?.into_iter().collect())
+    }
+}
+
+impl<T: Serialize + Ord> Serialize for BTreeSet<T> {
+    fn serialize(&self, w: &mut Writer) {
+        serialize_seq(w, self.len(), self.iter());
+    }
+}
+
+impl<T: Deserialize + Ord> Deserialize for BTreeSet<T> {
+    fn deserialize(r: &mut Reader) -> Result<BTreeSet<T>, DeserializeError> {
+        Ok(deserialize_seq(r)?.into_iter().collect())
+    }
+}
+
+// Hashed containers are written sorted to keep the output stable
+
+impl<T: Serialize + Ord + Hash> Serialize for HashSet<T> {
+    fn serialize(&self, w: &mut Writer) {
+        let sorted: BTreeSet<_> = self.iter().collect();
+        serialize_seq(w, sorted.len(), sorted.into_iter());
+    }
+}
+
+impl<T: Deserialize + Eq + Hash> Deserialize for HashSet<T> {
+    fn deserialize(r: &mut Reader) -> Result<HashSet<T>, DeserializeError> {
+        Ok(deserialize_seq(r)?.into_iter().collect())
+    }
+}
+
+impl<K: Serialize + Ord, V: Serialize> Serialize for BTreeMap<K, V> {
+    fn serialize(&self, w: &mut Writer) {
+        (self.len() as u64).serialize(w);
+        for (key, value) in self {
+            w.new_line();
+            key.serialize(w);
+            value.serialize(w);
+        }
+    }
+}
+
+impl<K: Deserialize + Ord, V: Deserialize> Deserialize for BTreeMap<K, V> {
+    fn deserialize(r: &mut Reader) -> Result<BTreeMap<K, V>, DeserializeError> {
+        Ok(deserialize_seq::<(K, V)>(r)?.into_iter().collect())
+    }
+}
+
+impl<K: Serialize + Ord + Hash, V: Serialize> Serialize for HashMap<K, V> {
+    fn serialize(&self, w: &mut Writer) {
+        let sorted: BTreeMap<_, _> = self.iter().collect();
+        (sorted.len() as u64).serialize(w);
+        for (key, value) in sorted {
+            w.new_line();
+            key.serialize(w);
+            value.serialize(w);
+        }
+    }
+}
+
+impl<K: Deserialize + Eq + Hash, V: Deserialize> Deserialize for HashMap<K, V> {
+    fn deserialize(r: &mut Reader) -> Result<HashMap<K, V>, DeserializeError> {
+        Ok(deserialize_seq::<(K, V)>(r)?.into_iter().collect())
+    }
+}
+
+impl Serialize for UnitId {
+    fn serialize(&self, w: &mut Writer) {
+        self.id.serialize(w);
+    }
+}
+
+impl Deserialize for UnitId {
+    fn deserialize(r: &mut Reader) -> Result<UnitId, DeserializeError> {
+        Ok(UnitId{id: r.read()?})
+    }
+}
+
+impl Serialize for UnitTypeId {
+    fn serialize(&self, w: &mut Writer) {
+        self.id.serialize(w);
+    }
+}
+
+impl Deserialize for UnitTypeId {
+    fn deserialize(r: &mut Reader) -> Result<UnitTypeId, DeserializeError> {
+        Ok(UnitTypeId{id: r.read()?})
+    }
+}
+
+impl Serialize for PlayerId {
+    fn serialize(&self, w: &mut Writer) {
+        self.id.serialize(w);
+    }
+}
+
+impl Deserialize for PlayerId {
+    fn deserialize(r: &mut Reader) -> Result<PlayerId, DeserializeError> {
+        Ok(PlayerId{id: r.read()?})
+    }
+}
+
+impl Serialize for TeamId {
+    fn serialize(&self, w: &mut Writer) {
+        self.id.serialize(w);
+    }
+}
+
+impl Deserialize for TeamId {
+    fn deserialize(r: &mut Reader) -> Result<TeamId, DeserializeError> {
+        Ok(TeamId{id: r.read()?})
+    }
+}
+
+impl Serialize for ObjectId {
+    fn serialize(&self, w: &mut Writer) {
+        self.id.serialize(w);
+    }
+}
+
+impl Deserialize for ObjectId {
+    fn deserialize(r: &mut Reader) -> Result<ObjectId, DeserializeError> {
+        Ok(ObjectId{id: r.read()?})
+    }
+}
+
+impl Serialize for SectorId {
+    fn serialize(&self, w: &mut Writer) {
+        self.id.serialize(w);
+    }
+}
+
+impl Deserialize for SectorId {
+    fn deserialize(r: &mut Reader) -> Result<SectorId, DeserializeError> {
+        Ok(SectorId{id: r.read()?})
+    }
+}
+
+impl Serialize for Distance {
+    fn serialize(&self, w: &mut Writer) {
+        self.n.serialize(w);
+    }
+}
+
+impl Deserialize for Distance {
+    fn deserialize(r: &mut Reader) -> Result<Distance, DeserializeError> {
+        Ok(Distance{n: r.read()?})
+    }
+}
+
+impl Serialize for MovePoints {
+    fn serialize(&self, w: &mut Writer) {
+        self.n.serialize(w);
+    }
+}
+
+impl Deserialize for MovePoints {
+    fn deserialize(r: &mut Reader) -> Result<MovePoints, DeserializeError> {
+        Ok(MovePoints{n: r.read()?})
+    }
+}
+
+impl Serialize for AttackPoints {
+    fn serialize(&self, w: &mut Writer) {
+        self.n.serialize(w);
+    }
+}
+
+impl Deserialize for AttackPoints {
+    fn deserialize(r: &mut Reader) -> Result<AttackPoints, DeserializeError> {
+        Ok(AttackPoints{n: r.read()?})
+    }
+}
+
+impl Serialize for ReinforcementPoints {
+    fn serialize(&self, w: &mut Writer) {
+        self.n.serialize(w);
+    }
+}
+
+impl Deserialize for ReinforcementPoints {
+    fn deserialize(r: &mut Reader) -> Result<ReinforcementPoints, DeserializeError> {
+        Ok(ReinforcementPoints{n: r.read()?})
+    }
+}
+
+impl Serialize for Score {
+    fn serialize(&self, w: &mut Writer) {
+        self.n.serialize(w);
+    }
+}
+
+impl Deserialize for Score {
+    fn deserialize(r: &mut Reader) -> Result<Score, DeserializeError> {
+        Ok(Score{n: r.read()?})
+    }
+}
+
+impl Serialize for Size2 {
+    fn serialize(&self, w: &mut Writer) {
+        self.w.serialize(w);
+        self.h.serialize(w);
+    }
+}
+
+impl Deserialize for Size2 {
+    fn deserialize(r: &mut Reader) -> Result<Size2, DeserializeError> {
+        let width = r.read()?;
+        let height = r.read()?;
+        Ok(Size2{w: width, h: height})
+    }
+}
+
+impl Serialize for MapPos {
+    fn serialize(&self, w: &mut Writer) {
+        self.v.x.serialize(w);
+        self.v.y.serialize(w);
+    }
+}
+
+impl Deserialize for MapPos {
+    fn deserialize(r: &mut Reader) -> Result<MapPos, DeserializeError> {
+        let x = r.read()?;
+        let y = r.read()?;
+        Ok(MapPos{v: Vector2{x: x, y: y}})
+    }
+}
+
+impl Serialize for Dir {
+    fn serialize(&self, w: &mut Writer) {
+        self.to_int().serialize(w);
+    }
+}
+
+impl Deserialize for Dir {
+    fn deserialize(r: &mut Reader) -> Result<Dir, DeserializeError> {
+        let n: i32 = r.read()?;
+        if n < 0 || n >= 6 {
+            return Err(DeserializeError::BadToken(n.to_string()));
+        }
+        Ok(Dir::from_int(n))
+    }
+}
+
+impl Serialize for SlotId {
+    fn serialize(&self, w: &mut Writer) {
+        match *self {
+            SlotId::Id(id) => {
+                w.write_tag("Id");
+                id.serialize(w);
+            },
+            SlotId::WholeTile => w.write_tag("WholeTile"),
+            SlotId::TwoTiles(dir) => {
+                w.write_tag("TwoTiles");
+                dir.serialize(w);
+            },
+            SlotId::Air => w.write_tag("Air"),
+        }
+    }
+}
+
+impl Deserialize for SlotId {
+    fn deserialize(r: &mut Reader) -> Result<SlotId, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Id" => Ok(SlotId::Id(r.read()?)),
+            "WholeTile" => Ok(SlotId::WholeTile),
+            "TwoTiles" => Ok(SlotId::TwoTiles(r.read()?)),
+            "Air" => Ok(SlotId::Air),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for ExactPos {
+    fn serialize(&self, w: &mut Writer) {
+        self.map_pos.serialize(w);
+        self.slot_id.serialize(w);
+    }
+}
+
+impl Deserialize for ExactPos {
+    fn deserialize(r: &mut Reader) -> Result<ExactPos, DeserializeError> {
+        let map_pos = r.read()?;
+        let slot_id = r.read()?;
+        Ok(ExactPos{map_pos: map_pos, slot_id: slot_id})
+    }
+}
+
+impl Serialize for FireMode {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_tag(match *self {
+            FireMode::Active => "Active",
+            FireMode::Reactive => "Reactive",
+        });
+    }
+}
+
+impl Deserialize for FireMode {
+    fn deserialize(r: &mut Reader) -> Result<FireMode, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Active" => Ok(FireMode::Active),
+            "Reactive" => Ok(FireMode::Reactive),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for ReactionFireMode {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_tag(match *self {
+            ReactionFireMode::Normal => "Normal",
+            ReactionFireMode::HoldFire => "HoldFire",
+        });
+    }
+}
+
+impl Deserialize for ReactionFireMode {
+    fn deserialize(r: &mut Reader) -> Result<ReactionFireMode, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Normal" => Ok(ReactionFireMode::Normal),
+            "HoldFire" => Ok(ReactionFireMode::HoldFire),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for MoveMode {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_tag(match *self {
+            MoveMode::Fast => "Fast",
+            MoveMode::Hunt => "Hunt",
+        });
+    }
+}
+
+impl Deserialize for MoveMode {
+    fn deserialize(r: &mut Reader) -> Result<MoveMode, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Fast" => Ok(MoveMode::Fast),
+            "Hunt" => Ok(MoveMode::Hunt),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for Unit {
+    fn serialize(&self, w: &mut Writer) {
+        self.id.serialize(w);
+        self.pos.serialize(w);
+        self.player_id.serialize(w);
+        self.type_id.serialize(w);
+        self.move_points.serialize(w);
+        self.attack_points.serialize(w);
+        self.reactive_attack_points.serialize(w);
+        self.reaction_fire_mode.serialize(w);
+        self.count.serialize(w);
+        self.morale.serialize(w);
+        self.passenger_id.serialize(w);
+        self.attached_unit_id.serialize(w);
+        self.is_alive.serialize(w);
+        self.is_loaded.serialize(w);
+        self.is_attached.serialize(w);
+    }
+}
+
+impl Deserialize for Unit {
+    fn deserialize(r: &mut Reader) -> Result<Unit, DeserializeError> {
+        Ok(Unit {
+            id: r.read()?,
+            pos: r.read()?,
+            player_id: r.read()?,
+            type_id: r.read()?,
+            move_points: r.read()?,
+            attack_points: r.read()?,
+            reactive_attack_points: r.read()?,
+            reaction_fire_mode: r.read()?,
+            count: r.read()?,
+            morale: r.read()?,
+            passenger_id: r.read()?,
+            attached_unit_id: r.read()?,
+            is_alive: r.read()?,
+            is_loaded: r.read()?,
+            is_attached: r.read()?,
+        })
+    }
+}
+
+impl Serialize for Command {
+    fn serialize(&self, w: &mut Writer) {
+        match *self {
+            Command::Move{unit_id, ref path, mode} => {
+                w.write_tag("Move");
+                unit_id.serialize(w);
+                path.serialize(w);
+                mode.serialize(w);
+            },
+            Command::EndTurn => w.write_tag("EndTurn"),
+            Command::CreateUnit{pos, type_id} => {
+                w.write_tag("CreateUnit");
+                pos.serialize(w);
+                type_id.serialize(w);
+            },
+            Command::AttackUnit{attacker_id, defender_id} => {
+                w.write_tag("AttackUnit");
+                attacker_id.serialize(w);
+                defender_id.serialize(w);
+            },
+            Command::LoadUnit{transporter_id, passenger_id} => {
+                w.write_tag("LoadUnit");
+                transporter_id.serialize(w);
+                passenger_id.serialize(w);
+            },
+            Command::UnloadUnit{transporter_id, passenger_id, pos} => {
+                w.write_tag("UnloadUnit");
+                transporter_id.serialize(w);
+                passenger_id.serialize(w);
+                pos.serialize(w);
+            },
+            Command::Attach{transporter_id, attached_unit_id} => {
+                w.write_tag("Attach");
+                transporter_id.serialize(w);
+                attached_unit_id.serialize(w);
+            },
+            Command::Detach{transporter_id, pos} => {
+                w.write_tag("Detach");
+                transporter_id.serialize(w);
+                pos.serialize(w);
+            },
+            Command::SetReactionFireMode{unit_id, mode} => {
+                w.write_tag("SetReactionFireMode");
+                unit_id.serialize(w);
+                mode.serialize(w);
+            },
+            Command::Smoke{unit_id, pos} => {
+                w.write_tag("Smoke");
+                unit_id.serialize(w);
+                pos.serialize(w);
+            },
+            Command::SetOrders{unit_id, ref orders} => {
+                w.write_tag("SetOrders");
+                unit_id.serialize(w);
+                orders.serialize(w);
+            },
+            Command::PassTime{time} => {
+                w.write_tag("PassTime");
+                time.serialize(w);
+            },
+            Command::TimeOut => w.write_tag("TimeOut"),
+        }
+    }
+}
+
+impl Deserialize for Command {
+    fn deserialize(r: &mut Reader) -> Result<Command, DeserializeError> {
+        let tag = r.read_tag()?;
+        let command = match tag.as_str() {
+            "Move" => Command::Move {
+                unit_id: r.read()?,
+                path: r.read()?,
+                mode: r.read()?,
+            },
+            "EndTurn" => Command::EndTurn,
+            "CreateUnit" => Command::CreateUnit {
+                pos: r.read()?,
+                type_id: r.read()?,
+            },
+            "AttackUnit" => Command::AttackUnit {
+                attacker_id: r.read()?,
+                defender_id: r.read()?,
+            },
+            "LoadUnit" => Command::LoadUnit {
+                transporter_id: r.read()?,
+                passenger_id: r.read()?,
+            },
+            "UnloadUnit" => Command::UnloadUnit {
+                transporter_id: r.read()?,
+                passenger_id: r.read()?,
+                pos: r.read()?,
+            },
+            "Attach" => Command::Attach {
+                transporter_id: r.read()?,
+                attached_unit_id: r.read()?,
+            },
+            "Detach" => Command::Detach {
+                transporter_id: r.read()?,
+                pos: r.read()?,
+            },
+            "SetReactionFireMode" => Command::SetReactionFireMode {
+                unit_id: r.read()?,
+                mode: r.read()?,
+            },
+            "Smoke" => Command::Smoke {
+                unit_id: r.read()?,
+                pos: r.read()?,
+            },
+            "SetOrders" => Command::SetOrders {
+                unit_id: r.read()?,
+                orders: r.read()?,
+            },
+            "PassTime" => Command::PassTime{time: r.read()?},
+            "TimeOut" => Command::TimeOut,
+            _ => return Err(DeserializeError::BadTag(tag)),
+        };
+        Ok(command)
+    }
+}
+
+impl Serialize for Order {
+    fn serialize(&self, w: &mut Writer) {
+        match *self {
+            Order::Move{ref path, mode} => {
+                w.write_tag("Move");
+                path.serialize(w);
+                mode.serialize(w);
+            },
+            Order::Attack{defender_id} => {
+                w.write_tag("Attack");
+                defender_id.serialize(w);
+            },
+            Order::Smoke{pos} => {
+                w.write_tag("Smoke");
+                pos.serialize(w);
+            },
+        }
+    }
+}
+
+impl Deserialize for Order {
+    fn deserialize(r: &mut Reader) -> Result<Order, DeserializeError> {
+        let tag = r.read_tag()?;
+        let order = match tag.as_str() {
+            "Move" => Order::Move {
+                path: r.read()?,
+                mode: r.read()?,
+            },
+            "Attack" => Order::Attack {
+                defender_id: r.read()?,
+            },
+            "Smoke" => Order::Smoke {
+                pos: r.read()?,
+            },
+            _ => return Err(DeserializeError::BadTag(tag)),
+        };
+        Ok(order)
+    }
+}
+
+impl Serialize for Plan {
+    fn serialize(&self, w: &mut Writer) {
+        (self.iter().count() as u64).serialize(w);
+        for (unit_id, orders) in self.iter() {
+            w.new_line();
+            unit_id.serialize(w);
+            orders.serialize(w);
+        }
+    }
+}
+
+impl Deserialize for Plan {
+    fn deserialize(r: &mut Reader) -> Result<Plan, DeserializeError> {
+        let orders: BTreeMap<UnitId, Vec<Order>> = r.read()?;
+        let mut plan = Plan::new();
+        for (unit_id, unit_orders) in orders {
+            plan.set_orders(unit_id, unit_orders);
+        }
+        Ok(plan)
+    }
+}
+
+impl Serialize for AttackInfo {
+    fn serialize(&self, w: &mut Writer) {
+        self.attacker_id.serialize(w);
+        self.defender_id.serialize(w);
+        self.mode.serialize(w);
+        self.killed.serialize(w);
+        self.suppression.serialize(w);
+        self.remove_move_points.serialize(w);
+        self.is_ambush.serialize(w);
+        self.is_inderect.serialize(w);
+        self.leave_wrecks.serialize(w);
+    }
+}
+
+impl Deserialize for AttackInfo {
+    fn deserialize(r: &mut Reader) -> Result<AttackInfo, DeserializeError> {
+        Ok(AttackInfo {
+            attacker_id: r.read()?,
+            defender_id: r.read()?,
+            mode: r.read()?,
+            killed: r.read()?,
+            suppression: r.read()?,
+            remove_move_points: r.read()?,
+            is_ambush: r.read()?,
+            is_inderect: r.read()?,
+            leave_wrecks: r.read()?,
+        })
+    }
+}
+
+impl Serialize for CoreEvent {
+    fn serialize(&self, w: &mut Writer) {
+        match *self {
+            CoreEvent::Move{unit_id, from, to, mode, cost} => {
+                w.write_tag("Move");
+                unit_id.serialize(w);
+                from.serialize(w);
+                to.serialize(w);
+                mode.serialize(w);
+                cost.serialize(w);
+            },
+            CoreEvent::EndTurn{old_id, new_id} => {
+                w.write_tag("EndTurn");
+                old_id.serialize(w);
+                new_id.serialize(w);
+            },
+            CoreEvent::CreateUnit{ref unit_info} => {
+                w.write_tag("CreateUnit");
+                unit_info.serialize(w);
+            },
+            CoreEvent::AttackUnit{ref attack_info} => {
+                w.write_tag("AttackUnit");
+                attack_info.serialize(w);
+            },
+            CoreEvent::Reveal{ref unit_info} => {
+                w.write_tag("Reveal");
+                unit_info.serialize(w);
+            },
+            CoreEvent::ShowUnit{ref unit_info} => {
+                w.write_tag("ShowUnit");
+                unit_info.serialize(w);
+            },
+            CoreEvent::HideUnit{unit_id} => {
+                w.write_tag("HideUnit");
+                unit_id.serialize(w);
+            },
+            CoreEvent::LoadUnit{transporter_id, passenger_id, from, to} => {
+                w.write_tag("LoadUnit");
+                transporter_id.serialize(w);
+                passenger_id.serialize(w);
+                from.serialize(w);
+                to.serialize(w);
+            },
+            CoreEvent::UnloadUnit{ref unit_info, transporter_id, from, to} => {
+                w.write_tag("UnloadUnit");
+                unit_info.serialize(w);
+                transporter_id.serialize(w);
+                from.serialize(w);
+                to.serialize(w);
+            },
+            CoreEvent::Attach{transporter_id, attached_unit_id, from, to} => {
+                w.write_tag("Attach");
+                transporter_id.serialize(w);
+                attached_unit_id.serialize(w);
+                from.serialize(w);
+                to.serialize(w);
+            },
+            CoreEvent::Detach{transporter_id, from, to} => {
+                w.write_tag("Detach");
+                transporter_id.serialize(w);
+                from.serialize(w);
+                to.serialize(w);
+            },
+            CoreEvent::SetReactionFireMode{unit_id, mode} => {
+                w.write_tag("SetReactionFireMode");
+                unit_id.serialize(w);
+                mode.serialize(w);
+            },
+            CoreEvent::SectorOwnerChanged{sector_id, new_owner_id} => {
+                w.write_tag("SectorOwnerChanged");
+                sector_id.serialize(w);
+                new_owner_id.serialize(w);
+            },
+            CoreEvent::VictoryPoint{player_id, pos, count} => {
+                w.write_tag("VictoryPoint");
+                player_id.serialize(w);
+                pos.serialize(w);
+                count.serialize(w);
+            },
+            CoreEvent::Smoke{id, pos, unit_id} => {
+                w.write_tag("Smoke");
+                id.serialize(w);
+                pos.serialize(w);
+                unit_id.serialize(w);
+            },
+            CoreEvent::RemoveSmoke{id} => {
+                w.write_tag("RemoveSmoke");
+                id.serialize(w);
+            },
+            CoreEvent::TimeLeft{player_id, turn, game} => {
+                w.write_tag("TimeLeft");
+                player_id.serialize(w);
+                turn.serialize(w);
+                game.serialize(w);
+            },
+        }
+    }
+}
+
+impl Deserialize for CoreEvent {
+    fn deserialize(r: &mut Reader) -> Result<CoreEvent, DeserializeError> {
+        let tag = r.read_tag()?;
+        let event = match tag.as_str() {
+            "Move" => CoreEvent::Move {
+                unit_id: r.read()?,
+                from: r.read()?,
+                to: r.read()?,
+                mode: r.read()?,
+                cost: r.read()?,
+            },
+            "EndTurn" => CoreEvent::EndTurn {
+                old_id: r.read()?,
+                new_id: r.read()?,
+            },
+            "CreateUnit" => CoreEvent::CreateUnit {
+                unit_info: r.read()?,
+            },
+            "AttackUnit" => CoreEvent::AttackUnit {
+                attack_info: r.read()?,
+            },
+            "Reveal" => CoreEvent::Reveal {
+                unit_info: r.read()?,
+            },
+            "ShowUnit" => CoreEvent::ShowUnit {
+                unit_info: r.read()?,
+            },
+            "HideUnit" => CoreEvent::HideUnit {
+                unit_id: r.read()?,
+            },
+            "LoadUnit" => CoreEvent::LoadUnit {
+                transporter_id: r.read()?,
+                passenger_id: r.read()?,
+                from: r.read()?,
+                to: r.read()?,
+            },
+            "UnloadUnit" => CoreEvent::UnloadUnit {
+                unit_info: r.read()?,
+                transporter_id: r.read()?,
+                from: r.read()?,
+                to: r.read()?,
+            },
+            "Attach" => CoreEvent::Attach {
+                transporter_id: r.read()?,
+                attached_unit_id: r.read()?,
+                from: r.read()?,
+                to: r.read()?,
+            },
+            "Detach" => CoreEvent::Detach {
+                transporter_id: r.read()?,
+                from: r.read()?,
+                to: r.read()?,
+            },
+            "SetReactionFireMode" => CoreEvent::SetReactionFireMode {
+                unit_id: r.read()?,
+                mode: r.read()?,
+            },
+            "SectorOwnerChanged" => CoreEvent::SectorOwnerChanged {
+                sector_id: r.read()?,
+                new_owner_id: r.read()?,
+            },
+            "VictoryPoint" => CoreEvent::VictoryPoint {
+                player_id: r.read()?,
+                pos: r.read()?,
+                count: r.read()?,
+            },
+            "Smoke" => CoreEvent::Smoke {
+                id: r.read()?,
+                pos: r.read()?,
+                unit_id: r.read()?,
+            },
+            "RemoveSmoke" => CoreEvent::RemoveSmoke {
+                id: r.read()?,
+            },
+            "TimeLeft" => CoreEvent::TimeLeft {
+                player_id: r.read()?,
+                turn: r.read()?,
+                game: r.read()?,
+            },
+            _ => return Err(DeserializeError::BadTag(tag)),
+        };
+        Ok(event)
+    }
+}
+
+impl Serialize for ObjectClass {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_tag(match *self {
+            ObjectClass::Building => "Building",
+            ObjectClass::Road => "Road",
+            ObjectClass::Smoke => "Smoke",
+            ObjectClass::ReinforcementSector => "ReinforcementSector",
+        });
+    }
+}
+
+impl Deserialize for ObjectClass {
+    fn deserialize(r: &mut Reader) -> Result<ObjectClass, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Building" => Ok(ObjectClass::Building),
+            "Road" => Ok(ObjectClass::Road),
+            "Smoke" => Ok(ObjectClass::Smoke),
+            "ReinforcementSector" => Ok(ObjectClass::ReinforcementSector),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for Object {
+    fn serialize(&self, w: &mut Writer) {
+        self.pos.serialize(w);
+        self.class.serialize(w);
+        self.timer.serialize(w);
+        self.owner_id.serialize(w);
+    }
+}
+
+impl Deserialize for Object {
+    fn deserialize(r: &mut Reader) -> Result<Object, DeserializeError> {
+        Ok(Object {
+            pos: r.read()?,
+            class: r.read()?,
+            timer: r.read()?,
+            owner_id: r.read()?,
+        })
+    }
+}
+
+impl Serialize for Sector {
+    fn serialize(&self, w: &mut Writer) {
+        self.owner_id.serialize(w);
+        self.positions.serialize(w);
+    }
+}
+
+impl Deserialize for Sector {
+    fn deserialize(r: &mut Reader) -> Result<Sector, DeserializeError> {
+        Ok(Sector {
+            owner_id: r.read()?,
+            positions: r.read()?,
+        })
+    }
+}
+
+impl Serialize for Terrain {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_tag(match *self {
+            Terrain::Plain => "Plain",
+            Terrain::Trees => "Trees",
+            Terrain::City => "City",
+            Terrain::Water => "Water",
+        });
+    }
+}
+
+impl Deserialize for Terrain {
+    fn deserialize(r: &mut Reader) -> Result<Terrain, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Plain" => Ok(Terrain::Plain),
+            "Trees" => Ok(Terrain::Trees),
+            "City" => Ok(Terrain::City),
+            "Water" => Ok(Terrain::Water),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for PlayerClass {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_tag(match *self {
+            PlayerClass::Human => "Human",
+            PlayerClass::Ai => "Ai",
+        });
+    }
+}
+
+impl Deserialize for PlayerClass {
+    fn deserialize(r: &mut Reader) -> Result<PlayerClass, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Human" => Ok(PlayerClass::Human),
+            "Ai" => Ok(PlayerClass::Ai),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for Difficulty {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_tag(match *self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        });
+    }
+}
+
+impl Deserialize for Difficulty {
+    fn deserialize(r: &mut Reader) -> Result<Difficulty, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Easy" => Ok(Difficulty::Easy),
+            "Normal" => Ok(Difficulty::Normal),
+            "Hard" => Ok(Difficulty::Hard),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for TurnMode {
+    fn serialize(&self, w: &mut Writer) {
+        w.write_tag(match *self {
+            TurnMode::Alternate => "Alternate",
+            TurnMode::Simultaneous => "Simultaneous",
+        });
+    }
+}
+
+impl Deserialize for TurnMode {
+    fn deserialize(r: &mut Reader) -> Result<TurnMode, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Alternate" => Ok(TurnMode::Alternate),
+            "Simultaneous" => Ok(TurnMode::Simultaneous),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for SearchBudget {
+    fn serialize(&self, w: &mut Writer) {
+        match *self {
+            SearchBudget::Rollouts(n) => {
+                w.write_tag("Rollouts");
+                n.serialize(w);
+            },
+            SearchBudget::Time(time) => {
+                w.write_tag("Time");
+                time.serialize(w);
+            },
+        }
+    }
+}
+
+impl Deserialize for SearchBudget {
+    fn deserialize(r: &mut Reader) -> Result<SearchBudget, DeserializeError> {
+        let tag = r.read_tag()?;
+        match tag.as_str() {
+            "Rollouts" => Ok(SearchBudget::Rollouts(r.read()?)),
+            "Time" => Ok(SearchBudget::Time(r.read()?)),
+            _ => Err(DeserializeError::BadTag(tag)),
+        }
+    }
+}
+
+impl Serialize for RememberedUnit {
+    fn serialize(&self, w: &mut Writer) {
+        self.unit.serialize(w);
+        self.confidence.serialize(w);
+    }
+}
+
+impl Deserialize for RememberedUnit {
+    fn deserialize(r: &mut Reader) -> Result<RememberedUnit, DeserializeError> {
+        Ok(RememberedUnit {
+            unit: r.read()?,
+            confidence: r.read()?,
+        })
+    }
+}
+
+impl Serialize for EnemyMemory {
+    fn serialize(&self, w: &mut Writer) {
+        self.player_id().serialize(w);
+        let units = self.units();
+        serialize_seq(w, units.len(), units);
+    }
+}
+
+impl Deserialize for EnemyMemory {
+    fn deserialize(r: &mut Reader) -> Result<EnemyMemory, DeserializeError> {
+        let player_id = r.read()?;
+        let units = deserialize_seq(r)?;
+        Ok(EnemyMemory::from_units(player_id, units))
+    }
+}
+
+impl Serialize for Player {
+    fn serialize(&self, w: &mut Writer) {
+        self.id.serialize(w);
+        self.class.serialize(w);
+        self.team_id.serialize(w);
+    }
+}
+
+impl Deserialize for Player {
+    fn deserialize(r: &mut Reader) -> Result<Player, DeserializeError> {
+        Ok(Player {
+            id: r.read()?,
+            class: r.read()?,
+            team_id: r.read()?,
+        })
+    }
+}
+
+impl Serialize for Options {
+    fn serialize(&self, w: &mut Writer) {
+        self.players.serialize(w);
+        self.teams.serialize(w);
+        self.shared_vision.serialize(w);
+        self.turn_mode.serialize(w);
+        self.turn_time_limit.serialize(w);
+        self.game_time_limit.serialize(w);
+        (self.ais.len() as u64).serialize(w);
+        for (player_id, builder) in &self.ais {
+            player_id.serialize(w);
+            w.write_str(builder.name());
+        }
+        self.difficulty.serialize(w);
+        w.new_line();
+        self.scenario.serialize(w);
+        w.new_line();
+        self.seed.serialize(w);
+    }
+}
+
+/// Options can't be just `Deserialize`: their AIs are looked up by name
+pub fn read_options(r: &mut Reader, find_ai: &AiFinder) -> Result<Options, DeserializeError> {
+    let players = r.read()?;
+    let teams = r.read()?;
+    let shared_vision = r.read()?;
+    let turn_mode = r.read()?;
+    let turn_time_limit = r.read()?;
+    let game_time_limit = r.read()?;
+    let mut ais = BTreeMap::new();
+    let ais_count: u64 = r.read()?;
+    for _ in 0 .. ais_count {
+        let player_id = r.read()?;
+        ais.insert(player_id, read_ai_builder(r, find_ai)?);
+    }
+    Ok(Options {
+        players: players,
+        teams: teams,
+        shared_vision: shared_vision,
+        turn_mode: turn_mode,
+        turn_time_limit: turn_time_limit,
+        game_time_limit: game_time_limit,
+        ais: ais,
+        difficulty: r.read()?,
+        scenario: r.read()?,
+        seed: r.read()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::{Debug};
+    use std::time::{Duration};
+    use cgmath::{Vector2};
+    use Core;
+    use options::{Options};
+    use player::{PlayerId, PlayerClass};
+    use scenario::{test_scenario};
+    use unit::{Unit, UnitId, UnitTypeId};
+    use position::{MapPos, ExactPos, SlotId};
+    use dir::{Dir};
+    use event::{CoreEvent, Command, AttackInfo, FireMode, ReactionFireMode, MoveMode};
+    use object::{Object, ObjectId, ObjectClass};
+    use sector::{Sector, SectorId};
+    use movement::{MovePoints};
+    use attack::{AttackPoints};
+    use game_state::{State};
+    use orders::{Order};
+    use serialize::{
+        Serialize,
+        Deserialize,
+        DeserializeError,
+        Writer,
+        Reader,
+        Format,
+        FORMAT_VERSION,
+        FORMAT_MAGIC,
+        encode,
+        decode,
+        write_header,
+        read_header,
+    };
+
+    /// Some types can't be compared, so the check is that
+    /// a decoded value encodes to the same data in both formats
+    fn round_trip<T: Serialize + Deserialize>(value: &T) -> T {
+        let text = encode(value, Format::Text);
+        let binary = encode(value, Format::Binary);
+        let from_text: T = decode(&text).unwrap();
+        let from_binary: T = decode(&binary).unwrap();
+        assert_eq!(encode(&from_text, Format::Text), text);
+        assert_eq!(encode(&from_text, Format::Binary), binary);
+        assert_eq!(encode(&from_binary, Format::Text), text);
+        assert_eq!(encode(&from_binary, Format::Binary), binary);
+        from_binary
+    }
+
+    fn check_eq<T: Serialize + Deserialize + PartialEq + Debug>(value: T) {
+        assert_eq!(round_trip(&value), value);
+    }
+
+    fn pos(x: i32, y: i32, slot_id: SlotId) -> ExactPos {
+        ExactPos{map_pos: MapPos{v: Vector2{x: x, y: y}}, slot_id: slot_id}
+    }
+
+    fn unit() -> Unit {
+        Unit {
+            id: UnitId{id: 7},
+            pos: pos(3, -2, SlotId::WholeTile),
+            player_id: PlayerId{id: 1},
+            type_id: UnitTypeId{id: 4},
+            move_points: Some(MovePoints{n: 12}),
+            attack_points: None,
+            reactive_attack_points: Some(AttackPoints{n: 1}),
+            reaction_fire_mode: ReactionFireMode::HoldFire,
+            count: 3,
+            morale: -10,
+            passenger_id: Some(UnitId{id: 8}),
+            attached_unit_id: None,
+            is_alive: true,
+            is_loaded: false,
+            is_attached: false,
+        }
+    }
+
+    fn attack_info() -> AttackInfo {
+        AttackInfo {
+            attacker_id: None,
+            defender_id: UnitId{id: 2},
+            mode: FireMode::Reactive,
+            killed: 1,
+            suppression: 25,
+            remove_move_points: true,
+            is_ambush: false,
+            is_inderect: true,
+            leave_wrecks: false,
+        }
+    }
+
+    fn new_core() -> Core {
+        Core::new(&Options {
+            turn_time_limit: Some(Duration::from_secs(90)),
+            game_time_limit: Some(Duration::from_secs(600)),
+            seed: Some(5),
+            .. Options::new(vec![PlayerClass::Human, PlayerClass::Ai], test_scenario())
+        })
+    }
+
+    #[test]
+    fn test_primitives() {
+        check_eq(0);
+        check_eq(-1);
+        check_eq(i32::min_value());
+        check_eq(i32::max_value());
+        check_eq(u8::max_value());
+        check_eq(u32::max_value());
+        check_eq(Duration::from_millis(1500));
+        check_eq(u64::max_value());
+        check_eq(true);
+        check_eq(false);
+        check_eq("".to_owned());
+        check_eq("\"quoted\" \\ new\nline, ünïcode".to_owned());
+        check_eq(Some(vec![1, 2, 3]));
+        check_eq(None::<i32>);
+    }
+
+    #[test]
+    fn test_positions() {
+        check_eq(MapPos{v: Vector2{x: -5, y: 300}});
+        check_eq(pos(0, 0, SlotId::Id(0)));
+        check_eq(pos(1, 2, SlotId::Id(2)));
+        check_eq(pos(1, 2, SlotId::WholeTile));
+        check_eq(pos(1, 2, SlotId::Air));
+        for &dir in &[Dir::SouthEast, Dir::East, Dir::NorthEast,
+            Dir::NorthWest, Dir::West, Dir::SouthWest]
+        {
+            check_eq(pos(1, 2, SlotId::TwoTiles(dir)));
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let unit_id = UnitId{id: 1};
+        let other_id = UnitId{id: 2};
+        let to = pos(2, 1, SlotId::Id(1));
+        let commands = vec![
+            Command::Move{unit_id: unit_id, path: vec![pos(1, 1, SlotId::Id(0)), to], mode: MoveMode::Fast},
+            Command::Move{unit_id: unit_id, path: vec![], mode: MoveMode::Hunt},
+            Command::EndTurn,
+            Command::CreateUnit{pos: to, type_id: UnitTypeId{id: 3}},
+            Command::AttackUnit{attacker_id: unit_id, defender_id: other_id},
+            Command::LoadUnit{transporter_id: unit_id, passenger_id: other_id},
+            Command::UnloadUnit{transporter_id: unit_id, passenger_id: other_id, pos: to},
+            Command::Attach{transporter_id: unit_id, attached_unit_id: other_id},
+            Command::Detach{transporter_id: unit_id, pos: to},
+            Command::SetReactionFireMode{unit_id: unit_id, mode: ReactionFireMode::Normal},
+            Command::PassTime{time: Duration::from_millis(1500)},
+            Command::TimeOut,
+            Command::SetReactionFireMode{unit_id: unit_id, mode: ReactionFireMode::HoldFire},
+            Command::Smoke{unit_id: unit_id, pos: to.map_pos},
+            Command::SetOrders{unit_id: unit_id, orders: vec![
+                Order::Move{path: vec![pos(1, 1, SlotId::Id(0)), to], mode: MoveMode::Fast},
+                Order::Attack{defender_id: other_id},
+                Order::Smoke{pos: to.map_pos},
+            ]},
+            Command::SetOrders{unit_id: unit_id, orders: vec![]},
+        ];
+        for command in commands {
+            check_eq(command);
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let unit_id = UnitId{id: 1};
+        let from = pos(1, 1, SlotId::Id(0));
+        let to = pos(2, 1, SlotId::WholeTile);
+        let events = vec![
+            CoreEvent::Move{unit_id: unit_id, from: from, to: to, mode: MoveMode::Hunt, cost: MovePoints{n: 4}},
+            CoreEvent::EndTurn{old_id: PlayerId{id: 0}, new_id: PlayerId{id: 1}},
+            CoreEvent::CreateUnit{unit_info: unit()},
+            CoreEvent::AttackUnit{attack_info: attack_info()},
+            CoreEvent::AttackUnit{attack_info: AttackInfo {
+                attacker_id: Some(unit_id),
+                mode: FireMode::Active,
+                .. attack_info()
+            }},
+            CoreEvent::Reveal{unit_info: unit()},
+            CoreEvent::ShowUnit{unit_info: unit()},
+            CoreEvent::HideUnit{unit_id: unit_id},
+            CoreEvent::LoadUnit{transporter_id: None, passenger_id: unit_id, from: from, to: to},
+            CoreEvent::UnloadUnit{unit_info: unit(), transporter_id: Some(unit_id), from: from, to: to},
+            CoreEvent::Attach{transporter_id: unit_id, attached_unit_id: UnitId{id: 2}, from: from, to: to},
+            CoreEvent::Detach{transporter_id: unit_id, from: from, to: to},
+            CoreEvent::SetReactionFireMode{unit_id: unit_id, mode: ReactionFireMode::HoldFire},
+            CoreEvent::SectorOwnerChanged{sector_id: SectorId{id: 0}, new_owner_id: None},
+            CoreEvent::SectorOwnerChanged{sector_id: SectorId{id: 1}, new_owner_id: Some(PlayerId{id: 1})},
+            CoreEvent::VictoryPoint{player_id: PlayerId{id: 0}, pos: to.map_pos, count: 2},
+            CoreEvent::Smoke{id: ObjectId{id: 3}, pos: to.map_pos, unit_id: Some(unit_id)},
+            CoreEvent::RemoveSmoke{id: ObjectId{id: 3}},
+            CoreEvent::TimeLeft {
+                player_id: PlayerId{id: 1},
+                turn: Duration::from_millis(59_500),
+                game: None,
+            },
+            CoreEvent::TimeLeft {
+                player_id: PlayerId{id: 0},
+                turn: Duration::from_secs(0),
+                game: Some(Duration::from_secs(600)),
+            },
+        ];
+        for event in &events {
+            let decoded = round_trip(event);
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", event));
+        }
+    }
+
+    #[test]
+    fn test_objects_and_sectors() {
+        let classes = [
+            ObjectClass::Building,
+            ObjectClass::Road,
+            ObjectClass::Smoke,
+            ObjectClass::ReinforcementSector,
+        ];
+        for &class in &classes {
+            let object = Object {
+                pos: pos(4, 4, SlotId::TwoTiles(Dir::West)),
+                class: class,
+                timer: Some(2),
+                owner_id: Some(PlayerId{id: 1}),
+            };
+            let decoded = round_trip(&object);
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", object));
+        }
+        let sector = Sector {
+            owner_id: None,
+            positions: vec![MapPos{v: Vector2{x: 1, y: 2}}, MapPos{v: Vector2{x: 2, y: 2}}],
+        };
+        let decoded = round_trip(&sector);
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", sector));
+    }
+
+    #[test]
+    fn test_state() {
+        let core = new_core();
+        let mut saved = Vec::new();
+        for &format in &[Format::Text, Format::Binary] {
+            let mut w = Writer::with_format(format);
+            write_header(&mut w, FORMAT_MAGIC, FORMAT_VERSION);
+            core.state().save(&mut w);
+            saved.push(w.into_bytes());
+        }
+        let text = String::from_utf8(saved[0].clone()).unwrap();
+        let mut r = Reader::new(&text);
+        read_header(&mut r, FORMAT_MAGIC, FORMAT_VERSION).unwrap();
+        let from_text = State::load(&mut r, core.db().clone()).unwrap();
+        assert!(r.is_finished());
+        let mut r = Reader::new_binary(&saved[1]);
+        read_header(&mut r, FORMAT_MAGIC, FORMAT_VERSION).unwrap();
+        let from_binary = State::load(&mut r, core.db().clone()).unwrap();
+        assert!(r.is_finished());
+        for state in &[from_text, from_binary] {
+            let mut w = Writer::new();
+            write_header(&mut w, FORMAT_MAGIC, FORMAT_VERSION);
+            state.save(&mut w);
+            assert_eq!(w.into_bytes(), saved[0]);
+        }
+        assert!(saved[1].len() < saved[0].len());
+    }
+
+    #[test]
+    fn test_bad_data() {
+        let text = format!("zoc {} 5", FORMAT_VERSION + 1);
+        assert_eq!(decode::<i32>(text.as_bytes()),
+            Err(DeserializeError::BadVersion(FORMAT_VERSION + 1)));
+        assert_eq!(decode::<i32>(b"zoc 1 5 6"),
+            Err(DeserializeError::BadData("data after the end".into())));
+        assert_eq!(decode::<i32>(b"zoc 1 3000000000"),
+            Err(DeserializeError::BadData("3000000000 is out of range".into())));
+        let binary = encode(&"some text".to_owned(), Format::Binary);
+        assert_eq!(decode::<String>(&binary[.. binary.len() - 1]),
+            Err(DeserializeError::UnexpectedEnd));
+        // Only the header's tag is known here
+        assert_eq!(decode::<Command>(&encode(&7u64, Format::Binary)),
+            Err(DeserializeError::BadData("unknown tag index 7".into())));
+    }
+}
@@ -3,13 +3,15 @@ use std::collections::{HashMap};
 use cgmath::{Vector2};
 use glutin::{self, WindowEvent, MouseButton, VirtualKeyCode};
 use glutin::ElementState::{Released};
-use core;
 use core::object::{ObjectClass};
 use core::position::{self, MapPos, ExactPos};
 use core::unit::{UnitId};
 use core::game_state::{State};
 use core::db::{Db};
 use core::check::{check_command};
+use core::options::{TurnMode};
+use core::orders::{Plan, check_orders, command_to_order};
+use core::player::{PlayerId};
 use core::attack;
 use core::event::Command as CoreCommand;
 use core::event::{ReactionFireMode, MoveMode};
@@ -19,6 +21,26 @@ use context::{Context};
 use gui::{ButtonManager, Button, ButtonId, is_tap, basic_text_size};
 use player_info::{PlayerInfo};
 use reinforcements_popup;
+use core_link::{CoreLink};
+
+/// Commands that are orders in simultaneous turns are checked
+/// as the unit's next order
+fn can_do(
+    db: &Db,
+    player_id: PlayerId,
+    state: &State,
+    plan: &Plan,
+    command: &CoreCommand,
+) -> bool {
+    if state.turn_mode() == TurnMode::Simultaneous {
+        if let Some((unit_id, order)) = command_to_order(command) {
+            let mut orders = plan.orders(unit_id).to_vec();
+            orders.push(order);
+            return check_orders(db, player_id, state, unit_id, &orders).is_ok();
+        }
+    }
+    check_command(db, player_id, state, command).is_ok()
+}
 
 fn can_unload_unit(
     db: &Db,
@@ -78,16 +100,19 @@ fn can_detach_unit(
 }
 
 pub fn get_options(
-    core: &core::Core,
+    core: &CoreLink,
     player_info: &PlayerInfo,
     selected_unit_id: Option<UnitId>,
     pos: MapPos,
 ) -> Options {
     let state = &player_info.game_state;
     let pathfinder = &player_info.pathfinder;
+    let plan = &player_info.plan;
     let db = core.db();
     let mut options = Options::new();
     let player_id = core.player_id();
+    // There are no transport orders, see `check::is_transport`
+    let can_transport = state.turn_mode() != TurnMode::Simultaneous;
     let unit_ids = position::get_unit_ids_at(state, pos);
     for object in state.objects_at(pos) {
         if object.class != ObjectClass::ReinforcementSector
@@ -131,6 +156,11 @@ pub fn get_options(
             } else {
                 if unit.is_alive {
                     options.selects.push(unit_id);
+                }
+                if !can_transport {
+                    continue;
+                }
+                if unit.is_alive {
                     let load_command = CoreCommand::LoadUnit {
                         transporter_id: selected_unit_id,
                         passenger_id: unit_id,
@@ -155,22 +185,24 @@ pub fn get_options(
                 attacker_id: attacker.id,
                 defender_id: defender.id,
             };
-            if check_command(db, player_id, state, &attack_command).is_ok() {
+            if can_do(db, player_id, state, plan, &attack_command) {
                 options.attacks.push((unit_id, hit_chance));
             }
         }
     }
-    if check_command(db, player_id, state, &CoreCommand::Smoke {
+    if can_do(db, player_id, state, plan, &CoreCommand::Smoke {
         unit_id: selected_unit_id,
         pos: pos,
-    }).is_ok() {
+    }) {
         options.smoke_pos = Some(pos);
     }
-    if let Some(pos) = can_unload_unit(db, state, selected_unit_id, pos) {
-        options.unload_pos = Some(pos);
-    }
-    if let Some(pos) = can_detach_unit(db, state, selected_unit_id, pos) {
-        options.detach_pos = Some(pos);
+    if can_transport {
+        if let Some(pos) = can_unload_unit(db, state, selected_unit_id, pos) {
+            options.unload_pos = Some(pos);
+        }
+        if let Some(pos) = can_detach_unit(db, state, selected_unit_id, pos) {
+            options.detach_pos = Some(pos);
+        }
     }
     let selected_unit = state.unit(selected_unit_id);
     let selected_unit_type = db.unit_type(selected_unit.type_id);
@@ -178,11 +210,11 @@ pub fn get_options(
         state, selected_unit_type, pos,
     ) {
         if let Some(path) = pathfinder.get_path(destination) {
-            if check_command(db, player_id, state, &CoreCommand::Move {
+            if can_do(db, player_id, state, plan, &CoreCommand::Move {
                 unit_id: selected_unit_id,
                 path: path.clone(),
                 mode: MoveMode::Fast,
-            }).is_ok() {
+            }) {
                 options.move_pos = Some(destination);
             }
             let hunt_command = CoreCommand::Move {
@@ -191,7 +223,7 @@ pub fn get_options(
                 mode: MoveMode::Hunt,
             };
             if !selected_unit_type.is_air
-                && check_command(db, player_id, state, &hunt_command).is_ok()
+                && can_do(db, player_id, state, plan, &hunt_command)
             {
                 options.hunt_pos = Some(destination);
             }
@@ -0,0 +1,169 @@
+//! `TacticalScreen` plays the same way with the core in this process
+//! and with a core on a server
+
+use std::rc::{Rc};
+use std::time::{Duration};
+use core::{Core};
+use core::db::{Db};
+use core::event::{CoreEvent, Command};
+use core::player::{PlayerId};
+use core::net::{Client, ServerMessage};
+
+#[derive(Debug)]
+pub struct RemoteCore {
+    client: Client,
+    current_player_id: PlayerId,
+    players_count: i32,
+    is_disconnected: bool,
+}
+
+impl RemoteCore {
+    pub fn new(client: Client) -> RemoteCore {
+        let players_count = client.options().players.len() as i32;
+        let current_player_id = client.current_player_id();
+        RemoteCore {
+            client: client,
+            current_player_id: current_player_id,
+            players_count: players_count,
+            is_disconnected: false,
+        }
+    }
+
+    /// Only own events come from the server
+    fn get_event(&mut self) -> Option<CoreEvent> {
+        if self.is_disconnected {
+            return None;
+        }
+        loop {
+            match self.client.get_message() {
+                Ok(Some(ServerMessage::Event(event))) => {
+                    if let CoreEvent::EndTurn{new_id, ..} = event {
+                        self.current_player_id = new_id;
+                    }
+                    return Some(event);
+                },
+                Ok(Some(ServerMessage::Rejected{reason})) => {
+                    println!("Server rejected the command: {}", reason);
+                },
+                Ok(Some(_)) => {
+                    println!("Unexpected message from the server");
+                },
+                Ok(None) => return None,
+                Err(err) => {
+                    println!("Lost the server: {}", err);
+                    self.is_disconnected = true;
+                    return None;
+                },
+            }
+        }
+    }
+
+    fn do_command(&mut self, command: Command) {
+        if self.is_disconnected {
+            return;
+        }
+        if command == Command::EndTurn {
+            // Commands that are sent before our EndTurn event comes back
+            // would be done in our next turn
+            let id = (self.client.player_id().id + 1) % self.players_count;
+            self.current_player_id = PlayerId{id: id};
+        }
+        if let Err(err) = self.client.send_command(command) {
+            println!("Lost the server: {}", err);
+            self.is_disconnected = true;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CoreLink {
+    Local(Core),
+    Remote(RemoteCore),
+}
+
+impl CoreLink {
+    pub fn db(&self) -> &Rc<Db> {
+        match *self {
+            CoreLink::Local(ref core) => core.db(),
+            CoreLink::Remote(ref remote) => remote.client.db(),
+        }
+    }
+
+    /// The local core, if it's not a network game
+    pub fn local(&self) -> Option<&Core> {
+        match *self {
+            CoreLink::Local(ref core) => Some(core),
+            CoreLink::Remote(_) => None,
+        }
+    }
+
+    pub fn local_mut(&mut self) -> Option<&mut Core> {
+        match *self {
+            CoreLink::Local(ref mut core) => Some(core),
+            CoreLink::Remote(_) => None,
+        }
+    }
+
+    pub fn player_id(&self) -> PlayerId {
+        match *self {
+            CoreLink::Local(ref core) => core.player_id(),
+            CoreLink::Remote(ref remote) => remote.current_player_id,
+        }
+    }
+
+    pub fn next_player_id(&self, id: PlayerId) -> PlayerId {
+        match *self {
+            CoreLink::Local(ref core) => core.next_player_id(id),
+            CoreLink::Remote(ref remote) => {
+                PlayerId{id: (id.id + 1) % remote.players_count}
+            },
+        }
+    }
+
+    /// Nobody at this screen can give commands now
+    pub fn is_waiting(&self) -> bool {
+        match *self {
+            CoreLink::Local(ref core) => core.is_ai_turn(),
+            CoreLink::Remote(ref remote) => {
+                remote.current_player_id != remote.client.player_id()
+            },
+        }
+    }
+
+    /// Lets the local AI think, the server plays its AI by itself
+    pub fn do_ai_step(&mut self, budget: Duration) {
+        if let CoreLink::Local(ref mut core) = *self {
+            if core.is_ai_turn() {
+                core.do_ai_step(budget);
+            }
+        }
+    }
+
+    /// Runs the clock of a timed turn and ends the turn when
+    /// its time is out, the server runs its own
+    pub fn pass_time(&mut self, time: Duration) {
+        if let CoreLink::Local(ref mut core) = *self {
+            core.pass_time(time);
+            if core.is_time_out() {
+                core.do_command(Command::TimeOut);
+            }
+        }
+    }
+
+    pub fn do_command(&mut self, command: Command) {
+        match *self {
+            CoreLink::Local(ref mut core) => core.do_command(command),
+            CoreLink::Remote(ref mut remote) => remote.do_command(command),
+        }
+    }
+
+    pub fn get_event_for(&mut self, player_id: PlayerId) -> Option<CoreEvent> {
+        match *self {
+            CoreLink::Local(ref mut core) => core.get_event_for(player_id),
+            CoreLink::Remote(ref mut remote) => {
+                assert_eq!(player_id, remote.client.player_id());
+                remote.get_event()
+            },
+        }
+    }
+}
@@ -596,6 +596,8 @@ impl EventSectorOwnerChangedVisualizer {
             None => [1.0, 1.0, 1.0, 0.5],
             Some(PlayerId{id: 0}) => [0.0, 0.0, 0.8, 0.5],
             Some(PlayerId{id: 1}) => [0.0, 0.8, 0.0, 0.5],
+            Some(PlayerId{id: 2}) => [0.8, 0.0, 0.0, 0.5],
+            Some(PlayerId{id: 3}) => [0.8, 0.8, 0.0, 0.5],
             Some(_) => unimplemented!(),
         };
         let node_id = scene.sector_id_to_node_id(sector_id);
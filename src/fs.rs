@@ -1,5 +1,5 @@
-use std::path::{Path};
-use std::io::{Cursor};
+use std::path::{Path, PathBuf};
+use std::io::{self, Cursor};
 
 pub fn load_as_string<P: AsRef<Path>>(path: P) -> String {
     String::from_utf8(load(path).into_inner()).unwrap()
@@ -38,3 +38,35 @@ pub fn load<P: AsRef<Path>>(path: P) -> Cursor<Vec<u8>> {
         Err(_) => panic!("Can`t load asset '{}'", filename),
     }
 }
+
+/// Writes a user's file (not an asset), like a saved game
+pub fn save_user_file<P: AsRef<Path>>(path: P, data: &str) -> io::Result<()> {
+    use std::fs::{File};
+    use std::io::{Write};
+
+    let mut file = File::create(path)?;
+    file.write_all(data.as_bytes())
+}
+
+/// Reads a user's file (not an asset), like a saved game
+pub fn load_user_file_as_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    use std::fs::{File};
+    use std::io::{Read};
+
+    let mut s = String::new();
+    let mut file = File::open(path)?;
+    file.read_to_string(&mut s)?;
+    Ok(s)
+}
+
+/// Paths of all files in a user's directory (not in assets)
+pub fn list_dir<P: AsRef<Path>>(path: P) -> io::Result<Vec<PathBuf>> {
+    use std::fs::{read_dir};
+
+    let mut paths = Vec::new();
+    for entry in read_dir(path)? {
+        paths.push(entry?.path());
+    }
+    paths.sort();
+    Ok(paths)
+}
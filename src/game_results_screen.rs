@@ -1,20 +1,22 @@
 use cgmath::{Vector2};
 use glutin::{self, WindowEvent, MouseButton, KeyboardInput, VirtualKeyCode};
 use glutin::ElementState::{Released};
-use core::player::{PlayerId};
+use core::player::{PlayerId, TeamId};
 use core::game_state::{State, Score};
 use screen::{Screen, ScreenCommand, EventStatus};
 use context::{Context};
 use gui::{ButtonManager, Button, is_tap};
 use types::{ScreenPos, Time};
 
-fn winner_id(state: &State) -> PlayerId {
+fn winner_team_id(state: &State) -> TeamId {
     // TODO: `CoreEvent::GameEnd` event?
-    let mut winner_id = PlayerId{id: 0};
+    let mut winner_id = state.team_id(PlayerId{id: 0});
     let mut winner_score = Score{n: 0};
-    for (&id, &score) in state.score() {
+    for &id in state.score().keys() {
+        let team_id = state.team_id(id);
+        let score = state.team_score(team_id);
         if score.n > winner_score.n {
-            winner_id = id;
+            winner_id = team_id;
             winner_score = score;
         }
     }
@@ -32,8 +34,8 @@ impl GameResultsScreen {
         let wh = context.win_size().h;
         let mut pos = ScreenPos{v: Vector2{x: 10, y: wh -10}};
         pos.v.y -= wh / 10; // TODO: magic num
-        let winner_index = winner_id(state);
-        let str = format!("Player {} wins!", winner_index.id);
+        let winner_team_id = winner_team_id(state);
+        let str = format!("Team {} wins!", winner_team_id.id);
         let title_button = Button::new(context, &str, pos);
         pos.v.y -= title_button.size().h; // TODO: autolayout
         let _ = button_manager.add_button(title_button);
@@ -22,6 +22,8 @@ pub fn get_player_color(player_id: PlayerId) -> [f32; 4] {
     match player_id.id {
         0 => [0.1, 0.1, 1.0, 1.0],
         1 => [0.0, 0.8, 0.0, 1.0],
+        2 => [0.9, 0.1, 0.1, 1.0],
+        3 => [0.9, 0.9, 0.0, 1.0],
         n => panic!("Wrong player id: {}", n),
     }
 }
@@ -133,7 +135,7 @@ pub fn build_targets_mesh(db: &Db, context: &mut Context, state: &State, unit_id
     let unit = state.unit(unit_id);
     let mut i = 0;
     for (&enemy_id, enemy) in state.units() {
-        if unit.player_id == enemy.player_id {
+        if state.is_ally(unit.player_id, enemy.player_id) {
             continue;
         }
         let command = Command::AttackUnit {
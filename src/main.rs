@@ -44,6 +44,7 @@ mod context;
 mod text;
 mod mesh;
 mod fs;
+mod core_link;
 
 use visualizer::{Visualizer};
 
@@ -1,33 +1,186 @@
+use std::env;
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::time::{Duration};
 use cgmath::{Vector2};
 use glutin::{self, WindowEvent, MouseButton, VirtualKeyCode};
 use glutin::ElementState::{Released};
 use screen::{Screen, ScreenCommand, EventStatus};
-use tactical_screen::{TacticalScreen};
-use core::options::{Options, GameType};
+use tactical_screen::{TacticalScreen, TURN_FILE_NAME};
+use std::path::{Path};
+use core::options::{Options, Difficulty, TurnMode};
+use core::player::{PlayerClass, TeamId};
+use core::scenario::{Scenario};
+use core::misc::{rx_collect};
+use core::net::{DEFAULT_PORT};
 use context::{Context};
 use gui::{ButtonManager, Button, ButtonId, is_tap};
 use types::{ScreenPos, Time};
+use fs;
 
-#[derive(Clone, Debug)]
+const SAVE_FILE_NAME: &'static str = "zoc.save";
+const SCENARIOS_DIR: &'static str = "scenarios";
+const SCENARIO_EXTENSION: &'static str = "txt";
+const SERVER_ENV_VAR: &'static str = "ZOC_SERVER";
+const SECRET_ENV_VAR: &'static str = "ZOC_SECRET";
+
+/// Turn and game time limits in seconds that the menu offers
+const TIME_LIMITS: &'static [(Option<u64>, Option<u64>)] = &[
+    (None, None),
+    (Some(60), None),
+    (Some(3 * 60), None),
+    (Some(3 * 60), Some(30 * 60)),
+];
+
+/// Maps that come with the game, for when there's no `SCENARIOS_DIR`
+/// next to it: on Android the game can't list its assets
+const BUILTIN_SCENARIOS: &'static [(&'static str, &'static str)] = &[
+    ("map01", include_str!("../scenarios/map01.txt")),
+    ("map02", include_str!("../scenarios/map02.txt")),
+    ("map03", include_str!("../scenarios/map03.txt")),
+    ("map04", include_str!("../scenarios/map04.txt")),
+    ("map05", include_str!("../scenarios/map05.txt")),
+    ("map06", include_str!("../scenarios/map06.txt")),
+];
+
+fn builtin_scenario_names() -> Vec<String> {
+    BUILTIN_SCENARIOS.iter().map(|&(name, _)| name.to_owned()).collect()
+}
+
+fn get_scenario_names() -> Vec<String> {
+    let paths = match fs::list_dir(SCENARIOS_DIR) {
+        Ok(paths) => paths,
+        Err(err) => {
+            println!("Can`t read '{}' ({}), using built-in maps", SCENARIOS_DIR, err);
+            return builtin_scenario_names();
+        },
+    };
+    let mut names = Vec::new();
+    for path in paths {
+        if path.extension().map_or(true, |ext| ext != SCENARIO_EXTENSION) {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+            names.push(name.to_owned());
+        }
+    }
+    if names.is_empty() {
+        builtin_scenario_names()
+    } else {
+        names
+    }
+}
+
+fn load_scenario(name: &str) -> Option<Scenario> {
+    let path = Path::new(SCENARIOS_DIR).join(name).with_extension(SCENARIO_EXTENSION);
+    let builtin_text = BUILTIN_SCENARIOS.iter()
+        .find(|&&(builtin_name, _)| builtin_name == name)
+        .map(|&(_, text)| text.to_owned());
+    let text = match fs::load_user_file_as_string(&path) {
+        Ok(text) => text,
+        Err(err) => match builtin_text {
+            Some(text) => text,
+            None => {
+                println!("Can`t read '{}' ({})", path.display(), err);
+                return None;
+            },
+        },
+    };
+    match text.parse::<Scenario>() {
+        Ok(scenario) => Some(scenario),
+        Err(err) => {
+            println!("Can`t load '{}' ({})", path.display(), err);
+            None
+        },
+    }
+}
+
+fn map_button_text(map_names: &[String], index: usize) -> String {
+    match map_names.get(index) {
+        Some(name) => format!("[map: {}]", name),
+        None => "[map: -]".to_owned(),
+    }
+}
+
+fn difficulty_button_text(difficulty: Difficulty) -> &'static str {
+    match difficulty {
+        Difficulty::Easy => "[ai: easy]",
+        Difficulty::Normal => "[ai: normal]",
+        Difficulty::Hard => "[ai: hard]",
+    }
+}
+
+fn next_difficulty(difficulty: Difficulty) -> Difficulty {
+    match difficulty {
+        Difficulty::Easy => Difficulty::Normal,
+        Difficulty::Normal => Difficulty::Hard,
+        Difficulty::Hard => Difficulty::Easy,
+    }
+}
+
+fn teams_button_text(is_two_teams: bool) -> &'static str {
+    if is_two_teams {
+        "[teams: two teams]"
+    } else {
+        "[teams: free for all]"
+    }
+}
+
+fn turn_mode_button_text(turn_mode: TurnMode) -> &'static str {
+    match turn_mode {
+        TurnMode::Alternate => "[turns: alternate]",
+        TurnMode::Simultaneous => "[turns: simultaneous]",
+    }
+}
+
+fn time_limits_button_text(index: usize) -> String {
+    match TIME_LIMITS[index] {
+        (None, None) => "[time: unlimited]".to_owned(),
+        (Some(turn), None) => format!("[time: {} min per turn]", turn / 60),
+        (None, Some(game)) => format!("[time: {} min per game]", game / 60),
+        (Some(turn), Some(game)) => {
+            format!("[time: {} min per turn, {} per game]", turn / 60, game / 60)
+        },
+    }
+}
+
+#[derive(Debug)]
 pub struct MainMenuScreen {
     button_start_hotseat_id: ButtonId,
     button_start_vs_ai_id: ButtonId,
+    button_difficulty_id: ButtonId,
     button_map_id: ButtonId,
+    button_teams_id: ButtonId,
+    button_turn_mode_id: ButtonId,
+    button_time_limits_id: ButtonId,
+    button_save_id: ButtonId,
+    button_load_id: ButtonId,
+    button_join_id: ButtonId,
+    button_start_pbem_id: ButtonId,
+    button_import_turn_id: ButtonId,
     button_manager: ButtonManager,
-    map_names: Vec<&'static str>,
+    map_names: Vec<String>,
     selected_map_index: usize,
+
+    // Players with even ids against players with odd ids
+    is_two_teams: bool,
+
+    difficulty: Difficulty,
+
+    turn_mode: TurnMode,
+
+    // Index in `TIME_LIMITS`
+    time_limits_index: usize,
+
+    save_tx: Sender<String>,
+    save_rx: Receiver<String>,
+
+    // Save of the last left game
+    last_save: Option<String>,
 }
 
 impl MainMenuScreen {
     pub fn new(context: &mut Context) -> MainMenuScreen {
-        let map_names = vec![
-            "map01",
-            "map02",
-            "map03",
-            "map04",
-            "map05",
-            "map_fov_bug_test",
-        ];
+        let map_names = get_scenario_names();
         let selected_map_index = 0;
         let mut button_manager = ButtonManager::new();
         // TODO: Use relative coords in ScreenPos - x: [0.0, 1.0], y: [0.0, 1.0]
@@ -46,19 +199,94 @@ impl MainMenuScreen {
             "[start human vs ai]",
             button_pos,
         ));
+        let difficulty = Difficulty::Normal;
+        let button_difficulty_pos = ScreenPos{v: Vector2 {
+            x: button_pos.v.x + button_manager.buttons()[&button_start_vs_ai_id].size().w,
+            y: button_pos.v.y,
+        }};
+        let button_difficulty_id = button_manager.add_button(Button::new(
+            context,
+            difficulty_button_text(difficulty),
+            button_difficulty_pos,
+        ));
         button_pos.v.y += vstep * 2;
         let button_map_id = button_manager.add_button(Button::new(
             context,
-            &format!("[map: {}]", map_names[selected_map_index]),
+            &map_button_text(&map_names, selected_map_index),
+            button_pos,
+        ));
+        button_pos.v.y += vstep;
+        let button_teams_id = button_manager.add_button(Button::new(
+            context,
+            teams_button_text(false),
+            button_pos,
+        ));
+        button_pos.v.y += vstep;
+        let button_turn_mode_id = button_manager.add_button(Button::new(
+            context,
+            turn_mode_button_text(TurnMode::Alternate),
+            button_pos,
+        ));
+        button_pos.v.y += vstep;
+        let button_time_limits_id = button_manager.add_button(Button::new(
+            context,
+            &time_limits_button_text(0),
+            button_pos,
+        ));
+        button_pos.v.y += vstep * 2;
+        let button_save_id = button_manager.add_button(Button::new(
+            context,
+            "[save]",
+            button_pos,
+        ));
+        button_pos.v.y += vstep;
+        let button_load_id = button_manager.add_button(Button::new(
+            context,
+            "[load]",
+            button_pos,
+        ));
+        button_pos.v.y += vstep * 2;
+        let button_join_id = button_manager.add_button(Button::new(
+            context,
+            "[join network game]",
+            button_pos,
+        ));
+        button_pos.v.y += vstep;
+        let button_start_pbem_id = button_manager.add_button(Button::new(
+            context,
+            "[start play-by-email]",
+            button_pos,
+        ));
+        button_pos.v.y += vstep;
+        let button_import_turn_id = button_manager.add_button(Button::new(
+            context,
+            "[join play-by-email]",
             button_pos,
         ));
+        let (save_tx, save_rx) = channel();
         MainMenuScreen {
             button_manager: button_manager,
             button_start_hotseat_id: button_start_hotseat_id,
             button_start_vs_ai_id: button_start_vs_ai_id,
+            button_difficulty_id: button_difficulty_id,
             button_map_id: button_map_id,
+            button_teams_id: button_teams_id,
+            button_turn_mode_id: button_turn_mode_id,
+            button_time_limits_id: button_time_limits_id,
+            button_save_id: button_save_id,
+            button_load_id: button_load_id,
+            button_join_id: button_join_id,
+            button_start_pbem_id: button_start_pbem_id,
+            button_import_turn_id: button_import_turn_id,
             map_names: map_names,
             selected_map_index: selected_map_index,
+            is_two_teams: false,
+            difficulty: difficulty,
+            turn_mode: TurnMode::Alternate,
+            time_limits_index: 0,
+            save_tx: save_tx,
+            save_rx: save_rx,
+            last_save: None,
         }
     }
 
@@ -76,36 +304,189 @@ impl MainMenuScreen {
         context: &mut Context,
         button_id: ButtonId
     ) {
-        let map_name = self.map_names[self.selected_map_index].to_string();
-        let mut core_options = Options {
-            game_type: GameType::Hotseat,
-            map_name: map_name,
-            players_count: 2,
-        };
         if button_id == self.button_start_hotseat_id {
-            let tactical_screen = Box::new(
-                TacticalScreen::new(context, &core_options));
-            context.add_command(ScreenCommand::PushScreen(tactical_screen));
+            self.start_game(context, false);
         } else if button_id == self.button_start_vs_ai_id {
-            core_options.game_type = GameType::SingleVsAi;
-            let tactical_screen = Box::new(
-                TacticalScreen::new(context, &core_options));
-            context.add_command(ScreenCommand::PushScreen(tactical_screen));
+            self.start_game(context, true);
+        } else if button_id == self.button_difficulty_id {
+            self.difficulty = next_difficulty(self.difficulty);
+            let text = difficulty_button_text(self.difficulty);
+            let pos = self.button_manager.buttons()[&self.button_difficulty_id].pos();
+            let button_difficulty = Button::new(context, text, pos);
+            self.button_manager.remove_button(self.button_difficulty_id);
+            self.button_difficulty_id = self.button_manager.add_button(button_difficulty);
         } else if button_id == self.button_map_id {
             self.selected_map_index += 1;
-            if self.selected_map_index == self.map_names.len() {
+            if self.selected_map_index >= self.map_names.len() {
                 self.selected_map_index = 0;
             }
-            let text = &format!("[map: {}]", self.map_names[self.selected_map_index]);
+            let text = &map_button_text(&self.map_names, self.selected_map_index);
             let pos = self.button_manager.buttons()[&self.button_map_id].pos();
             let button_map = Button::new(context, text, pos);
             self.button_manager.remove_button(self.button_map_id);
             self.button_map_id = self.button_manager.add_button(button_map);
+        } else if button_id == self.button_teams_id {
+            self.is_two_teams = !self.is_two_teams;
+            let text = teams_button_text(self.is_two_teams);
+            let pos = self.button_manager.buttons()[&self.button_teams_id].pos();
+            let button_teams = Button::new(context, text, pos);
+            self.button_manager.remove_button(self.button_teams_id);
+            self.button_teams_id = self.button_manager.add_button(button_teams);
+        } else if button_id == self.button_turn_mode_id {
+            self.turn_mode = match self.turn_mode {
+                TurnMode::Alternate => TurnMode::Simultaneous,
+                TurnMode::Simultaneous => TurnMode::Alternate,
+            };
+            let text = turn_mode_button_text(self.turn_mode);
+            let pos = self.button_manager.buttons()[&self.button_turn_mode_id].pos();
+            let button_turn_mode = Button::new(context, text, pos);
+            self.button_manager.remove_button(self.button_turn_mode_id);
+            self.button_turn_mode_id = self.button_manager.add_button(button_turn_mode);
+        } else if button_id == self.button_time_limits_id {
+            self.time_limits_index = (self.time_limits_index + 1) % TIME_LIMITS.len();
+            let text = &time_limits_button_text(self.time_limits_index);
+            let pos = self.button_manager.buttons()[&self.button_time_limits_id].pos();
+            let button_time_limits = Button::new(context, text, pos);
+            self.button_manager.remove_button(self.button_time_limits_id);
+            self.button_time_limits_id = self.button_manager.add_button(button_time_limits);
+        } else if button_id == self.button_save_id {
+            self.save_game();
+        } else if button_id == self.button_load_id {
+            self.load_game(context);
+        } else if button_id == self.button_join_id {
+            self.join_game(context);
+        } else if button_id == self.button_start_pbem_id {
+            self.start_pbem_game(context);
+        } else if button_id == self.button_import_turn_id {
+            self.join_pbem_game(context);
         } else {
             panic!("Bad button id: {}", button_id.id);
         }
     }
 
+    /// Hotseat game if `vs_ai` is false, otherwise all players
+    /// except the first one are controlled by the AI
+    fn start_game(&self, context: &mut Context, vs_ai: bool) {
+        let core_options = match self.core_options(vs_ai) {
+            Some(core_options) => core_options,
+            None => return,
+        };
+        let tactical_screen = Box::new(
+            TacticalScreen::new(context, &core_options, self.save_tx.clone()));
+        context.add_command(ScreenCommand::PushScreen(tactical_screen));
+    }
+
+    /// The first player plays here, the others import the turn files
+    fn start_pbem_game(&self, context: &mut Context) {
+        let core_options = match self.core_options(false) {
+            Some(core_options) => core_options,
+            None => return,
+        };
+        let tactical_screen = Box::new(
+            TacticalScreen::new_pbem(context, &core_options, self.save_tx.clone()));
+        context.add_command(ScreenCommand::PushScreen(tactical_screen));
+    }
+
+    fn join_pbem_game(&self, context: &mut Context) {
+        let text = match fs::load_user_file_as_string(TURN_FILE_NAME) {
+            Ok(text) => text,
+            Err(err) => {
+                println!("Can`t read '{}' ({})", TURN_FILE_NAME, err);
+                return;
+            },
+        };
+        match TacticalScreen::join_pbem(context, &text, self.save_tx.clone()) {
+            Ok(tactical_screen) => {
+                let tactical_screen = Box::new(tactical_screen);
+                context.add_command(ScreenCommand::PushScreen(tactical_screen));
+            },
+            Err(err) => println!("Can`t join '{}' ({})", TURN_FILE_NAME, err),
+        }
+    }
+
+    fn core_options(&self, vs_ai: bool) -> Option<Options> {
+        let scenario = match self.map_names.get(self.selected_map_index) {
+            Some(name) => load_scenario(name),
+            None => None,
+        };
+        let scenario = match scenario {
+            Some(scenario) => scenario,
+            None => return None,
+        };
+        let mut players = vec![PlayerClass::Human];
+        let other_class = if vs_ai { PlayerClass::Ai } else { PlayerClass::Human };
+        for _ in 1 .. scenario.players_count {
+            players.push(other_class);
+        }
+        let teams = if self.is_two_teams {
+            (0 .. players.len()).map(|id| TeamId{id: id as i32 % 2}).collect()
+        } else {
+            Options::free_for_all_teams(players.len())
+        };
+        let (turn_time_limit, game_time_limit) = TIME_LIMITS[self.time_limits_index];
+        let options = Options {
+            teams: teams,
+            shared_vision: self.is_two_teams,
+            turn_mode: self.turn_mode,
+            turn_time_limit: turn_time_limit.map(Duration::from_secs),
+            game_time_limit: game_time_limit.map(Duration::from_secs),
+            difficulty: self.difficulty,
+            .. Options::new(players, scenario)
+        };
+        match options.check() {
+            Ok(()) => Some(options),
+            Err(err) => {
+                println!("{}", err);
+                None
+            },
+        }
+    }
+
+    fn save_game(&self) {
+        let save = match self.last_save {
+            Some(ref save) => save,
+            None => {
+                println!("No game to save");
+                return;
+            },
+        };
+        match fs::save_user_file(SAVE_FILE_NAME, save) {
+            Ok(()) => println!("Saved to '{}'", SAVE_FILE_NAME),
+            Err(err) => println!("Can`t save to '{}' ({})", SAVE_FILE_NAME, err),
+        }
+    }
+
+    fn load_game(&mut self, context: &mut Context) {
+        let text = match fs::load_user_file_as_string(SAVE_FILE_NAME) {
+            Ok(text) => text,
+            Err(err) => {
+                println!("Can`t read '{}' ({})", SAVE_FILE_NAME, err);
+                return;
+            },
+        };
+        match TacticalScreen::load(context, &text, self.save_tx.clone()) {
+            Ok(tactical_screen) => {
+                let tactical_screen = Box::new(tactical_screen);
+                context.add_command(ScreenCommand::PushScreen(tactical_screen));
+            },
+            Err(err) => println!("Can`t load '{}' ({})", SAVE_FILE_NAME, err),
+        }
+    }
+
+    fn join_game(&mut self, context: &mut Context) {
+        let addr = env::var(SERVER_ENV_VAR)
+            .unwrap_or_else(|_| format!("127.0.0.1:{}", DEFAULT_PORT));
+        let secret = env::var(SECRET_ENV_VAR).ok();
+        let secret = secret.as_ref().map(|secret| secret.as_str());
+        match TacticalScreen::connect(context, &addr, secret, self.save_tx.clone()) {
+            Ok(tactical_screen) => {
+                let tactical_screen = Box::new(tactical_screen);
+                context.add_command(ScreenCommand::PushScreen(tactical_screen));
+            },
+            Err(err) => println!("Can`t join '{}' ({})", addr, err),
+        }
+    }
+
     fn handle_event_key_press(&mut self, context: &mut Context, key: VirtualKeyCode) {
         match key {
             glutin::VirtualKeyCode::Q
@@ -120,6 +501,9 @@ impl MainMenuScreen {
 
 impl Screen for MainMenuScreen {
     fn tick(&mut self, context: &mut Context, _: Time) {
+        if let Some(save) = rx_collect(&self.save_rx).pop() {
+            self.last_save = Some(save);
+        }
         context.clear();
         context.set_basic_color([0.0, 0.0, 0.0, 1.0]);
         self.button_manager.draw(context);
@@ -5,9 +5,10 @@ use core::game_state::{State};
 use core::movement::{Pathfinder};
 use core::map::{Map};
 use core::db::{Db};
-use core::player::{PlayerId};
-use core::options::{Options, GameType};
+use core::player::{PlayerId, PlayerClass};
+use core::options::{Options};
 use core::position::{MapPos};
+use core::orders::{Plan};
 use context::{Context};
 use types::{Size2, Time, WorldPos};
 use scene::{Scene, NodeId};
@@ -49,6 +50,9 @@ pub struct PlayerInfo {
     pub scene: Scene,
     pub camera: Camera,
     pub fow_info: FowInfo,
+
+    /// Orders plotted in this simultaneous turn
+    pub plan: Plan,
 }
 
 #[derive(Clone, Debug)]
@@ -56,30 +60,43 @@ pub struct PlayerInfoManager {
     pub info: HashMap<PlayerId, PlayerInfo>,
 }
 
+fn new_player_info(db: Rc<Db>, context: &Context, state: State) -> PlayerInfo {
+    let map_size = state.map().size();
+    let mut camera = Camera::new(context.win_size());
+    camera.set_max_pos(get_max_camera_pos(map_size));
+    camera.set_pos(get_initial_camera_pos(map_size));
+    PlayerInfo {
+        game_state: state,
+        pathfinder: Pathfinder::new(db, map_size),
+        scene: Scene::new(),
+        camera: camera,
+        fow_info: FowInfo::new(map_size),
+        plan: Plan::new(),
+    }
+}
+
 impl PlayerInfoManager {
     pub fn new(db: Rc<Db>, context: &Context, options: &Options) -> PlayerInfoManager {
-        let state = State::new_partial(db.clone(), options, PlayerId{id: 0});
-        let map_size = state.map().size();
+        let mut states = Vec::new();
+        for (id, &class) in options.players.iter().enumerate() {
+            if class == PlayerClass::Human {
+                let player_id = PlayerId{id: id as i32};
+                let state = State::new_partial(db.clone(), options, player_id);
+                states.push((player_id, state));
+            }
+        }
+        PlayerInfoManager::from_states(db, context, states)
+    }
+
+    /// Used when a game is restored from a save
+    pub fn from_states(
+        db: Rc<Db>,
+        context: &Context,
+        states: Vec<(PlayerId, State)>,
+    ) -> PlayerInfoManager {
         let mut m = HashMap::new();
-        let mut camera = Camera::new(context.win_size());
-        camera.set_max_pos(get_max_camera_pos(map_size));
-        camera.set_pos(get_initial_camera_pos(map_size));
-        m.insert(PlayerId{id: 0}, PlayerInfo {
-            game_state: state,
-            pathfinder: Pathfinder::new(db.clone(), map_size),
-            scene: Scene::new(),
-            camera: camera.clone(),
-            fow_info: FowInfo::new(map_size),
-        });
-        if options.game_type == GameType::Hotseat {
-            let state2 = State::new_partial(db.clone(), options, PlayerId{id: 1});
-            m.insert(PlayerId{id: 1}, PlayerInfo {
-                game_state: state2,
-                pathfinder: Pathfinder::new(db, map_size),
-                scene: Scene::new(),
-                camera: camera,
-                fow_info: FowInfo::new(map_size),
-            });
+        for (player_id, state) in states {
+            m.insert(player_id, new_player_info(db.clone(), context, state));
         }
         PlayerInfoManager{info: m}
     }
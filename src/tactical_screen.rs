@@ -1,22 +1,31 @@
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::mpsc::{channel, Sender, Receiver};
 use std::f32::consts::{PI};
 use rand::{thread_rng, Rng};
 use std::iter::IntoIterator;
 use std::collections::{HashMap};
+use std::time::{Duration};
 use cgmath::{self, Array, Vector2, Vector3, Rad};
 use glutin::{self, VirtualKeyCode, WindowEvent, MouseButton, KeyboardInput, TouchPhase, MouseScrollDelta};
 use glutin::ElementState::{Released};
 use core;
 use core::map::{Terrain};
 use core::game_state::{State};
+use core::db::{Db};
 use core::event::{CoreEvent, Command, MoveMode, ReactionFireMode};
-use core::player::{PlayerId};
+use core::player::{PlayerId, PlayerClass};
 use core::object::{Object, ObjectClass};
 use core::options::Options as CoreOptions;
+use core::options::{TurnMode};
+use core::orders;
 use core::position::{self, MapPos, ExactPos, SlotId};
 use core::unit::{UnitId, UnitTypeId};
 use core::misc::{opt_rx_collect};
+use core::serialize::{Serialize, Writer, Reader, DeserializeError};
 use core::print_info::{print_pos_info};
+use core::influence::{InfluenceMap};
+use core::net::{Client, NetError};
+use core::pbem::{self, TurnFile, TurnFileError};
+use core::ai::{builtin_ai_builder};
 use gui::{ButtonManager, Button, ButtonId, is_tap};
 use scene::{Scene, NodeId, SceneNode};
 use event_visualizer;
@@ -37,22 +46,50 @@ use game_results_screen::{GameResultsScreen};
 use types::{Time, ScreenPos, WorldPos};
 use gen;
 use pick;
+use fs;
 use player_info::{PlayerInfoManager, PlayerInfo};
 use mesh_manager::{MeshIdManager, MeshManager};
+use core_link::{CoreLink, RemoteCore};
 
 const FOW_FADING_TIME: f32 = 0.6;
 const ZOOM_LEVEL: f32 = 0.3;
+const REPLAY_FILE_NAME: &'static str = "last_game.replay";
+
+/// Play-by-email turns are exported to and imported from this file
+pub const TURN_FILE_NAME: &'static str = "zoc_turn.txt";
+
+/// Marks saves of play-by-email games
+const PBEM_TAG: &'static str = "pbem";
+
+/// How long the AI may think in one frame
+const AI_STEP_BUDGET_MS: u64 = 10;
+
+/// Overrides the built-in unit and weapon types if it exists
+const DB_FILE_NAME: &'static str = "db.txt";
+
+fn load_custom_db() -> Option<Db> {
+    let text = match fs::load_user_file_as_string(DB_FILE_NAME) {
+        Ok(text) => text,
+        Err(_) => return None,
+    };
+    match text.parse::<Db>() {
+        Ok(db) => Some(db),
+        Err(err) => {
+            println!("Can`t load '{}' ({}), using the default db", DB_FILE_NAME, err);
+            None
+        },
+    }
+}
 
 fn score_text(state: &State) -> String {
     let target_score = state.target_score();
     let score = state.score();
-    // TODO: get rid of magic num
-    format!("P0:{}/{}, P1:{}/{}",
-        score[&PlayerId{id: 0}].n,
-        target_score.n,
-        score[&PlayerId{id: 1}].n,
-        target_score.n,
-    )
+    let mut ids: Vec<_> = score.keys().collect();
+    ids.sort_by_key(|id| id.id);
+    let parts: Vec<_> = ids.iter()
+        .map(|id| format!("P{}:{}/{}", id.id, score[id].n, target_score.n))
+        .collect();
+    parts.join(", ")
 }
 
 fn reinforcement_points_text(state: &State, player_id: PlayerId) -> String {
@@ -61,6 +98,46 @@ fn reinforcement_points_text(state: &State, player_id: PlayerId) -> String {
     format!("reinforcements: {} (+{})", rp, rp_per_turn)
 }
 
+/// The clock of the player who plays now, from `CoreEvent::TimeLeft`.
+/// It's counted down here between the events.
+#[derive(Clone, Copy, Debug)]
+struct Clock {
+    player_id: PlayerId,
+    turn: Duration,
+    game: Option<Duration>,
+}
+
+impl Clock {
+    fn pass_time(&mut self, time: Duration) {
+        let zero = Duration::from_secs(0);
+        self.turn = self.turn.checked_sub(time).unwrap_or(zero);
+        if let Some(ref mut game) = self.game {
+            *game = game.checked_sub(time).unwrap_or(zero);
+        }
+    }
+}
+
+/// Minutes and seconds, rounded up: 0:00 is shown only when the time is out
+fn minutes_text(time: Duration) -> String {
+    let mut secs = time.as_secs();
+    if time.subsec_nanos() > 0 {
+        secs += 1;
+    }
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+fn clock_text(clock: &Clock) -> String {
+    let mut text = format!("P{} time: {}", clock.player_id.id, minutes_text(clock.turn));
+    if let Some(game) = clock.game {
+        text += &format!(" (game: {})", minutes_text(game));
+    }
+    text
+}
+
+fn time_to_duration(time: Time) -> Duration {
+    Duration::from_millis((time.n * 1000.0) as u64)
+}
+
 fn building_mesh_id(mesh_ids: &MeshIdManager, object: &Object) -> MeshId {
     let slot_id = object.pos.slot_id;
     match slot_id {
@@ -89,8 +166,13 @@ pub struct Gui {
     button_zoom_in_id: ButtonId,
     button_zoom_out_id: ButtonId,
     label_unit_info_id: Option<ButtonId>,
+    label_thinking_id: Option<ButtonId>,
+    thinking_player_id: Option<PlayerId>,
     label_score_id: ButtonId,
     label_reinforcement_points_id: ButtonId,
+    label_clock_id: Option<ButtonId>,
+    clock_text: String,
+    clock: Option<Clock>,
 }
 
 impl Gui {
@@ -150,8 +232,13 @@ impl Gui {
             button_zoom_in_id: button_zoom_in_id,
             button_zoom_out_id: button_zoom_out_id,
             label_unit_info_id: None,
+            label_thinking_id: None,
+            thinking_player_id: None,
             label_score_id: label_score_id,
             label_reinforcement_points_id: label_reinforcement_points_id,
+            label_clock_id: None,
+            clock_text: String::new(),
+            clock: None,
         }
     }
 }
@@ -266,7 +353,7 @@ pub struct TacticalScreen {
     map_text_manager: MapTextManager,
     gui: Gui,
     player_info: PlayerInfoManager,
-    core: core::Core,
+    core: CoreLink,
     event: Option<CoreEvent>,
     event_visualizer: Option<Box<event_visualizer::EventVisualizer>>,
     mesh_ids: MeshIdManager,
@@ -276,23 +363,165 @@ pub struct TacticalScreen {
     selection_manager: SelectionManager,
     context_menu_popup_rx: Option<Receiver<context_menu_popup::Command>>,
     reinforcements_popup_rx: Option<Receiver<(UnitTypeId, ExactPos)>>,
+    save_tx: Sender<String>,
+
+    /// Play-by-email games only: the index of the first command
+    /// of the local player's current turn
+    pbem_turn_start: Option<usize>,
 }
 
 impl TacticalScreen {
-    pub fn new(context: &mut Context, core_options: &CoreOptions) -> TacticalScreen {
-        let core = core::Core::new(core_options);
-        let mut player_info = PlayerInfoManager::new(
+    pub fn new(
+        context: &mut Context,
+        core_options: &CoreOptions,
+        save_tx: Sender<String>,
+    ) -> TacticalScreen {
+        let core = match load_custom_db() {
+            Some(db) => core::Core::new_with_db(core_options, db),
+            None => core::Core::new(core_options),
+        };
+        let player_info = PlayerInfoManager::new(
             core.db().clone(), context, core_options);
+        TacticalScreen::from_core(context, CoreLink::Local(core), player_info, save_tx)
+    }
+
+    /// Play-by-email game, the first player plays at this screen
+    pub fn new_pbem(
+        context: &mut Context,
+        core_options: &CoreOptions,
+        save_tx: Sender<String>,
+    ) -> TacticalScreen {
+        let mut core = core::Core::new(core_options);
+        let player_id = core.player_id();
+        let states = vec![(player_id, core.snapshot_for(player_id))];
+        let core = CoreLink::Local(core);
+        let mut screen = TacticalScreen::from_snapshots(context, core, states, save_tx);
+        screen.pbem_turn_start = Some(0);
+        screen
+    }
+
+    /// Starts a play-by-email game from its first turn file,
+    /// the next human player after the file's author plays at this screen
+    pub fn join_pbem(
+        context: &mut Context,
+        text: &str,
+        save_tx: Sender<String>,
+    ) -> Result<TacticalScreen, TurnFileError> {
+        let turn = TurnFile::from_text(text, &builtin_ai_builder)?;
+        let mut core = pbem::new_game(&turn)?;
+        let players = core.replay().options.players.clone();
+        let mut player_id = turn.player_id;
+        loop {
+            player_id = core.next_player_id(player_id);
+            if player_id == turn.player_id {
+                return Err(TurnFileError::NotPlayersTurn(player_id));
+            }
+            if players[player_id.id as usize] == PlayerClass::Human {
+                break;
+            }
+        }
+        let states = vec![(player_id, core.snapshot_for(player_id))];
+        let core = CoreLink::Local(core);
+        let mut screen = TacticalScreen::from_snapshots(context, core, states, save_tx);
+        screen.import_turn(&turn)?;
+        Ok(screen)
+    }
+
+    /// Joins a game hosted by `zoc_server`,
+    /// see `Client::connect` about `secret`
+    pub fn connect(
+        context: &mut Context,
+        addr: &str,
+        secret: Option<&str>,
+        save_tx: Sender<String>,
+    ) -> Result<TacticalScreen, NetError> {
+        let client = Client::connect(addr, secret)?;
+        println!("Secret of this seat: {}", client.secret());
+        let states = vec![(client.player_id(), client.state().clone())];
+        let core = CoreLink::Remote(RemoteCore::new(client));
+        Ok(TacticalScreen::from_snapshots(context, core, states, save_tx))
+    }
+
+    /// Restores a game written by `TacticalScreen::save`
+    /// or by `Core::save` alone.
+    ///
+    /// Human players without saved states start from snapshots.
+    pub fn load(
+        context: &mut Context,
+        text: &str,
+        save_tx: Sender<String>,
+    ) -> Result<TacticalScreen, DeserializeError> {
+        let mut r = Reader::new(text);
+        let mut core = core::Core::load(&mut r, &builtin_ai_builder)?;
+        let states_count: u64 = if r.is_finished() { 0 } else { r.read()? };
+        let mut states: Vec<(PlayerId, State)> = Vec::new();
+        for _ in 0 .. states_count {
+            let player_id = r.read()?;
+            let state = State::load(&mut r, core.db().clone())?;
+            states.push((player_id, state));
+        }
+        // Other players of a play-by-email game are somewhere else
+        let pbem_turn_start = if r.is_finished() {
+            None
+        } else {
+            r.expect_tag(PBEM_TAG)?;
+            let turn_start: u64 = r.read()?;
+            Some(turn_start as usize)
+        };
+        if pbem_turn_start.is_none() {
+            let players = core.replay().options.players.clone();
+            for (id, &class) in players.iter().enumerate() {
+                let player_id = PlayerId{id: id as i32};
+                if class == PlayerClass::Human && states.iter().all(|&(id, _)| id != player_id) {
+                    states.push((player_id, core.snapshot_for(player_id)));
+                }
+            }
+        }
+        let plans: Vec<_> = states.iter()
+            .map(|&(player_id, _)| (player_id, core.plan(player_id)))
+            .collect();
+        let core = CoreLink::Local(core);
+        let mut screen = TacticalScreen::from_snapshots(context, core, states, save_tx);
+        screen.pbem_turn_start = pbem_turn_start;
+        for (player_id, plan) in plans {
+            screen.player_info.get_mut(player_id).plan = plan;
+        }
+        Ok(screen)
+    }
+
+    /// Starts from what the players know now instead of from `Options`
+    fn from_snapshots(
+        context: &mut Context,
+        core: CoreLink,
+        states: Vec<(PlayerId, State)>,
+        save_tx: Sender<String>,
+    ) -> TacticalScreen {
+        let player_info = PlayerInfoManager::from_states(core.db().clone(), context, states);
+        TacticalScreen::from_core(context, core, player_info, save_tx)
+    }
+
+    fn from_core(
+        context: &mut Context,
+        core: CoreLink,
+        mut player_info: PlayerInfoManager,
+        save_tx: Sender<String>,
+    ) -> TacticalScreen {
+        // The game may start with a turn of somebody who isn't at this screen
+        let watcher_id = if player_info.info.contains_key(&core.player_id()) {
+            core.player_id()
+        } else {
+            *player_info.info.keys().min().unwrap()
+        };
         let mut meshes = MeshManager::new();
         let mesh_ids = MeshIdManager::new(
             context,
             &mut meshes,
-            &player_info.get(core.player_id()).game_state,
+            &player_info.get(watcher_id).game_state,
         );
         let unit_type_visual_info
             = get_unit_type_visual_info(core.db(), context, &mut meshes);
         let map_text_manager = MapTextManager::new();
-        let gui = Gui::new(context, &player_info.get(core.player_id()).game_state);
+        let gui = Gui::new(context, &player_info.get(watcher_id).game_state);
         let selection_manager = SelectionManager::new(mesh_ids.selection_marker_mesh_id);
         for player_info in player_info.info.values_mut() {
             player_info.scene = make_scene(&player_info.game_state, &mesh_ids);
@@ -311,16 +540,51 @@ impl TacticalScreen {
             map_text_manager: map_text_manager,
             context_menu_popup_rx: None,
             reinforcements_popup_rx: None,
+            save_tx: save_tx,
+            pbem_turn_start: None,
         };
         screen.regenerate_fow();
         screen
     }
 
+    /// Core's save followed by the states that humans see on their screens.
+    ///
+    /// Can't be done in the middle of event's visualization because
+    /// that event is already taken from the core but isn't applied yet,
+    /// and during AI turns because AIs don't save their unfinished thoughts.
+    /// Network games are saved only by the server.
+    fn save(&self) -> Option<String> {
+        let core = match self.core.local() {
+            Some(core) => core,
+            None => return None,
+        };
+        if self.event.is_some() || core.is_ai_turn() {
+            return None;
+        }
+        let mut w = Writer::new();
+        core.save(&mut w);
+        let mut player_ids: Vec<_> = self.player_info.info.keys().cloned().collect();
+        player_ids.sort();
+        (player_ids.len() as u64).serialize(&mut w);
+        for player_id in player_ids {
+            w.new_line();
+            player_id.serialize(&mut w);
+            self.player_info.get(player_id).game_state.save(&mut w);
+        }
+        w.new_line();
+        if let Some(turn_start) = self.pbem_turn_start {
+            w.write_tag(PBEM_TAG);
+            (turn_start as u64).serialize(&mut w);
+            w.new_line();
+        }
+        Some(w.into_string())
+    }
+
     fn show_reinforcements_menu(&mut self, context: &mut Context, pos: MapPos) {
         let options = reinforcements_popup::get_options(
             self.core.db(),
             self.current_state(),
-            self.core.player_id(),
+            self.watcher_id(),
             pos,
         );
         if options == reinforcements_popup::Options::new() {
@@ -338,18 +602,79 @@ impl TacticalScreen {
     }
 
     fn end_turn(&mut self, context: &mut Context) {
+        let player_id = self.watcher_id();
+        self.core.do_command(Command::EndTurn);
+        self.on_turn_end(context, player_id);
+    }
+
+    /// The player has ended the turn or the clock has done it for them
+    fn on_turn_end(&mut self, context: &mut Context, player_id: PlayerId) {
         if self.player_info.info.len() > 1 {
-            let next_id = self.core.next_player_id(self.core.player_id());
+            let next_id = self.next_human_id(player_id);
             let screen = Box::new(EndTurnScreen::new(context, next_id));
             context.add_command(ScreenCommand::PushScreen(screen));
         }
         self.deselect_unit(context);
-        self.core.do_command(Command::EndTurn);
+        // The core carries out the plotted orders after the last player
+        self.player_info.get_mut(player_id).plan.clear();
         self.regenerate_fow();
+        if self.pbem_turn_start.is_some() {
+            self.export_turn();
+        }
+    }
+
+    /// Writes the turn that the local player has just ended
+    /// to a file that they send to the other players
+    fn export_turn(&mut self) {
+        let core = self.core.local().unwrap();
+        let turn_start = self.pbem_turn_start.unwrap();
+        let player_id = self.watcher_id();
+        let text = pbem::export_turn(core, player_id, turn_start);
+        match fs::save_user_file(TURN_FILE_NAME, &text) {
+            Ok(()) => println!("Send '{}' to the next player", TURN_FILE_NAME),
+            Err(err) => println!("Can`t save turn to '{}' ({})", TURN_FILE_NAME, err),
+        }
+        self.pbem_turn_start = Some(pbem::commands_count(core));
+    }
+
+    /// Plays somebody else's turn from a file, the local player's
+    /// turn starts after it
+    fn import_turn(&mut self, turn: &TurnFile) -> Result<(), TurnFileError> {
+        let watcher_id = self.watcher_id();
+        let core = self.core.local_mut().unwrap();
+        pbem::import_turn(core, turn)?;
+        // Nobody at this screen sees the events of other humans
+        let players = core.replay().options.players.clone();
+        for (id, &class) in players.iter().enumerate() {
+            let player_id = PlayerId{id: id as i32};
+            if class == PlayerClass::Human && player_id != watcher_id {
+                while core.get_event_for(player_id).is_some() {}
+            }
+        }
+        self.pbem_turn_start = Some(pbem::commands_count(core));
+        Ok(())
+    }
+
+    fn import_turn_file(&mut self) {
+        if self.pbem_turn_start.is_none() || !self.is_waiting() {
+            return;
+        }
+        let text = match fs::load_user_file_as_string(TURN_FILE_NAME) {
+            Ok(text) => text,
+            Err(err) => {
+                println!("Can`t read '{}' ({})", TURN_FILE_NAME, err);
+                return;
+            },
+        };
+        let result = TurnFile::from_text(&text, &builtin_ai_builder)
+            .and_then(|turn| self.import_turn(&turn));
+        if let Err(err) = result {
+            println!("Can`t import '{}' ({})", TURN_FILE_NAME, err);
+        }
     }
 
     fn regenerate_fow(&mut self) {
-        let player_info = self.player_info.get_mut(self.core.player_id());
+        let player_info = self.player_info.get_mut(self.watcher_id());
         let fow = &mut player_info.fow_info;
         let state = &player_info.game_state;
         for pos in state.map().get_iter() {
@@ -383,7 +708,7 @@ impl TacticalScreen {
 
     fn update_fow(&mut self, dtime: Time) {
         let max_alpha = 0.4;
-        let player_info = self.player_info.get_mut(self.core.player_id());
+        let player_info = self.player_info.get_mut(self.watcher_id());
         let scene = &mut player_info.scene;
         let fow = &mut player_info.fow_info;
         for (&node_id, time) in &mut fow.forthcoming_node_ids {
@@ -410,7 +735,7 @@ impl TacticalScreen {
     }
 
     fn bobble_helicopters(&mut self, context: &Context, dtime: Time) {
-        let player_info = self.player_info.get_mut(self.core.player_id());
+        let player_info = self.player_info.get_mut(self.watcher_id());
         let state = &player_info.game_state;
         let scene = &mut player_info.scene;
         for (_, unit) in state.units() {
@@ -425,7 +750,7 @@ impl TacticalScreen {
     }
 
     fn hide_selected_unit_meshes(&mut self, context: &mut Context) {
-        let scene = &mut self.player_info.get_mut(self.core.player_id()).scene;
+        let scene = &mut self.player_info.get_mut(self.watcher_id()).scene;
         self.selection_manager.deselect(scene);
         self.meshes.set(self.mesh_ids.walkable_mesh_id, gen::empty_mesh(context));
         self.meshes.set(self.mesh_ids.targets_mesh_id, gen::empty_mesh(context));
@@ -439,16 +764,40 @@ impl TacticalScreen {
         self.hide_selected_unit_meshes(context);
     }
 
+    /// Nobody at this screen can give commands now
+    fn is_waiting(&self) -> bool {
+        self.core.is_waiting() || !self.player_info.info.contains_key(&self.core.player_id())
+    }
+
+    /// The human who sees the screen: the current player
+    /// or, during AI turns, the next human to play
+    fn watcher_id(&self) -> PlayerId {
+        let player_id = self.core.player_id();
+        if self.player_info.info.contains_key(&player_id) {
+            player_id
+        } else {
+            self.next_human_id(player_id)
+        }
+    }
+
+    fn next_human_id(&self, player_id: PlayerId) -> PlayerId {
+        let mut next_id = self.core.next_player_id(player_id);
+        while !self.player_info.info.contains_key(&next_id) {
+            next_id = self.core.next_player_id(next_id);
+        }
+        next_id
+    }
+
     fn current_state(&self) -> &State {
-        &self.player_info.get(self.core.player_id()).game_state
+        &self.player_info.get(self.watcher_id()).game_state
     }
 
     fn current_player_info(&self) -> &PlayerInfo {
-        self.player_info.get(self.core.player_id())
+        self.player_info.get(self.watcher_id())
     }
 
     fn current_player_info_mut(&mut self) -> &mut PlayerInfo {
-        self.player_info.get_mut(self.core.player_id())
+        self.player_info.get_mut(self.watcher_id())
     }
 
     // TODO: show commands preview
@@ -487,11 +836,16 @@ impl TacticalScreen {
             self.deselect_unit(context);
         }
         self.selected_unit_id = Some(unit_id);
-        let player_info = self.player_info.get_mut(self.core.player_id());
+        let player_info = self.player_info.get_mut(self.watcher_id());
+        let orders_count = player_info.plan.orders(unit_id).len();
         let state = &player_info.game_state;
+        // In simultaneous turns the next order starts where
+        // the unit will be after the plotted ones
+        let unit = orders::planned_unit(
+            self.core.db(), state, state.unit(unit_id), player_info.plan.orders(unit_id));
         let pf = &mut player_info.pathfinder;
-        pf.fill_map(state, state.unit(unit_id));
-        let move_points = state.unit(unit_id).move_points.unwrap();
+        pf.fill_map(state, &unit);
+        let move_points = unit.move_points.unwrap();
         let new_walkable_mesh = gen::build_walkable_mesh(
             context, pf, state, move_points);
         self.meshes.set(self.mesh_ids.walkable_mesh_id, new_walkable_mesh);
@@ -502,8 +856,7 @@ impl TacticalScreen {
             state, scene, unit_id);
         {
             let pos = ScreenPos{v: Vector2{x: 10, y: context.win_size().h - 10}};
-            let text = {
-                let unit = state.unit(unit_id);
+            let mut text = {
                 let unit_type = self.core.db().unit_type(unit.type_id);
                 // TODO: core.rs: print_unit_info
                 format!("MP={}/{}, AP={}/{}, RAP={}/{}, C={}, M={}",
@@ -518,6 +871,9 @@ impl TacticalScreen {
                 )
                 // TODO: print info about unit type and weapon
             };
+            if orders_count != 0 {
+                text += &format!(", orders={}", orders_count);
+            }
             let mut unit_info_button = Button::new_small(context, &text, pos);
             let mut pos = unit_info_button.pos();
             pos.v.y -= unit_info_button.size().h;
@@ -532,16 +888,52 @@ impl TacticalScreen {
 
     fn move_unit(&mut self, pos: ExactPos, move_mode: MoveMode) {
         let unit_id = self.selected_unit_id.unwrap();
-        let player_info = self.player_info.get_mut(self.core.player_id());
+        let player_info = self.player_info.get_mut(self.watcher_id());
         // TODO: duplicated get_path =\
         let path = player_info.pathfinder.get_path(pos).unwrap();
-        self.core.do_command(Command::Move {
+        self.do_or_plot(Command::Move {
             unit_id: unit_id,
             path: path,
             mode: move_mode,
         });
     }
 
+    /// In simultaneous turns the command becomes the unit's next order
+    fn do_or_plot(&mut self, command: Command) {
+        if self.current_state().turn_mode() != TurnMode::Simultaneous {
+            self.core.do_command(command);
+            return;
+        }
+        let (unit_id, order) = orders::command_to_order(&command).unwrap();
+        let orders = {
+            let plan = &mut self.current_player_info_mut().plan;
+            let mut orders = plan.orders(unit_id).to_vec();
+            orders.push(order);
+            plan.set_orders(unit_id, orders.clone());
+            orders
+        };
+        self.core.do_command(Command::SetOrders {
+            unit_id: unit_id,
+            orders: orders,
+        });
+    }
+
+    fn clear_orders(&mut self, context: &mut Context) {
+        let unit_id = match self.selected_unit_id {
+            Some(unit_id) => unit_id,
+            None => return,
+        };
+        if self.is_waiting() || self.current_player_info().plan.orders(unit_id).is_empty() {
+            return;
+        }
+        self.current_player_info_mut().plan.set_orders(unit_id, Vec::new());
+        self.core.do_command(Command::SetOrders {
+            unit_id: unit_id,
+            orders: Vec::new(),
+        });
+        self.select_unit(context, unit_id);
+    }
+
     fn handle_camera_move(&mut self, context: &Context, pos: ScreenPos) {
         let diff = pos.v - context.mouse().pos.v;
         let camera_move_speed = geom::HEX_EX_RADIUS * 12.0;
@@ -605,11 +997,50 @@ impl TacticalScreen {
         }
     }
 
+    /// Debug overlay: the AI's influence map built from the current player's view.
+    /// 't' is for ground threat, 'a' - air threat, 's' - support, 'v' - sector value.
+    fn show_influence(&mut self) {
+        let state = &self.player_info.get(self.watcher_id()).game_state;
+        let mut influence = InfluenceMap::new(state.map().size());
+        influence.update(self.core.db(), state, self.watcher_id(), &[]);
+        for pos in state.map().get_iter() {
+            let tile = influence.tile(pos);
+            if tile.threat == 0 && tile.air_threat == 0 && tile.sector_value == 0 {
+                continue;
+            }
+            let text = format!(
+                "t{} a{} s{} v{}",
+                tile.threat,
+                tile.air_threat,
+                tile.support,
+                tile.sector_value,
+            );
+            self.map_text_manager.add_text(pos, &text);
+        }
+    }
+
+    /// Commands log that can be attached to bug reports
+    fn save_replay(&self) {
+        let core = match self.core.local() {
+            Some(core) => core,
+            None => return,
+        };
+        let mut w = Writer::new();
+        core.replay().save(&mut w);
+        if let Err(err) = fs::save_user_file(REPLAY_FILE_NAME, &w.into_string()) {
+            println!("Can`t save replay to '{}' ({})", REPLAY_FILE_NAME, err);
+        }
+    }
+
     fn handle_event_key_press(&mut self, context: &mut Context, key: VirtualKeyCode) {
         let camera_move_speed_on_keypress = geom::HEX_EX_RADIUS;
         let s = camera_move_speed_on_keypress;
         match key {
             VirtualKeyCode::Q | VirtualKeyCode::Escape => {
+                if let Some(save) = self.save() {
+                    self.save_tx.send(save).unwrap();
+                }
+                self.save_replay();
                 context.add_command(ScreenCommand::PopScreen);
             },
             VirtualKeyCode::W | VirtualKeyCode::Up => {
@@ -627,6 +1058,15 @@ impl TacticalScreen {
             VirtualKeyCode::I => {
                 self.print_info(context);
             },
+            VirtualKeyCode::T => {
+                self.show_influence();
+            },
+            VirtualKeyCode::M => {
+                self.import_turn_file();
+            },
+            VirtualKeyCode::C => {
+                self.clear_orders(context);
+            },
             VirtualKeyCode::Subtract | VirtualKeyCode::Key1 => {
                 self.current_player_info_mut().camera.change_zoom(1.3);
             },
@@ -638,7 +1078,7 @@ impl TacticalScreen {
     }
 
     fn handle_event_lmb_release(&mut self, context: &mut Context) {
-        if self.event_visualizer.is_some() {
+        if self.event_visualizer.is_some() || self.is_waiting() {
             return;
         }
         if !is_tap(context) {
@@ -660,13 +1100,13 @@ impl TacticalScreen {
         } else if button_id == self.gui.button_prev_unit_id {
             if let Some(id) = self.selected_unit_id {
                 let prev_id = position::find_prev_player_unit_id(
-                    self.current_state(), self.core.player_id(), id);
+                    self.current_state(), self.watcher_id(), id);
                 self.select_unit(context, prev_id);
             }
         } else if button_id == self.gui.button_next_unit_id {
             if let Some(id) = self.selected_unit_id {
                 let next_id = position::find_next_player_unit_id(
-                    self.current_state(), self.core.player_id(), id);
+                    self.current_state(), self.watcher_id(), id);
                 self.select_unit(context, next_id);
             }
         } else if button_id == self.gui.button_zoom_in_id {
@@ -693,7 +1133,7 @@ impl TacticalScreen {
     }
 
     fn scene(&self) -> &Scene {
-        &self.player_info.get(self.core.player_id()).scene
+        &self.player_info.get(self.watcher_id()).scene
     }
 
     fn draw_scene_node(
@@ -732,8 +1172,9 @@ impl TacticalScreen {
 
     fn draw_scene(&mut self, context: &mut Context, dtime: Time) {
         self.draw_scene_nodes(context);
+        let watcher_id = self.watcher_id();
         if let Some(ref mut event_visualizer) = self.event_visualizer {
-            let player_info = self.player_info.get_mut(self.core.player_id());
+            let player_info = self.player_info.get_mut(watcher_id);
             event_visualizer.draw(&mut player_info.scene, dtime);
         }
     }
@@ -741,7 +1182,7 @@ impl TacticalScreen {
     fn draw(&mut self, context: &mut Context, dtime: Time) {
         context.clear();
         self.draw_scene(context, dtime);
-        let player_info = self.player_info.get(self.core.player_id());
+        let player_info = self.player_info.get(self.watcher_id());
         self.map_text_manager.draw(context, &player_info.camera, dtime);
         context.set_basic_color([0.0, 0.0, 0.0, 1.0]);
         self.gui.button_manager.draw(context);
@@ -757,7 +1198,7 @@ impl TacticalScreen {
         &mut self,
         event: &CoreEvent,
     ) -> Box<event_visualizer::EventVisualizer> {
-        let current_player_id = self.core.player_id();
+        let current_player_id = self.watcher_id();
         let player_info = self.player_info.get_mut(current_player_id);
         let scene = &mut player_info.scene;
         let state = &player_info.game_state;
@@ -776,6 +1217,10 @@ impl TacticalScreen {
             CoreEvent::EndTurn{..} => {
                 event_visualizer::EventEndTurnVisualizer::new()
             },
+            CoreEvent::TimeLeft{..} => {
+                // Only the clock's label changes
+                event_visualizer::EventEndTurnVisualizer::new()
+            },
             CoreEvent::CreateUnit{ref unit_info} => {
                 let mesh_id = self.unit_type_visual_info
                     .get(unit_info.type_id).mesh_id;
@@ -938,7 +1383,7 @@ impl TacticalScreen {
             Some(CoreEvent::AttackUnit{ref attack_info}) => attack_info,
             _ => return,
         };
-        let player_info = self.player_info.get(self.core.player_id());
+        let player_info = self.player_info.get(self.watcher_id());
         let state = &player_info.game_state;
         let selected_unit_id = match self.selected_unit_id {
             Some(id) => id,
@@ -954,11 +1399,13 @@ impl TacticalScreen {
 
     fn check_game_end(&mut self, context: &mut Context) {
         let state = self.current_state();
-        for score in state.score().values() {
+        for &player_id in state.score().keys() {
+            let score = state.team_score(state.team_id(player_id));
             if score.n >= state.target_score().n {
                 context.add_command(ScreenCommand::PopScreen);
                 let screen = Box::new(GameResultsScreen::new(context, self.current_state()));
                 context.add_command(ScreenCommand::PushScreen(screen));
+                return;
             }
         }
     }
@@ -973,14 +1420,14 @@ impl TacticalScreen {
     fn update_reinforcement_points_label(&mut self, context: &mut Context) {
         let id = self.gui.label_reinforcement_points_id;
         let pos = self.gui.button_manager.buttons()[&id].pos();
-        let text = reinforcement_points_text(self.current_state(), self.core.player_id());
+        let text = reinforcement_points_text(self.current_state(), self.watcher_id());
         let label = Button::new_small(context, &text, pos);
         self.gui.button_manager.remove_button(id);
         self.gui.label_reinforcement_points_id = self.gui.button_manager.add_button(label);
     }
 
     fn switch_wireframe(&mut self) {
-        let player_info = self.player_info.get_mut(self.core.player_id());
+        let player_info = self.player_info.get_mut(self.watcher_id());
         let scene = &mut player_info.scene;
         let state = &mut player_info.game_state;
         'object_loop: for (&object_id, object) in state.objects() {
@@ -1012,7 +1459,7 @@ impl TacticalScreen {
     fn end_event_visualization(&mut self, context: &mut Context) {
         self.attacker_died_from_reaction_fire();
         {
-            let player_info = self.player_info.get_mut(self.core.player_id());
+            let player_info = self.player_info.get_mut(self.watcher_id());
             let scene = &mut player_info.scene;
             let state = &mut player_info.game_state;
             self.event_visualizer.as_mut().unwrap().end(scene, state);
@@ -1023,28 +1470,113 @@ impl TacticalScreen {
             self.gui.button_manager.remove_button(label_id);
         }
         self.update_reinforcement_points_label(context);
-        if let Some(CoreEvent::VictoryPoint{..}) = self.event {
-            self.update_score_labels(context);
-            self.check_game_end(context);
+        match self.event {
+            Some(CoreEvent::VictoryPoint{..}) => {
+                self.update_score_labels(context);
+                self.check_game_end(context);
+            },
+            Some(CoreEvent::EndTurn{..}) => self.gui.clock = None,
+            Some(CoreEvent::TimeLeft{player_id, turn, game}) => {
+                self.gui.clock = Some(Clock {
+                    player_id: player_id,
+                    turn: turn,
+                    game: game,
+                });
+            },
+            _ => {},
         }
         self.regenerate_fow();
         self.event_visualizer = None;
         self.event = None;
-        if let Some(event) = self.core.get_event() {
+        if let Some(event) = self.core.get_event_for(self.watcher_id()) {
             self.start_event_visualization(context, event);
         } else if let Some(unit_id) = self.selected_unit_id {
             self.select_unit(context, unit_id);
         }
     }
 
-    fn logic(&mut self, context: &mut Context) {
+    /// Shows who plays while the watcher waits
+    fn update_thinking_label(&mut self, context: &mut Context) {
+        let thinking_player_id = if self.is_waiting() {
+            Some(self.core.player_id())
+        } else {
+            None
+        };
+        if thinking_player_id == self.gui.thinking_player_id {
+            return;
+        }
+        self.gui.thinking_player_id = thinking_player_id;
+        if let Some(label_id) = self.gui.label_thinking_id.take() {
+            self.gui.button_manager.remove_button(label_id);
+        }
+        if let Some(player_id) = thinking_player_id {
+            let text = format!("[Player {} is thinking...]", player_id.id);
+            let pos = ScreenPos{v: Vector2{x: 10, y: context.win_size().h - 10}};
+            let mut label = Button::new_small(context, &text, pos);
+            let mut pos = label.pos();
+            pos.v.y -= label.size().h;
+            label.set_pos(pos);
+            self.gui.label_thinking_id = Some(self.gui.button_manager.add_button(label));
+        }
+    }
+
+    fn update_clock_label(&mut self, context: &mut Context) {
+        let text = match self.gui.clock {
+            Some(ref clock) => clock_text(clock),
+            None => String::new(),
+        };
+        if text == self.gui.clock_text {
+            return;
+        }
+        if let Some(label_id) = self.gui.label_clock_id.take() {
+            self.gui.button_manager.remove_button(label_id);
+        }
+        if !text.is_empty() {
+            // Under the reinforcement points
+            let y = {
+                let id = self.gui.label_reinforcement_points_id;
+                let rp_label = &self.gui.button_manager.buttons()[&id];
+                rp_label.pos().v.y + rp_label.size().h
+            };
+            let pos = ScreenPos{v: Vector2{x: context.win_size().w - 10, y: y}};
+            let mut label = Button::new_small(context, &text, pos);
+            let mut pos = label.pos();
+            pos.v.x -= label.size().w;
+            label.set_pos(pos);
+            self.gui.label_clock_id = Some(self.gui.button_manager.add_button(label));
+        }
+        self.gui.clock_text = text;
+    }
+
+    /// AI thinks a bit every frame and only when all its
+    /// events are shown, so the human watches its turn as it goes.
+    ///
+    /// The clock of a timed turn runs only while the player is
+    /// at the screen: not during the hotseat's `EndTurnScreen`.
+    fn logic(&mut self, context: &mut Context, dtime: Time) {
+        let player_id = self.core.player_id();
+        let is_players_turn = !self.is_waiting();
+        if is_players_turn {
+            self.core.pass_time(time_to_duration(dtime));
+        }
+        if let Some(ref mut clock) = self.gui.clock {
+            clock.pass_time(time_to_duration(dtime));
+        }
         if self.event_visualizer.is_none() {
-            if let Some(event) = self.core.get_event() {
+            if let Some(event) = self.core.get_event_for(self.watcher_id()) {
                 self.start_event_visualization(context, event);
+            } else {
+                self.core.do_ai_step(Duration::from_millis(AI_STEP_BUDGET_MS));
             }
         } else if self.is_event_visualization_finished() {
             self.end_event_visualization(context);
         }
+        if is_players_turn && self.core.player_id() != player_id {
+            // The clock has ended the turn
+            self.on_turn_end(context, player_id);
+        }
+        self.update_clock_label(context);
+        self.update_thinking_label(context);
     }
 
     fn handle_context_menu_popup_command(
@@ -1068,7 +1600,7 @@ impl TacticalScreen {
             },
             context_menu_popup::Command::Attack{id} => {
                 let selected_unit_id = self.selected_unit_id.unwrap();
-                self.core.do_command(Command::AttackUnit {
+                self.do_or_plot(Command::AttackUnit {
                     attacker_id: selected_unit_id,
                     defender_id: id,
                 });
@@ -1120,7 +1652,7 @@ impl TacticalScreen {
             },
             context_menu_popup::Command::Smoke{pos} => {
                 let selected_unit_id = self.selected_unit_id.unwrap();
-                self.core.do_command(Command::Smoke {
+                self.do_or_plot(Command::Smoke {
                     unit_id: selected_unit_id,
                     pos: pos,
                 });
@@ -1129,6 +1661,12 @@ impl TacticalScreen {
                 self.show_reinforcements_menu(context, pos);
             },
         }
+        // Shows what the unit has left after its new order
+        if self.current_state().turn_mode() == TurnMode::Simultaneous {
+            if let Some(unit_id) = self.selected_unit_id {
+                self.select_unit(context, unit_id);
+            }
+        }
     }
 
     fn handle_reinforce_command(&mut self, type_id: UnitTypeId, pos: ExactPos) {
@@ -1150,7 +1688,7 @@ impl TacticalScreen {
 
 impl Screen for TacticalScreen {
     fn tick(&mut self, context: &mut Context, dtime: Time) {
-        self.logic(context);
+        self.logic(context, dtime);
         self.draw(context, dtime);
         self.bobble_helicopters(context, dtime);
         self.update_fow(dtime);